@@ -0,0 +1,126 @@
+#!/usr/bin/env python3
+# Writes the fonts used by the tests of the fallback chain: each one
+# covers whole Unicode blocks of some scripts, all drawn with the same box
+# glyph.  They only tell which characters a font has, like the real fonts
+# of these scripts, without their size.
+#
+#   python3 make_test_fonts.py    (in this directory)
+
+import struct
+
+UNITS_PER_EM = 1000
+ASCENT = 800
+DESCENT = 200
+
+FONTS = [
+	("Test Fallback RTL", "TestFallbackRTL.ttf", 600, [
+		(0x0591, 0x05FF),    # Hebrew
+		(0x0600, 0x06FF),    # Arabic
+		(0x0750, 0x077F),    # Arabic Supplement
+		(0xFB1D, 0xFB4F),    # Hebrew presentation forms
+		(0xFB50, 0xFDFF),    # Arabic Presentation Forms-A
+		(0xFE70, 0xFEFC),    # Arabic Presentation Forms-B
+	]),
+	("Test Fallback Indic", "TestFallbackIndic.ttf", 600, [
+		(0x0900, 0x097F),    # Devanagari
+		(0x0E00, 0x0E7F),    # Thai
+	]),
+	("Test Fallback CJK", "TestFallbackCJK.ttf", 1000, [
+		(0x1100, 0x11FF),    # Hangul Jamo
+		(0x3000, 0x30FF),    # CJK punctuation, Hiragana, Katakana
+		(0x4E00, 0x9FFF),    # CJK Unified Ideographs
+		(0xAC00, 0xD7A3),    # Hangul syllables
+		(0xFF00, 0xFFEF),    # Halfwidth and fullwidth forms
+	]),
+	("Test Fallback Symbols", "TestFallbackSymbols.ttf", 1000, [
+		(0x2600, 0x27BF),    # Miscellaneous Symbols, Dingbats
+		(0x1F300, 0x1F64F),  # Pictographs, emoticons, skin tones
+		(0x1F680, 0x1F6FF),  # Transport and map symbols
+		(0x1F900, 0x1F9FF),  # Supplemental symbols and pictographs
+	]),
+]
+
+
+def checksum(data):
+	data += b"\0" * (-len(data) % 4)
+	return sum(struct.unpack(">%dI" % (len(data) // 4), data)) & 0xFFFFFFFF
+
+
+def box_glyph(width):
+	# A rectangle with a hole, two contours of four points
+	l, r, b, t = 50, width - 50, 0, 700
+	points = [(l, b), (l, t), (r, t), (r, b), (l + 80, b + 80), (r - 80, b + 80), (r - 80, t - 80), (l + 80, t - 80)]
+	data = struct.pack(">hhhhh", 2, l, b, r, t)
+	data += struct.pack(">HHH", 3, 7, 0)
+	data += bytes([0x01] * len(points))
+	x = y = 0
+	xs, ys = b"", b""
+	for px, py in points:
+		xs += struct.pack(">h", px - x)
+		ys += struct.pack(">h", py - y)
+		x, y = px, py
+	data += xs + ys
+	return data + b"\0" * (len(data) % 2)
+
+
+def name_table(family):
+	names = [(1, family), (2, "Regular"), (4, family), (6, family.replace(" ", ""))]
+	records, strings = b"", b""
+	for name_id, s in names:
+		raw = s.encode("utf-16-be")
+		records += struct.pack(">HHHHHH", 3, 1, 0x409, name_id, len(raw), len(strings))
+		strings += raw
+	return struct.pack(">HHH", 0, len(names), 6 + len(records)) + records + strings
+
+
+def cmap_table(ranges):
+	# Format 13: every character of the ranges has glyph 1
+	groups = b"".join(struct.pack(">III", a, b, 1) for a, b in ranges)
+	sub = struct.pack(">HHIII", 13, 0, 16 + len(groups), 0, len(ranges)) + groups
+	return struct.pack(">HHHHI", 0, 1, 0, 6, 12) + sub
+
+
+def make_font(family, width, ranges):
+	glyph = box_glyph(width)
+	first = min(a for a, _ in ranges)
+	last = max(b for _, b in ranges)
+	tables = {
+		b"head": struct.pack(">IIIIHHqqhhhhHHhhh", 0x00010000, 0x00010000, 0, 0x5F0F3CF5, 0x000B, UNITS_PER_EM,
+			0, 0, 0, -DESCENT, width, ASCENT, 0, 8, 2, 0, 0),
+		b"hhea": struct.pack(">IhhhHhhhhhhhhhhhH", 0x00010000, ASCENT, -DESCENT, 0, width, 0, 0, width,
+			1, 0, 0, 0, 0, 0, 0, 0, 2),
+		b"maxp": struct.pack(">IHHHHHHHHHHHHHH", 0x00010000, 2, 8, 2, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0),
+		b"OS/2": struct.pack(">HhHHHhhhhhhhhhhh10s4I4sHHHhhhHHIIhhHHH", 4, width, 400, 5, 0,
+			650, 600, 0, 75, 650, 600, 0, 350, 50, 250, 0, bytes(10), 0, 0, 0, 0, b"TEST", 0x40,
+			min(first, 0xFFFF), min(last, 0xFFFF), ASCENT, -DESCENT, 0, ASCENT, DESCENT, 1, 0, 500, 700, 0, 32, 0),
+		b"hmtx": struct.pack(">HhHh", width, 0, width, 50),
+		b"loca": struct.pack(">HHH", 0, 0, len(glyph) // 2),
+		b"glyf": glyph,
+		b"cmap": cmap_table(ranges),
+		b"name": name_table(family),
+		b"post": struct.pack(">IIhhIIIII", 0x00030000, 0, -100, 50, 0, 0, 0, 0, 0),
+	}
+
+	tags = sorted(tables)
+	entry_selector = max(i for i in range(16) if 2 ** i <= len(tags))
+	search_range = 2 ** entry_selector * 16
+	header = struct.pack(">IHHHH", 0x00010000, len(tags), search_range, entry_selector, len(tags) * 16 - search_range)
+	offset = len(header) + 16 * len(tags)
+	directory, body = b"", b""
+	offsets = {}
+	for tag in tags:
+		data = tables[tag]
+		offsets[tag] = offset + len(body)
+		directory += struct.pack(">4sIII", tag, checksum(data), offsets[tag], len(data))
+		body += data + b"\0" * (-len(data) % 4)
+	font = bytearray(header + directory + body)
+
+	# checkSumAdjustment of head, computed on the whole file
+	adjustment = (0xB1B0AFBA - checksum(bytes(font))) & 0xFFFFFFFF
+	font[offsets[b"head"] + 8:offsets[b"head"] + 12] = struct.pack(">I", adjustment)
+	return bytes(font)
+
+
+for family, file_name, width, ranges in FONTS:
+	with open(file_name, "wb") as f:
+		f.write(make_font(family, width, ranges))
@@ -0,0 +1,5 @@
+/* The web interface is embedded with include_dir!(), which cannot tell
+ * cargo to rebuild when its files change */
+fn main() {
+	println!("cargo:rerun-if-changed=runtime-data");
+}
@@ -103,14 +103,14 @@ function changePassword(oldpass, newpass)
 				reject('auth error');
 				return;
 			}
-			if (d.err != undefined) {
-				let merr = 'Server error while saving config: ' + d.err;
+			if (d.code == 'forbidden') {
+				let merr = 'Invalid old password, please retry.';
 				enableControls(true);
 				reject(merr);
 				return;
 			}
-			if (d.rc == 1) {
-				let merr = 'Invalid old password, please retry.';
+			if (d.err != undefined) {
+				let merr = 'Server error while saving config: ' + d.err;
 				enableControls(true);
 				reject(merr);
 				return;
@@ -1,11 +1,71 @@
+/* Version of the configuration shown in the form */
+let cfgVersion = null;
+let saving = false;
+/* Family of the configuration, kept until the font list is read */
+let fontFamily = '';
+/* Same for the background image, and the gradient colors between the
+ * first and the last one, which the form does not show */
+let backgroundImage = '';
+let gradientColors = [];
+
 document.addEventListener("DOMContentLoaded", function(event) {
+	readFonts();
+	readImages();
 	readConfig();
+	readWhoami();
+	subscribeEvents();
 
 	document.getElementById('btnApply').addEventListener('click', (e) => {
 		showNotification(null);
+		saving = true;
 		saveConfig()
 		.then(() => readConfig())
-		.catch(msg => showNotification(msg));
+		.catch(msg => showNotification(msg))
+		.finally(() => saving = false);
+	});
+
+	document.getElementById('btnPause').addEventListener('click', (e) => {
+		let cmd = document.getElementById('btnPause').dataset.paused == "true" ? 'resume' : 'pause';
+		fetch('/lapi', {
+			method: 'POST',
+			body: JSON.stringify({cmd: cmd})
+		})
+		.then(response => response.json())
+		.then(d => {
+			if (d.err != undefined)
+				showNotification(d.err);
+			else
+				showPaused(d.paused);
+		})
+		.catch(err => showNotification('Error: ' + err));
+	});
+
+	document.getElementById('fontupload').addEventListener('change', (e) => {
+		let file = e.target.files[0];
+		if (file == undefined)
+			return;
+		showNotification(null);
+		uploadFont(file)
+		.then(font => {
+			fontFamily = font.family;
+			readFonts();
+		})
+		.catch(msg => showNotification(msg))
+		.finally(() => e.target.value = '');
+	});
+
+	document.getElementById('imageupload').addEventListener('change', (e) => {
+		let file = e.target.files[0];
+		if (file == undefined)
+			return;
+		showNotification(null);
+		uploadImage(file)
+		.then(image => {
+			backgroundImage = image.file;
+			readImages();
+		})
+		.catch(msg => showNotification(msg))
+		.finally(() => e.target.value = '');
 	});
 
 	bulmaNavbarEnable();
@@ -25,6 +85,33 @@ function showNotification(msg) {
 	}
 }
 
+/* Keeps the page in sync with the changes made by the other operators */
+function subscribeEvents() {
+	if (typeof EventSource === 'undefined')
+		return;
+	const es = new EventSource('/events');
+	es.addEventListener('state', (e) => {
+		let s = JSON.parse(e.data);
+		showPaused(s.paused);
+		configVersionChanged(s.version);
+	});
+	es.addEventListener('config', (e) => configVersionChanged(JSON.parse(e.data).version));
+	es.addEventListener('pause', (e) => showPaused(JSON.parse(e.data).paused));
+}
+
+function configVersionChanged(version) {
+	if (saving || cfgVersion == null || version == cfgVersion)
+		return;
+	showNotification('The configuration has been changed by another operator and has been reloaded.');
+	readConfig();
+}
+
+function showPaused(paused) {
+	let b = document.getElementById('btnPause');
+	b.dataset.paused = paused ? "true" : "false";
+	b.innerText = paused ? 'Resume' : 'Pause';
+}
+
 function bulmaNotifEnable() {
 	document.querySelectorAll('.notification .delete').forEach( del => {
 		del.addEventListener('click', () => {
@@ -49,18 +136,52 @@ function bulmaNavbarEnable() {
 
 
 function fillform(cfg) {
+	cfgVersion = cfg.version;
 	document.getElementById('currenttext').value = cfg.disp_text;
 	document.getElementById('newtext').value = cfg.disp_text;
 	document.getElementById('scrollspeed').value = cfg.disp_scrollspeed;
 	document.getElementById('backgroundcolor').value = cfg.disp_backgroundcolor;
+	document.querySelectorAll('input[name="background"]').forEach((input) => input.checked = false);
+	document.querySelector('input[name="background"][value="' + cfg.disp_background + '"]').checked = true;
+	gradientColors = cfg.disp_gradientcolors;
+	document.getElementById('gradientfrom').value = gradientColors.length > 0 ? gradientColors[0] : '#000000';
+	document.getElementById('gradientto').value = gradientColors.length > 1 ? gradientColors[gradientColors.length - 1] : '#000000';
+	document.getElementById('gradientangle').value = cfg.disp_gradientangle;
+	backgroundImage = cfg.disp_backgroundimage;
+	selectBackgroundImage();
+	document.querySelectorAll('input[name="imagescaling"]').forEach((input) => input.checked = false);
+	document.querySelector('input[name="imagescaling"][value="' + cfg.disp_imagescaling + '"]').checked = true;
 	document.getElementById('textcolor').value = cfg.disp_textcolor;
 	document.getElementById('hmargin').value = cfg.disp_hmargin;
 	document.getElementById('vmargin').value = cfg.disp_vmargin;
 	document.getElementById('fontsize').value = cfg.disp_fontsize;
+	document.getElementById('lines').value = cfg.disp_lines;
+	document.querySelectorAll('input[name="autosize"]').forEach((input) => input.checked = false);
+	document.querySelector('input[name="autosize"][value="' + cfg.disp_autosize + '"]').checked = true;
+	document.getElementById('fontweight').value = cfg.disp_fontweight;
+	fontFamily = cfg.disp_fontfamily;
+	selectFontFamily();
 
 	document.querySelectorAll('input[name="orientation"]').forEach((input) => input.checked = false);
 	document.querySelector('input[name="orientation"][value="' + cfg.disp_orientation+ '"]').checked = true;
 
+	document.querySelectorAll('input[name="mode"]').forEach((input) => input.checked = false);
+	document.querySelector('input[name="mode"][value="' + cfg.disp_mode + '"]').checked = true;
+	document.getElementById('dwell').value = cfg.disp_dwell;
+	document.getElementById('typespeed').value = cfg.disp_typespeed;
+	document.getElementById('blinkinterval').value = cfg.disp_blinkinterval;
+
+	document.getElementById('separator').value = cfg.disp_separator;
+	document.getElementById('spacing').value = cfg.disp_spacing;
+	document.querySelectorAll('input[name="continuous"]').forEach((input) => input.checked = false);
+	document.querySelector('input[name="continuous"][value="' + cfg.disp_continuous + '"]').checked = true;
+
+	document.querySelectorAll('input[name="textalign"]').forEach((input) => input.checked = false);
+	document.querySelector('input[name="textalign"][value="' + cfg.disp_textalign + '"]').checked = true;
+
+	document.querySelectorAll('input[name="followdirection"]').forEach((input) => input.checked = false);
+	document.querySelector('input[name="followdirection"][value="' + cfg.disp_followdirection + '"]').checked = true;
+
 	document.querySelectorAll('input[name="fullscreen"]').forEach((input) => input.checked = false);
 	document.querySelector('input[name="fullscreen"][value="' + cfg.disp_fullscreen+ '"]').checked = true;
 
@@ -90,6 +211,141 @@ function readConfig()
 	});
 }
 
+/* One entry per family, the embedded font is the default */
+function fillFontList(fonts)
+{
+	let select = document.getElementById('fontfamily');
+	select.replaceChildren();
+	let families = new Set();
+	fonts.forEach((f) => {
+		if (families.has(f.family))
+			return;
+		families.add(f.family);
+		let opt = document.createElement('option');
+		opt.value = f.source == 'Embedded' ? '' : f.family;
+		opt.innerText = f.source == 'Embedded' ? f.family + ' (default)' : f.family;
+		select.appendChild(opt);
+	});
+	selectFontFamily();
+}
+
+function selectFontFamily()
+{
+	let select = document.getElementById('fontfamily');
+	let found = Array.from(select.options).some((o) => o.value == fontFamily);
+	select.value = found ? fontFamily : '';
+}
+
+function readFonts()
+{
+	fetch('/lapi', {
+		method: 'POST',
+		body: JSON.stringify({cmd: 'font_list'})
+	})
+	.then(response => response.json())
+	.then(d => {
+		if (d.err != undefined || d.auth != undefined)
+			return;
+		fillFontList(d.fonts);
+	})
+	.catch(err => {
+		console.log(err);
+	});
+}
+
+function uploadFont(file)
+{
+	return fetch('/api/v1/fonts?name=' + encodeURIComponent(file.name), {
+		method: 'POST',
+		body: file
+	})
+	.then(response => response.json())
+	.then(d => {
+		if (d.error != undefined)
+			throw 'Unable to upload the font: ' + d.error.message;
+		return d;
+	}, err => {
+		throw 'Error while uploading the font: ' + err;
+	});
+}
+
+function fillImageList(images)
+{
+	let select = document.getElementById('backgroundimage');
+	select.replaceChildren();
+	let none = document.createElement('option');
+	none.value = '';
+	none.innerText = '(none)';
+	select.appendChild(none);
+	images.forEach((i) => {
+		let opt = document.createElement('option');
+		opt.value = i.file;
+		opt.innerText = i.file + ' (' + i.width + 'x' + i.height + ')';
+		select.appendChild(opt);
+	});
+	selectBackgroundImage();
+}
+
+function selectBackgroundImage()
+{
+	let select = document.getElementById('backgroundimage');
+	let found = Array.from(select.options).some((o) => o.value == backgroundImage);
+	select.value = found ? backgroundImage : '';
+}
+
+function readImages()
+{
+	fetch('/lapi', {
+		method: 'POST',
+		body: JSON.stringify({cmd: 'image_list'})
+	})
+	.then(response => response.json())
+	.then(d => {
+		if (d.err != undefined || d.auth != undefined)
+			return;
+		fillImageList(d.images);
+	})
+	.catch(err => {
+		console.log(err);
+	});
+}
+
+function uploadImage(file)
+{
+	return fetch('/api/v1/images?name=' + encodeURIComponent(file.name), {
+		method: 'POST',
+		body: file
+	})
+	.then(response => response.json())
+	.then(d => {
+		if (d.error != undefined)
+			throw 'Unable to upload the image: ' + d.error.message;
+		return d;
+	}, err => {
+		throw 'Error while uploading the image: ' + err;
+	});
+}
+
+/* Shows the user and what their role allows */
+function readWhoami()
+{
+	fetch('/lapi', {
+		method: 'POST',
+		body: JSON.stringify({cmd: 'whoami'})
+	})
+	.then(response => response.json())
+	.then(d => {
+		if (d.err != undefined || d.auth != undefined)
+			return;
+		document.getElementById('whoami').innerText = d.username + ' (' + d.role + ')';
+		if (d.role == 'Admin')
+			document.getElementById('lnkUsers').classList.remove('is-hidden');
+	})
+	.catch(err => {
+		console.log(err);
+	});
+}
+
 function getNumberFromForm(id, defaultval)
 {
 	let v = parseInt(document.getElementById(id).value);
@@ -99,17 +355,28 @@ function getNumberFromForm(id, defaultval)
 		return v;
 }
 
+function getFloatFromForm(id, defaultval)
+{
+	let v = parseFloat(document.getElementById(id).value);
+	if (isNaN(v))
+		return defaultval;
+	else
+		return v;
+}
+
 function enableControls(enable)
 {
 	if (!enable) {
 		document.getElementById('btnApply').classList.add('is-loading');
 		document.querySelectorAll('input').forEach((e) => {e.disabled = true;});
 		document.querySelectorAll('textarea').forEach((e) => {e.disabled = true;});
+		document.querySelectorAll('select').forEach((e) => {e.disabled = true;});
 		document.querySelectorAll('button').forEach((e) => {e.disabled = true;});
 	} else {
 		document.getElementById('btnApply').classList.remove('is-loading');
 		document.querySelectorAll('input').forEach((e) => {e.disabled = false;});
 		document.querySelectorAll('textarea').forEach((e) => {e.disabled = false;});
+		document.querySelectorAll('select').forEach((e) => {e.disabled = false;});
 		document.querySelectorAll('button').forEach((e) => {e.disabled = false;});
 	}
 }
@@ -120,18 +387,37 @@ function saveConfig()
 		enableControls(false);
 		let cfg = {
 			disp_text: document.getElementById('newtext').value,
-			disp_scrollspeed: getNumberFromForm('scrollspeed', 1),
+			disp_scrollspeed: getNumberFromForm('scrollspeed', 60),
 			disp_hmargin: getNumberFromForm('hmargin', 10),
 			disp_vmargin: getNumberFromForm('vmargin', 10),
 			disp_fontsize: getNumberFromForm('fontsize', 24),
+			disp_autosize: document.querySelector('input[name="autosize"]:checked').value == "true",
+			disp_lines: getNumberFromForm('lines', 3),
+			disp_fontfamily: document.getElementById('fontfamily').value,
+			disp_fontweight: getNumberFromForm('fontweight', 400),
 			disp_backgroundcolor: document.getElementById('backgroundcolor').value,
+			disp_background: document.querySelector('input[name="background"]:checked').value,
+			disp_gradientcolors: [document.getElementById('gradientfrom').value]
+				.concat(gradientColors.slice(1, -1), [document.getElementById('gradientto').value]),
+			disp_gradientangle: getFloatFromForm('gradientangle', 180),
+			disp_backgroundimage: document.getElementById('backgroundimage').value,
+			disp_imagescaling: document.querySelector('input[name="imagescaling"]:checked').value,
 			disp_textcolor: document.getElementById('textcolor').value,
 			disp_orientation: document.querySelector('input[name="orientation"]:checked').value,
+			disp_mode: document.querySelector('input[name="mode"]:checked').value,
+			disp_dwell: getFloatFromForm('dwell', 3),
+			disp_typespeed: getFloatFromForm('typespeed', 15),
+			disp_blinkinterval: getFloatFromForm('blinkinterval', 0.5),
+			disp_continuous: document.querySelector('input[name="continuous"]:checked').value == "true",
+			disp_separator: document.getElementById('separator').value,
+			disp_spacing: getNumberFromForm('spacing', 0),
+			disp_textalign: document.querySelector('input[name="textalign"]:checked').value,
+			disp_followdirection: document.querySelector('input[name="followdirection"]:checked').value == "true",
 			disp_fullscreen: document.querySelector('input[name="fullscreen"]:checked').value == "true"
 		};
 		fetch('/lapi', {
 			method: 'POST',
-			body: JSON.stringify({cmd: 'config_set', cfg: cfg })
+			body: JSON.stringify({cmd: 'config_set', cfg: cfg, version: cfgVersion })
 		})
 		.then(response => response.json())
 		.then(d => {
@@ -146,6 +432,7 @@ function saveConfig()
 				reject(merr);
 				return;
 			}
+			cfgVersion = d.version;
 			enableControls(true);
 			resolve();
 		})
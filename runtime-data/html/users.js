@@ -0,0 +1,177 @@
+
+document.addEventListener("DOMContentLoaded", function(event) {
+
+	readUsers();
+	readSessions();
+
+	document.getElementById('btnSave').addEventListener('click', (e) => {
+		showNotification(null);
+		let rq = {
+			cmd: 'user_set',
+			username: document.getElementById('frmUsername').value,
+			role: document.getElementById('frmRole').value
+		};
+		let password = document.getElementById('frmPassword').value;
+		if (password != '')
+			rq.password = password;
+
+		lapi(rq)
+		.then(() => {
+			document.getElementById('frmUsername').value = '';
+			document.getElementById('frmPassword').value = '';
+			readUsers();
+		})
+		.catch(msg => showNotification(msg));
+	});
+
+	bulmaNotifEnable();
+
+});
+
+function showNotification(msg) {
+	let n = document.getElementById('notification1');
+	let nt = n.querySelector('.notiftext');
+	if (msg == null) {
+		nt.innerText = '';
+		n.classList.add('is-hidden');
+	} else {
+		nt.innerText = msg;
+		n.classList.remove('is-hidden');
+	}
+}
+
+function bulmaNotifEnable() {
+	document.querySelectorAll('.notification .delete').forEach( del => {
+		del.addEventListener('click', () => {
+			del.parentNode.classList.add('is-hidden');
+		});
+	});
+}
+
+/* Runs a lapi command, resolves with its result */
+function lapi(rq)
+{
+	return new Promise((resolve, reject) => {
+		fetch('/lapi', {
+			method: 'POST',
+			body: JSON.stringify(rq)
+		})
+		.then(response => response.json())
+		.then(d => {
+			if (d.auth != undefined) {
+				document.location = "auth.html";
+				reject('auth error');
+				return;
+			}
+			if (d.err != undefined) {
+				reject(d.err);
+				return;
+			}
+			resolve(d);
+		})
+		.catch(err => reject('Error: ' + err));
+	});
+}
+
+function readUsers()
+{
+	lapi({cmd: 'user_list'})
+	.then(d => fillUserList(d.users))
+	.catch(msg => showNotification(msg));
+}
+
+function fillUserList(users)
+{
+	let tbody = document.getElementById('userlist');
+	tbody.innerHTML = '';
+	users.forEach(u => {
+		let tr = document.createElement('tr');
+
+		let tdName = document.createElement('td');
+		tdName.innerText = u.username;
+		tr.appendChild(tdName);
+
+		let tdRole = document.createElement('td');
+		tdRole.innerText = u.role;
+		tr.appendChild(tdRole);
+
+		let tdButtons = document.createElement('td');
+		let btnEdit = document.createElement('button');
+		btnEdit.className = 'button is-small';
+		btnEdit.innerText = 'Edit';
+		btnEdit.addEventListener('click', () => {
+			document.getElementById('frmUsername').value = u.username;
+			document.getElementById('frmRole').value = u.role;
+			document.getElementById('frmPassword').focus();
+		});
+		tdButtons.appendChild(btnEdit);
+
+		let btnDelete = document.createElement('button');
+		btnDelete.className = 'button is-small is-danger ml-2';
+		btnDelete.innerText = 'Delete';
+		btnDelete.addEventListener('click', () => {
+			if (!confirm('Delete user ' + u.username + '?'))
+				return;
+			showNotification(null);
+			lapi({cmd: 'user_delete', username: u.username})
+			.then(() => {
+				readUsers();
+				readSessions();
+			})
+			.catch(msg => showNotification(msg));
+		});
+		tdButtons.appendChild(btnDelete);
+		tr.appendChild(tdButtons);
+
+		tbody.appendChild(tr);
+	});
+}
+
+function formatDuration(sec)
+{
+	if (sec < 60)
+		return sec + ' s';
+	if (sec < 3600)
+		return Math.floor(sec / 60) + ' min';
+	return Math.floor(sec / 3600) + ' h ' + Math.floor((sec % 3600) / 60) + ' min';
+}
+
+function readSessions()
+{
+	lapi({cmd: 'session_list'})
+	.then(d => fillSessionList(d.sessions))
+	.catch(msg => showNotification(msg));
+}
+
+function fillSessionList(sessions)
+{
+	let tbody = document.getElementById('sessionlist');
+	tbody.innerHTML = '';
+	sessions.forEach(s => {
+		let tr = document.createElement('tr');
+		[s.username, s.remote_addr, formatDuration(s.age_sec) + ' ago', formatDuration(s.idle_sec)].forEach(v => {
+			let td = document.createElement('td');
+			td.innerText = v;
+			tr.appendChild(td);
+		});
+
+		let tdButtons = document.createElement('td');
+		if (s.current) {
+			tdButtons.innerText = 'This session';
+		} else {
+			let btnRevoke = document.createElement('button');
+			btnRevoke.className = 'button is-small is-danger';
+			btnRevoke.innerText = 'Log out';
+			btnRevoke.addEventListener('click', () => {
+				showNotification(null);
+				lapi({cmd: 'session_revoke', id: s.id})
+				.then(() => readSessions())
+				.catch(msg => showNotification(msg));
+			});
+			tdButtons.appendChild(btnRevoke);
+		}
+		tr.appendChild(tdButtons);
+
+		tbody.appendChild(tr);
+	});
+}
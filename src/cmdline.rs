@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+
+use chrono::NaiveDateTime;
+use speedy2d::dimen::Vector2;
+
+pub const USAGE: &str = "\
+Usage: screen-text-scroller [OPTIONS]
+
+Without options, opens the scroller window and the HTTP control panel.
+
+  --webroot <dir>        Serve the web interface from <dir> instead of the
+                         files embedded in the executable
+
+Offscreen rendering (no window, no GPU needed):
+  --render-frames <dir>  Render frames to <dir>/frameNNNNN.png and exit
+  --frames <n>           Number of frames to render (default 1)
+  --size <W>x<H>         Size of the frames in pixels (default 1920x1080)
+  --fps <fps>            Frames per second used to advance the scrolling (default 30)
+  --config <file>        Read the configuration from <file> instead of the user config dir
+  --at <date time>       Show what the schedule shows at \"YYYY-MM-DD HH:MM\" local time
+                         (default: the configuration as it is, without the schedule)
+
+  --help                 Show this help
+";
+
+pub struct RenderFramesOptions {
+	pub dir: PathBuf,
+	pub frames: u32,
+	pub size: Vector2<u32>,
+	pub fps: f32,
+	pub config_file: Option<PathBuf>,
+	/* Time deciding the schedule rule, None for no rule */
+	pub at: Option<NaiveDateTime>
+}
+
+#[derive(Default)]
+pub struct CmdLineOptions {
+	pub help: bool,
+	pub webroot: Option<PathBuf>,
+	pub render_frames: Option<RenderFramesOptions>
+}
+
+fn parse_datetime(s: &str) -> Option<NaiveDateTime> {
+	["%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S"].iter()
+		.find_map(|f| NaiveDateTime::parse_from_str(s.trim(), f).ok())
+}
+
+fn parse_size(s: &str) -> Option<Vector2<u32>> {
+	let (w, h) = s.split_once(['x', 'X'])?;
+	let w = w.trim().parse::<u32>().ok()?;
+	let h = h.trim().parse::<u32>().ok()?;
+	if w == 0 || h == 0 {
+		return None;
+	}
+	Some(Vector2::new(w, h))
+}
+
+pub fn parse_cmdline<I: Iterator<Item = String>>(mut args: I) -> Result<CmdLineOptions, String> {
+	let mut opts = CmdLineOptions::default();
+	let mut render_dir = None;
+	let mut frames = 1;
+	let mut size = Vector2::new(1920, 1080);
+	let mut fps = 30.0;
+	let mut config_file = None;
+	let mut at = None;
+	let mut offscreen_only = None;
+
+	while let Some(arg) = args.next() {
+		let mut value = |name: &str| args.next().ok_or(format!("Missing value for {}", name));
+		match arg.as_str() {
+			"--help" | "-h" => opts.help = true,
+			"--webroot" => opts.webroot = Some(PathBuf::from(value(&arg)?)),
+			"--render-frames" => render_dir = Some(PathBuf::from(value(&arg)?)),
+			"--frames" => {
+				let v = value(&arg)?;
+				frames = v.parse::<u32>().map_err(|_| format!("Invalid number of frames: {}", v))?;
+				offscreen_only = Some(arg);
+			},
+			"--size" => {
+				let v = value(&arg)?;
+				size = parse_size(&v).ok_or(format!("Invalid size {}, expected WxH", v))?;
+				offscreen_only = Some(arg);
+			},
+			"--fps" => {
+				let v = value(&arg)?;
+				fps = match v.parse::<f32>() {
+					Ok(f) if f.is_finite() && f > 0.0 => f,
+					_ => return Err(format!("Invalid frames per second: {}", v))
+				};
+				offscreen_only = Some(arg);
+			},
+			"--config" => {
+				config_file = Some(PathBuf::from(value(&arg)?));
+				offscreen_only = Some(arg);
+			},
+			"--at" => {
+				let v = value(&arg)?;
+				at = Some(parse_datetime(&v).ok_or(format!("Invalid date and time {}, expected YYYY-MM-DD HH:MM", v))?);
+				offscreen_only = Some(arg);
+			},
+			_ => return Err(format!("Unknown option {}", arg))
+		}
+	}
+
+	match render_dir {
+		Some(dir) => opts.render_frames = Some(RenderFramesOptions { dir, frames, size, fps, config_file, at }),
+		None => {
+			if let Some(o) = offscreen_only {
+				return Err(format!("{} can only be used with --render-frames", o));
+			}
+		}
+	}
+
+	Ok(opts)
+}
@@ -0,0 +1,329 @@
+/* The fonts the text can be shown with: the one embedded in the
+ * executable, the ones uploaded through the web interface, the ones in
+ * runtime-data/fonts and the fonts of the system.  Only the headers of the
+ * files are read to make the list, a font is loaded when a zone uses it. */
+
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::lconfig::Config;
+
+pub const EMBEDDED_FONT: &[u8] = include_bytes!("../assets/fonts/Ubuntu-R.ttf");
+
+/* Next to the configuration file */
+const USER_FONTS_DIR: &str = "fonts";
+const RUNTIME_DATA_FONTS_DIR: &str = "fonts";
+const FONT_EXTENSIONS: &[&str] = &["ttf", "otf"];
+pub const MAX_FONT_FILE_SIZE: usize = 32 * 1024 * 1024;
+pub const DEFAULT_FONT_WEIGHT: u16 = 400;
+/* Fonts covering other scripts and symbols, tried in this order for the
+ * characters missing from the font of a zone.  Color emoji fonts have no
+ * outlines and cannot be drawn, a monochrome emoji font is needed */
+pub const DEFAULT_FALLBACK_FONTS: &[&str] = &[
+	"Noto Sans", "DejaVu Sans", "Noto Sans Arabic", "Noto Sans Hebrew", "Noto Sans Devanagari",
+	"Noto Sans Thai", "Noto Sans CJK SC", "Noto Sans SC", "Droid Sans Fallback", "Noto Emoji",
+	"Symbola"
+];
+/* The system directories are searched this deep, in case of link loops */
+const MAX_SCAN_DEPTH: usize = 8;
+
+#[cfg(all(unix, not(target_os = "macos")))]
+const SYSTEM_FONT_DIRS: &[&str] = &["/usr/share/fonts", "/usr/local/share/fonts"];
+#[cfg(target_os = "macos")]
+const SYSTEM_FONT_DIRS: &[&str] = &["/System/Library/Fonts", "/Library/Fonts"];
+#[cfg(windows)]
+const SYSTEM_FONT_DIRS: &[&str] = &["C:\\Windows\\Fonts"];
+#[cfg(not(any(unix, windows)))]
+const SYSTEM_FONT_DIRS: &[&str] = &[];
+
+/* Where a font comes from, in the order they are preferred when several
+ * fonts have the same family and weight */
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum FontSource {
+	Embedded,
+	User,
+	RuntimeData,
+	System
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct FontInfo {
+	pub family: String,
+	/* 100 (thin) to 900 (black), 400 is regular and 700 bold */
+	pub weight: u16,
+	pub italic: bool,
+	pub source: FontSource,
+	/* File name, empty for the embedded font */
+	pub file: String,
+	#[serde(skip)]
+	pub path: Option<PathBuf>
+}
+
+impl FontInfo {
+	/* Contents of the font file */
+	pub fn load(&self) -> Result<Vec<u8>, Error> {
+		match &self.path {
+			Some(p) => std::fs::read(p),
+			None => Ok(EMBEDDED_FONT.to_vec())
+		}
+	}
+}
+
+/* The known fonts, the embedded one first */
+#[derive(Clone)]
+pub struct FontCatalog {
+	pub fonts: Vec<FontInfo>,
+	/* Changes every time a font is added or removed */
+	pub generation: u64
+}
+
+struct FontFace {
+	family: String,
+	weight: u16,
+	italic: bool
+}
+
+fn be16(b: &[u8], at: usize) -> Option<u16> {
+	b.get(at..at + 2).map(|v| u16::from_be_bytes([v[0], v[1]]))
+}
+
+fn be32(b: &[u8], at: usize) -> Option<u32> {
+	b.get(at..at + 4).map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+}
+
+/* The family in the name table, preferring the typographic family (which
+ * groups all the weights) and the English names of the Windows platform */
+fn family_name(table: &[u8]) -> Option<String> {
+	let count = be16(table, 2)? as usize;
+	let strings = be16(table, 4)? as usize;
+	let mut best: Option<(u32, String)> = None;
+
+	for i in 0..count {
+		let r = 6 + i * 12;
+		let (platform, lang, name_id) = (be16(table, r)?, be16(table, r + 4)?, be16(table, r + 6)?);
+		let (len, off) = (be16(table, r + 8)? as usize, be16(table, r + 10)? as usize);
+		let score = match name_id {
+			16 => 4,
+			1 => 0,
+			_ => continue
+		} + match (platform, lang) {
+			(3, 0x409) => 2,
+			(3, _) | (0, _) => 1,
+			_ => 0
+		};
+		if best.as_ref().is_some_and(|b| b.0 >= score) {
+			continue;
+		}
+		let Some(raw) = table.get(strings + off..strings + off + len) else {
+			continue;
+		};
+		let name = match platform {
+			/* Unicode and Windows names are UTF-16 */
+			0 | 3 => {
+				let units: Vec<u16> = raw.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+				String::from_utf16_lossy(&units)
+			},
+			_ => raw.iter().map(|b| *b as char).collect()
+		};
+		let name = name.trim().to_string();
+		if !name.is_empty() {
+			best = Some((score, name));
+		}
+	}
+	best.map(|b| b.1)
+}
+
+/* Family, weight and style of a TrueType or OpenType font, reading only
+ * the tables needed */
+fn read_face(read_at: &mut dyn FnMut(u64, usize) -> Option<Vec<u8>>) -> Option<FontFace> {
+	let header = read_at(0, 12)?;
+	if !matches!(&header[0..4], [0, 1, 0, 0] | b"OTTO" | b"true") {
+		return None;
+	}
+	let num_tables = be16(&header, 4)? as usize;
+	let records = read_at(12, num_tables * 16)?;
+
+	let mut name = None;
+	let mut os2 = None;
+	for r in records.chunks_exact(16) {
+		let table = (be32(r, 8)? as u64, be32(r, 12)? as usize);
+		match &r[0..4] {
+			b"name" => name = Some(table),
+			b"OS/2" => os2 = Some(table),
+			_ => ()
+		}
+	}
+
+	let (off, len) = name?;
+	let family = family_name(&read_at(off, len)?)?;
+	/* usWeightClass and the italic bit of fsSelection */
+	let (weight, italic) = match os2 {
+		Some((off, len)) if len >= 64 => {
+			let t = read_at(off, 64)?;
+			(be16(&t, 4)?.clamp(1, 1000), be16(&t, 62)? & 1 != 0)
+		},
+		_ => (DEFAULT_FONT_WEIGHT, false)
+	};
+	Some(FontFace { family, weight, italic })
+}
+
+fn face_from_bytes(data: &[u8]) -> Option<FontFace> {
+	read_face(&mut |off, len| data.get(off as usize..off as usize + len).map(<[u8]>::to_vec))
+}
+
+fn face_from_file(path: &Path) -> Option<FontFace> {
+	let mut f = File::open(path).ok()?;
+	read_face(&mut |off, len| {
+		/* Corrupt files may claim huge tables */
+		if len > MAX_FONT_FILE_SIZE {
+			return None;
+		}
+		let mut buf = vec![0u8; len];
+		f.seek(SeekFrom::Start(off)).ok()?;
+		f.read_exact(&mut buf).ok()?;
+		Some(buf)
+	})
+}
+
+fn is_font_file(path: &Path) -> bool {
+	path.extension()
+		.and_then(|e| e.to_str())
+		.is_some_and(|e| FONT_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+fn scan_dir(dir: &Path, source: FontSource, depth: usize, fonts: &mut Vec<FontInfo>) {
+	let Ok(entries) = std::fs::read_dir(dir) else {
+		return;
+	};
+	let mut paths: Vec<PathBuf> = entries.filter_map(|e| e.ok().map(|e| e.path())).collect();
+	paths.sort();
+	for path in paths {
+		if path.is_dir() {
+			if depth < MAX_SCAN_DEPTH {
+				scan_dir(&path, source, depth + 1, fonts);
+			}
+		} else if is_font_file(&path) {
+			if let Some(face) = face_from_file(&path) {
+				fonts.push(FontInfo {
+					family: face.family,
+					weight: face.weight,
+					italic: face.italic,
+					source,
+					file: path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+					path: Some(path)
+				});
+			}
+		}
+	}
+}
+
+pub fn user_fonts_dir() -> PathBuf {
+	Config::get_config_file_name(false).with_file_name(USER_FONTS_DIR)
+}
+
+/* The font directories of the user, in their home */
+fn home_font_dirs() -> Vec<PathBuf> {
+	let Some(dirs) = directories_next::BaseDirs::new() else {
+		return Vec::new();
+	};
+	let home = dirs.home_dir();
+	if cfg!(target_os = "macos") {
+		vec![home.join("Library/Fonts")]
+	} else if cfg!(windows) {
+		vec![dirs.data_local_dir().join("Microsoft\\Windows\\Fonts")]
+	} else {
+		vec![dirs.data_dir().join("fonts"), home.join(".fonts")]
+	}
+}
+
+impl FontCatalog {
+	/* Only the embedded font */
+	pub fn embedded() -> FontCatalog {
+		let face = face_from_bytes(EMBEDDED_FONT).expect("Unable to read the embedded font");
+		FontCatalog {
+			fonts: vec![FontInfo {
+				family: face.family,
+				weight: face.weight,
+				italic: face.italic,
+				source: FontSource::Embedded,
+				file: String::new(),
+				path: None
+			}],
+			generation: 0
+		}
+	}
+
+	/* The fonts coming with the application and the uploaded ones, the
+	 * same on every machine */
+	pub fn scan_without_system(runtime_data_dir: &Path) -> FontCatalog {
+		let mut c = FontCatalog::embedded();
+		scan_dir(&user_fonts_dir(), FontSource::User, 0, &mut c.fonts);
+		scan_dir(&runtime_data_dir.join(RUNTIME_DATA_FONTS_DIR), FontSource::RuntimeData, 0, &mut c.fonts);
+		c
+	}
+
+	pub fn scan(runtime_data_dir: &Path) -> FontCatalog {
+		let mut c = FontCatalog::scan_without_system(runtime_data_dir);
+		for dir in SYSTEM_FONT_DIRS.iter().map(PathBuf::from).chain(home_font_dirs()) {
+			scan_dir(&dir, FontSource::System, 0, &mut c.fonts);
+		}
+		c
+	}
+
+	/* The font of family closest to weight, not italic if possible */
+	pub fn find(&self, family: &str, weight: u16) -> Option<usize> {
+		self.fonts.iter().enumerate()
+			.filter(|(_, f)| f.family.eq_ignore_ascii_case(family.trim()))
+			.min_by_key(|(i, f)| (f.italic, f.weight.abs_diff(weight), *i))
+			.map(|(i, _)| i)
+	}
+
+	/* Adds a font uploaded by a user, replacing the one with the same file */
+	pub fn add_user_font(&mut self, info: FontInfo) {
+		self.fonts.retain(|f| !(f.source == FontSource::User && f.file == info.file));
+		/* Before the runtime-data and system fonts */
+		let pos = self.fonts.iter().position(|f| f.source != FontSource::Embedded && f.source != FontSource::User)
+			.unwrap_or(self.fonts.len());
+		self.fonts.insert(pos, info);
+		self.generation += 1;
+	}
+
+	pub fn user_font(&self, file_name: &str) -> Option<&FontInfo> {
+		self.fonts.iter().find(|f| f.source == FontSource::User && f.file == file_name)
+	}
+
+	pub fn remove_user_font(&mut self, file_name: &str) {
+		self.fonts.retain(|f| !(f.source == FontSource::User && f.file == file_name));
+		self.generation += 1;
+	}
+}
+
+/* Checks a font uploaded by a user, returning where to save it */
+pub fn check_user_font(file_name: &str, data: &[u8]) -> Result<FontInfo, Error> {
+	let invalid = |msg: &str| Error::new(ErrorKind::InvalidInput, msg.to_string());
+	let path = Path::new(file_name);
+	if file_name.starts_with('.') || path.file_name() != Some(path.as_os_str()) {
+		return Err(invalid("Invalid font file name"));
+	}
+	if !is_font_file(path) {
+		return Err(invalid("Only .ttf and .otf fonts are supported"));
+	}
+	if data.len() > MAX_FONT_FILE_SIZE {
+		return Err(invalid("The font file is too large"));
+	}
+	let face = face_from_bytes(data).ok_or_else(|| invalid("Not a TrueType or OpenType font"))?;
+	if rusttype::Font::try_from_bytes(data).is_none() {
+		return Err(invalid("Unable to load the font"));
+	}
+	Ok(FontInfo {
+		family: face.family,
+		weight: face.weight,
+		italic: face.italic,
+		source: FontSource::User,
+		file: file_name.to_string(),
+		path: Some(user_fonts_dir().join(file_name))
+	})
+}
@@ -0,0 +1,37 @@
+/* Offscreen rendering: draws the frames the window would show into PNG
+ * files, rasterising the text on the CPU.  Used to check what a given
+ * configuration looks like on machines without a display or a GPU. */
+
+use image::ImageError;
+
+use crate::cmdline::RenderFramesOptions;
+use crate::fonts::FontCatalog;
+use crate::images::ImageCatalog;
+use crate::lconfig::Config;
+use crate::render::Renderer;
+use crate::render::image_canvas::ImageCanvas;
+use crate::render::layout::FontSet;
+
+pub fn render_frames(opts: &RenderFramesOptions, cfg: Config, fonts: FontCatalog, images: ImageCatalog) -> Result<(), ImageError> {
+	std::fs::create_dir_all(&opts.dir)?;
+
+	let mut renderer = Renderer::new(FontSet::new(fonts), images);
+	renderer.resize(opts.size);
+	renderer.set_config(cfg);
+
+	let frame_time = 1.0 / opts.fps;
+	let mut canvas = ImageCanvas::new(opts.size.x, opts.size.y);
+
+	for n in 0..opts.frames {
+		if n > 0 {
+			renderer.advance(frame_time);
+		}
+		renderer.draw(&mut canvas);
+
+		let filename = opts.dir.join(format!("frame{:05}.png", n));
+		canvas.image().save(&filename)?;
+	}
+
+	println!("Rendered {} frames of {}x{} to {}", opts.frames, opts.size.x, opts.size.y, opts.dir.display());
+	Ok(())
+}
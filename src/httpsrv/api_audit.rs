@@ -0,0 +1,41 @@
+use tokio::sync::Mutex;
+use std::sync::Arc;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::httpsrv::LpfHttpServerData;
+use crate::httpsrv::HTTPAPIError;
+use crate::httpsrv::Credential;
+use crate::httpsrv::audit::AuditFilter;
+use crate::lconfig::UserRole;
+
+const AUDIT_DEFAULT_LIMIT: usize = 100;
+const AUDIT_MAX_LIMIT: usize = 1000;
+
+/* Entries of the audit log, newest first, optionally filtered by event,
+ * user and time */
+pub async fn lapi_audit_get(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Admin)?;
+	let audit = d.lock().await.audit.clone();
+
+	let get_str = |k: &str| params.get(k).and_then(Value::as_str).filter(|s| !s.is_empty()).map(String::from);
+	let filter = AuditFilter {
+		event: get_str("event"),
+		user: get_str("user"),
+		since: get_str("since")
+	};
+	/* Numbers from a query string arrive as strings */
+	let limit = match params.get("limit") {
+		Some(Value::String(s)) => s.parse::<usize>().ok(),
+		Some(v) => v.as_u64().map(|l| l as usize),
+		None => Some(AUDIT_DEFAULT_LIMIT)
+	};
+	let limit = match limit {
+		Some(l) => l.min(AUDIT_MAX_LIMIT),
+		None => return Err(HTTPAPIError::BadRequest{description: String::from("limit must be a number")})
+	};
+
+	let entries = audit.query(&filter, limit).await;
+	Ok(json!({ "entries": entries }))
+}
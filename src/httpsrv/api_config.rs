@@ -17,48 +17,219 @@ use serde_json::Value;
 
 use crate::httpsrv::LpfHttpServerData;
 use crate::httpsrv::HTTPAPIError;
+use crate::httpsrv::Credential;
+use crate::httpsrv::listen::apply_listen_config;
+use crate::lconfig::{Config, UserRole};
+
+/* Credentials have their own commands */
+const PROTECTED_KEYS: &[&str] = &["users", "api_tokens"];
+
+/* Colors written the same way, the control panel sends #rrggbb for
+ * colors the configuration can have as names or in uppercase */
+fn canonical_colors(v: &mut Value) {
+	match v {
+		Value::Object(m) => for (k, v) in m.iter_mut() {
+			if !k.contains("color") {
+				canonical_colors(v);
+				continue;
+			}
+			let items: Vec<&mut Value> = match v {
+				Value::Array(a) => a.iter_mut().collect(),
+				v => vec![v]
+			};
+			for item in items {
+				if let Some(c) = item.as_str().and_then(|s| csscolorparser::parse(s).ok()) {
+					*item = Value::String(c.to_hex_string());
+				}
+			}
+		},
+		Value::Array(a) => a.iter_mut().for_each(canonical_colors),
+		_ => ()
+	}
+}
+
+/* Whether the changes leave everything but the text as it is.  The values
+ * are compared once parsed, since the control panel sends all its fields
+ * and writes numbers and colors its own way */
+fn changes_only_text(cfg: &Config, changes: &Value) -> bool {
+	if !changes.is_object() {
+		return false;
+	}
+	let mut changed = cfg.clone();
+	changed.set_partial_cfg(changes);
+	changed.disp_text = cfg.disp_text.clone();
+	changed.version = cfg.version;
+	/* Credentials cannot be changed here, set_partial_cfg adds an admin when there are none */
+	changed.users = cfg.users.clone();
+	let (mut before, mut after) = (json!(cfg), json!(changed));
+	canonical_colors(&mut before);
+	canonical_colors(&mut after);
+	before == after
+}
 
 
-pub async fn lapi_config_get(d: Arc<Mutex<LpfHttpServerData>>, _params: Value) -> Result<Value, HTTPAPIError>
+pub async fn lapi_config_get(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, _params: Value) -> Result<Value, HTTPAPIError>
 {
+	cred.require(UserRole::Viewer)?;
 	let ud = d.lock().await;
 	let rgd = ud.runtime_global_data.lock().await;
 
 	let body = json!({
+		"version": rgd.cfg.version,
 		"http_port": rgd.cfg.http_port,
+		"listen": rgd.cfg.listen,
+		"https": rgd.cfg.https,
+		"trusted_proxies": rgd.cfg.trusted_proxies,
 		"disp_text": rgd.cfg.disp_text,
+		"disp_mode": rgd.cfg.disp_mode,
 		"disp_scrollspeed": rgd.cfg.disp_scrollspeed,
+		"disp_dwell": rgd.cfg.disp_dwell,
+		"disp_typespeed": rgd.cfg.disp_typespeed,
+		"disp_blinkinterval": rgd.cfg.disp_blinkinterval,
 		"disp_textcolor": rgd.cfg.disp_textcolor,
 		"disp_backgroundcolor": rgd.cfg.disp_backgroundcolor,
 		"disp_orientation": rgd.cfg.disp_orientation,
 		"disp_hmargin": rgd.cfg.disp_hmargin,
 		"disp_vmargin": rgd.cfg.disp_vmargin,
 		"disp_fontsize": rgd.cfg.disp_fontsize,
+		"disp_autosize": rgd.cfg.disp_autosize,
+		"disp_lines": rgd.cfg.disp_lines,
+		"disp_fontfamily": rgd.cfg.disp_fontfamily,
+		"disp_fontweight": rgd.cfg.disp_fontweight,
+		"fallback_fonts": rgd.cfg.fallback_fonts,
+		"disp_textalign": rgd.cfg.disp_textalign,
+		"disp_followdirection": rgd.cfg.disp_followdirection,
+		"disp_continuous": rgd.cfg.disp_continuous,
+		"disp_separator": rgd.cfg.disp_separator,
+		"disp_spacing": rgd.cfg.disp_spacing,
+		"disp_background": rgd.cfg.disp_background,
+		"disp_gradientcolors": rgd.cfg.disp_gradientcolors,
+		"disp_gradientangle": rgd.cfg.disp_gradientangle,
+		"disp_backgroundimage": rgd.cfg.disp_backgroundimage,
+		"disp_imagescaling": rgd.cfg.disp_imagescaling,
+		"overlays": rgd.cfg.overlays,
 		"disp_fullscreen": rgd.cfg.disp_fullscreen,
+		"zones": rgd.cfg.zones,
 	});
 	Ok(body)
 }
 
-pub async fn lapi_config_set(d: Arc<Mutex<LpfHttpServerData>>, params: Value) -> Result<Value, HTTPAPIError>
+pub async fn lapi_config_set(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, params: Value) -> Result<Value, HTTPAPIError>
 {
 	let ud = d.lock().await;
 	let mut rgd = ud.runtime_global_data.lock().await;
 
 	let cfgval: &Value = match params.get("cfg") {
 		Some(v) => v,
-		None => {
-			let r = json!({ "err": "Unable to find cfg in request parameters" }).to_string();
-			return Err(HTTPAPIError::Message{description: r.to_string()})
-		}
+		None => return Err(HTTPAPIError::BadRequest{description: String::from("Unable to find cfg in request parameters")})
 	};
+	/* Editors can change the text, the other settings need an admin */
+	let only_text = changes_only_text(&rgd.cfg, cfgval);
+	cred.require(if only_text { UserRole::Editor } else { UserRole::Admin })?;
+
+	if let Some(k) = PROTECTED_KEYS.iter().find(|k| cfgval.get(k).is_some()) {
+		return Err(HTTPAPIError::BadRequest{description: format!("{} cannot be changed with config_set", k)})
+	}
+
+	/* The client can send the version it has read, to avoid overwriting
+	 * the changes made by someone else in the meantime */
+	if let Some(v) = params.get("version").and_then(Value::as_u64) {
+		if v != rgd.cfg.version {
+			return Err(HTTPAPIError::VersionMismatch{description: String::from("The configuration has been changed by someone else, reload it before saving")})
+		}
+	}
 
+	let previous = rgd.cfg.clone();
 	rgd.cfg.set_partial_cfg(cfgval);
+	let mut cfg = rgd.cfg.clone();
+	/* Switching the listeners can take time, the display and the other
+	 * requests go on meanwhile.  The other writing commands wait for
+	 * config_writes, held by the caller since cfg_snapshot() */
+	drop(rgd);
+	let listeners = ud.listeners.clone();
+	let runtime_global_data = ud.runtime_global_data.clone();
+	drop(ud);
+
+	/* Nothing is saved when the new addresses cannot be used */
+	if let Err(e) = apply_listen_config(&d, &listeners, &cfg).await {
+		runtime_global_data.lock().await.cfg = previous;
+		return Err(e);
+	}
 
-	rgd.cfg.save().await ?;
+	cfg.save().await ?;
 
 
 	let body = json!({
-		"risultato": 0
+		"risultato": 0,
+		"version": cfg.version
 	});
 	Ok(body)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/* What the control panel sends when saving, from the values it has read */
+	fn panel_payload(cfg: &Config, text: &str) -> Value {
+		json!({
+			"disp_text": text,
+			"disp_scrollspeed": cfg.disp_scrollspeed,
+			"disp_hmargin": cfg.disp_hmargin,
+			"disp_vmargin": cfg.disp_vmargin,
+			"disp_fontsize": cfg.disp_fontsize,
+			"disp_autosize": cfg.disp_autosize,
+			"disp_lines": cfg.disp_lines,
+			"disp_fontfamily": cfg.disp_fontfamily,
+			"disp_fontweight": cfg.disp_fontweight,
+			"disp_backgroundcolor": cfg.disp_backgroundcolor.to_lowercase(),
+			"disp_background": cfg.disp_background,
+			"disp_gradientcolors": cfg.disp_gradientcolors,
+			"disp_gradientangle": cfg.disp_gradientangle.round() as i64,
+			"disp_backgroundimage": cfg.disp_backgroundimage,
+			"disp_imagescaling": cfg.disp_imagescaling,
+			"disp_textcolor": "#ffffff",
+			"disp_orientation": cfg.disp_orientation,
+			"disp_mode": cfg.disp_mode,
+			"disp_dwell": 3,
+			"disp_typespeed": 15,
+			"disp_blinkinterval": 0.5,
+			"disp_continuous": cfg.disp_continuous,
+			"disp_separator": cfg.disp_separator,
+			"disp_spacing": cfg.disp_spacing,
+			"disp_textalign": cfg.disp_textalign,
+			"disp_followdirection": cfg.disp_followdirection,
+			"disp_fullscreen": cfg.disp_fullscreen
+		})
+	}
+
+	fn config() -> Config {
+		let mut cfg = Config::new();
+		cfg.disp_textcolor = String::from("White");
+		cfg.disp_backgroundcolor = String::from("#20A0FF");
+		cfg.disp_dwell = 3.0;
+		cfg.disp_typespeed = 15.0;
+		cfg.disp_blinkinterval = 0.5;
+		cfg
+	}
+
+	#[test]
+	fn panel_save_changing_the_text_needs_an_editor() {
+		let cfg = config();
+		assert!(changes_only_text(&cfg, &panel_payload(&cfg, "New text")));
+		assert!(changes_only_text(&cfg, &json!({"disp_text": "New text"})));
+	}
+
+	#[test]
+	fn panel_save_changing_settings_needs_an_admin() {
+		let cfg = config();
+		let mut payload = panel_payload(&cfg, "New text");
+		payload["disp_dwell"] = json!(4);
+		assert!(!changes_only_text(&cfg, &payload));
+		let mut payload = panel_payload(&cfg, "New text");
+		payload["disp_textcolor"] = json!("#ff0000");
+		assert!(!changes_only_text(&cfg, &payload));
+		assert!(!changes_only_text(&cfg, &json!({"http_port": cfg.http_port + 1})));
+		assert!(!changes_only_text(&cfg, &json!("New text")));
+	}
+}
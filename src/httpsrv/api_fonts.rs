@@ -0,0 +1,62 @@
+use tokio::fs;
+use tokio::sync::Mutex;
+use std::sync::Arc;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::httpsrv::LpfHttpServerData;
+use crate::httpsrv::HTTPAPIError;
+use crate::httpsrv::Credential;
+use crate::fonts::check_user_font;
+use crate::lconfig::UserRole;
+
+/* The fonts the zones can use, selected by family and weight */
+pub async fn lapi_font_list(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, _params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Viewer)?;
+	let ud = d.lock().await;
+	let rgd = ud.runtime_global_data.lock().await;
+	Ok(json!({ "fonts": rgd.fonts.fonts }))
+}
+
+/* Saves a TTF or OTF file in the fonts directory of the configuration,
+ * replacing the one with the same name */
+pub async fn font_upload(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, file_name: &str, data: &[u8]) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Admin)?;
+	let info = check_user_font(file_name, data)
+		.map_err(|e| HTTPAPIError::BadRequest{description: e.to_string()})?;
+	if let Some(path) = &info.path {
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir).await?;
+		}
+		fs::write(path, data).await?;
+	}
+
+	let ud = d.lock().await;
+	let mut rgd = ud.runtime_global_data.lock().await;
+	rgd.fonts.add_user_font(info.clone());
+	Ok(json!(info))
+}
+
+/* Only the uploaded fonts can be deleted */
+pub async fn lapi_font_delete(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Admin)?;
+	let file = match params.get("file").and_then(Value::as_str) {
+		Some(f) => f.to_string(),
+		None => return Err(HTTPAPIError::BadRequest{description: String::from("Unable to find file in request parameters")})
+	};
+
+	let ud = d.lock().await;
+	let mut rgd = ud.runtime_global_data.lock().await;
+	let path = match rgd.fonts.user_font(&file) {
+		Some(f) => f.path.clone(),
+		None => return Err(HTTPAPIError::NotFound{description: format!("No uploaded font {}", file)})
+	};
+	if let Some(p) = path {
+		fs::remove_file(p).await?;
+	}
+	rgd.fonts.remove_user_font(&file);
+	Ok(json!({ "file": file }))
+}
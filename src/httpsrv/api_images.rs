@@ -0,0 +1,57 @@
+use tokio::fs;
+use tokio::sync::Mutex;
+use std::sync::Arc;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::httpsrv::LpfHttpServerData;
+use crate::httpsrv::HTTPAPIError;
+use crate::httpsrv::Credential;
+use crate::images::check_user_image;
+use crate::lconfig::UserRole;
+
+/* The images the background and the overlays can show */
+pub async fn lapi_image_list(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, _params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Viewer)?;
+	let ud = d.lock().await;
+	let rgd = ud.runtime_global_data.lock().await;
+	Ok(json!({ "images": rgd.images.images }))
+}
+
+/* Saves a PNG, JPEG, GIF, BMP or WebP file in the images directory of the
+ * configuration, replacing the one with the same name */
+pub async fn image_upload(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, file_name: &str, data: &[u8]) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Admin)?;
+	let info = check_user_image(file_name, data)
+		.map_err(|e| HTTPAPIError::BadRequest{description: e.to_string()})?;
+	if let Some(dir) = info.path.parent() {
+		fs::create_dir_all(dir).await?;
+	}
+	fs::write(&info.path, data).await?;
+
+	let ud = d.lock().await;
+	let mut rgd = ud.runtime_global_data.lock().await;
+	rgd.images.add(info.clone());
+	Ok(json!(info))
+}
+
+pub async fn lapi_image_delete(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Admin)?;
+	let file = match params.get("file").and_then(Value::as_str) {
+		Some(f) => f.to_string(),
+		None => return Err(HTTPAPIError::BadRequest{description: String::from("Unable to find file in request parameters")})
+	};
+
+	let ud = d.lock().await;
+	let mut rgd = ud.runtime_global_data.lock().await;
+	let path = match rgd.images.find(&file) {
+		Some(i) => i.path.clone(),
+		None => return Err(HTTPAPIError::NotFound{description: format!("No uploaded image {}", file)})
+	};
+	fs::remove_file(path).await?;
+	rgd.images.remove(&file);
+	Ok(json!({ "file": file }))
+}
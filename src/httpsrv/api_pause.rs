@@ -0,0 +1,35 @@
+use tokio::sync::Mutex;
+use std::sync::Arc;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::httpsrv::LpfHttpServerData;
+use crate::httpsrv::HTTPAPIError;
+use crate::httpsrv::Credential;
+use crate::lconfig::UserRole;
+
+
+async fn set_paused(d: Arc<Mutex<LpfHttpServerData>>, paused: bool) -> Result<Value, HTTPAPIError>
+{
+	let ud = d.lock().await;
+	let mut rgd = ud.runtime_global_data.lock().await;
+
+	if rgd.paused != paused {
+		rgd.paused = paused;
+		rgd.notify("pause", json!({ "paused": paused }));
+	}
+
+	Ok(json!({ "rc": 0, "paused": paused }))
+}
+
+pub async fn lapi_pause(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, _params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Editor)?;
+	set_paused(d, true).await
+}
+
+pub async fn lapi_resume(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, _params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Editor)?;
+	set_paused(d, false).await
+}
@@ -0,0 +1,77 @@
+use tokio::sync::Mutex;
+use std::sync::Arc;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::httpsrv::LpfHttpServerData;
+use crate::httpsrv::HTTPAPIError;
+use crate::httpsrv::Credential;
+use crate::lconfig::{PlaylistRequest, UserRole};
+
+
+pub async fn lapi_playlist_get(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, _params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Viewer)?;
+	let ud = d.lock().await;
+	let rgd = ud.runtime_global_data.lock().await;
+
+	let body = json!({
+		"playlist": rgd.cfg.playlist,
+		"current": rgd.playlist_current
+	});
+	Ok(body)
+}
+
+pub async fn lapi_playlist_set(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Editor)?;
+	let ud = d.lock().await;
+	let mut rgd = ud.runtime_global_data.lock().await;
+
+	let playlist: &Value = match params.get("playlist") {
+		Some(v) if v.is_object() => v,
+		_ => return Err(HTTPAPIError::BadRequest{description: String::from("Unable to find playlist object in request parameters")})
+	};
+
+	rgd.cfg.set_partial_cfg(&json!({ "playlist": playlist }));
+
+	rgd.cfg.save().await ?;
+
+	let body = json!({
+		"rc": 0,
+		"items": rgd.cfg.playlist.items.len()
+	});
+	Ok(body)
+}
+
+pub async fn lapi_playlist_next(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, _params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Editor)?;
+	let ud = d.lock().await;
+	let mut rgd = ud.runtime_global_data.lock().await;
+
+	if rgd.cfg.playlist.items.is_empty() {
+		return Err(HTTPAPIError::NotFound{description: String::from("The playlist is empty")});
+	}
+	rgd.playlist_request = Some(PlaylistRequest::Next);
+
+	Ok(json!({ "rc": 0 }))
+}
+
+pub async fn lapi_playlist_jump(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Editor)?;
+	let ud = d.lock().await;
+	let mut rgd = ud.runtime_global_data.lock().await;
+
+	let index = match params.get("index").and_then(Value::as_u64) {
+		Some(i) => i as usize,
+		None => return Err(HTTPAPIError::BadRequest{description: String::from("Unable to find index in request parameters")})
+	};
+	if index >= rgd.cfg.playlist.items.len() {
+		return Err(HTTPAPIError::NotFound{description: format!("Playlist item {} does not exist", index)});
+	}
+	rgd.playlist_request = Some(PlaylistRequest::Jump(index));
+
+	Ok(json!({ "rc": 0 }))
+}
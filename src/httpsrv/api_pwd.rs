@@ -7,57 +7,59 @@ use pwhash::bcrypt;
 
 use crate::httpsrv::LpfHttpServerData;
 use crate::httpsrv::HTTPAPIError;
+use crate::httpsrv::Credential;
+use crate::httpsrv::revoke_user_sessions;
 
 
-pub async fn lapi_password_change(d: Arc<Mutex<LpfHttpServerData>>, params: Value) -> Result<Value, HTTPAPIError>
+/* Changes the password of the user making the request */
+pub async fn lapi_password_change(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, params: Value) -> Result<Value, HTTPAPIError>
 {
-	let ud = d.lock().await;
-	let mut rgd = ud.runtime_global_data.lock().await;
+	let username = match cred.username() {
+		Some(u) => u.to_string(),
+		None => return Err(HTTPAPIError::Forbidden{description: String::from("API tokens have no password")})
+	};
+	let mut ud = d.lock().await;
+	let rgd_arc = ud.runtime_global_data.clone();
+	let mut rgd = rgd_arc.lock().await;
 
 	let oldpassv: &Value = match params.get("oldpass") {
 		Some(v) => v,
-		None => {
-			let r = json!({ "err": "Unable to find oldpass in request parameters" });
-			return Err(HTTPAPIError::Message{description: r.to_string()})
-		}
+		None => return Err(HTTPAPIError::BadRequest{description: String::from("Unable to find oldpass in request parameters")})
 	};
 
 	let newpassv: &Value = match params.get("newpass") {
 		Some(v) => v,
-		None => {
-			let r = json!({ "err": "Unable to find newpass in request parameters" });
-			return Err(HTTPAPIError::Message{description: r.to_string()})
-		}
-	};
-
-	let oldpass= match oldpassv.as_str() {
-		Some(s) => s,
-		None => ""
+		None => return Err(HTTPAPIError::BadRequest{description: String::from("Unable to find newpass in request parameters")})
 	};
 
+	let oldpass = oldpassv.as_str().unwrap_or_default();
+	let newpass = newpassv.as_str().unwrap_or_default();
 
-	let newpass= match newpassv.as_str() {
-		Some(s) => s,
-		None => ""
+	let user = match rgd.cfg.users.iter_mut().find(|u| u.username == username) {
+		Some(u) => u,
+		None => return Err(HTTPAPIError::NotFound{description: format!("User {} does not exist", username)})
 	};
 
-	if !bcrypt::verify(oldpass, &rgd.cfg.encrypted_admin_password) {
-		let r = json!({ "rc": 1, "description": "Old password is invalid"});
-		return Ok(r);
+	if !bcrypt::verify(oldpass, &user.encrypted_password) {
+		return Err(HTTPAPIError::Forbidden{description: String::from("Old password is invalid")});
 	}
 
 	let encrypted_password = match bcrypt::hash(newpass) {
 		Ok(s) => s,
-		Err(_e) => String::from("")
+		Err(e) => return Err(HTTPAPIError::Internal{description: format!("Unable to encrypt the new password: {}", e)})
 	};
 
 
-	println!("Encrypted new password is {}", encrypted_password);
+	println!("Changed the password of user {}", username);
 
-	rgd.cfg.encrypted_admin_password = encrypted_password;
+	user.encrypted_password = encrypted_password;
 
 	rgd.cfg.save().await ?;
 
+	/* The other sessions may have been opened by whoever knew the old one */
+	let n = revoke_user_sessions(&mut ud, &username, cred.session_id());
+	println!("Closed {} other sessions of {}", n, username);
+
 	let body = json!({
 		"rc": 0,
 		"description": "Password changed"
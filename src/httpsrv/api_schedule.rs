@@ -0,0 +1,82 @@
+use tokio::sync::Mutex;
+use std::sync::Arc;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::httpsrv::LpfHttpServerData;
+use crate::httpsrv::HTTPAPIError;
+use crate::httpsrv::Credential;
+use crate::lconfig::{ScheduleRule, UserRole};
+use crate::schedule;
+
+
+pub async fn lapi_schedule_get(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, _params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Viewer)?;
+	let ud = d.lock().await;
+	let rgd = ud.runtime_global_data.lock().await;
+
+	let body = json!({
+		"schedule": rgd.cfg.schedule
+	});
+	Ok(body)
+}
+
+pub async fn lapi_schedule_set(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Admin)?;
+	let ud = d.lock().await;
+	let mut rgd = ud.runtime_global_data.lock().await;
+
+	let rules: &Vec<Value> = match params.get("schedule").and_then(Value::as_array) {
+		Some(v) => v,
+		None => return Err(HTTPAPIError::BadRequest{description: String::from("Unable to find schedule array in request parameters")})
+	};
+
+	/* Refuse the whole schedule if a rule is invalid */
+	for (i, rv) in rules.iter().enumerate() {
+		let mut r = ScheduleRule::new();
+		r.set_partial(rv);
+		if let Err(e) = schedule::validate_rule(&r) {
+			return Err(HTTPAPIError::BadRequest{description: format!("Schedule rule {}: {}", i, e)});
+		}
+	}
+
+	rgd.cfg.set_partial_cfg(&json!({ "schedule": rules }));
+
+	rgd.cfg.save().await ?;
+
+	let body = json!({
+		"rc": 0,
+		"rules": rgd.cfg.schedule.len()
+	});
+	Ok(body)
+}
+
+/* What is showing now, and which rule decided it */
+pub async fn lapi_schedule_now(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, _params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Viewer)?;
+	let ud = d.lock().await;
+	let rgd = ud.runtime_global_data.lock().await;
+
+	let now = chrono::Local::now().naive_local();
+	let rule = schedule::active_rule(&rgd.cfg, now);
+	let scfg = schedule::scheduled_config(&rgd.cfg, rule);
+
+	let reason = match rule.and_then(|r| rgd.cfg.schedule.get(r)) {
+		Some(r) => format!("Schedule rule \"{}\" is active: {}", r.name, schedule::describe_rule(r)),
+		None => String::from("No schedule rule is active, showing the default content")
+	};
+
+	let body = json!({
+		"now": now.format("%Y-%m-%d %H:%M:%S").to_string(),
+		"rule": rule,
+		"rule_name": rule.and_then(|r| rgd.cfg.schedule.get(r)).map(|r| r.name.clone()),
+		"reason": reason,
+		"disp_text": scfg.disp_text,
+		"zones": scfg.effective_zones().iter().map(|z| json!({ "name": z.name, "text": z.text })).collect::<Vec<Value>>(),
+		"playlist_items": scfg.playlist.items.len()
+	});
+	Ok(body)
+}
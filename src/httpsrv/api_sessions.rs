@@ -0,0 +1,64 @@
+/* Lists and closes the browser sessions.  Users see their own sessions,
+ * admins all of them */
+
+use tokio::sync::Mutex;
+use std::sync::Arc;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::httpsrv::{LpfHttpServerData, BrowserSession};
+use crate::httpsrv::HTTPAPIError;
+use crate::httpsrv::Credential;
+use crate::lconfig::UserRole;
+
+fn can_see(cred: &Credential, ses: &BrowserSession) -> bool {
+	cred.role() == UserRole::Admin || cred.username() == Some(ses.username.as_str())
+}
+
+pub async fn lapi_session_list(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, _params: Value) -> Result<Value, HTTPAPIError>
+{
+	let ud = d.lock().await;
+
+	let current = match cred {
+		Credential::Session { sid, .. } => ud.authenticated_sessions.get(sid).map(|s| s.id.clone()),
+		Credential::Token { .. } => None
+	};
+	let mut sessions: Vec<&BrowserSession> = ud.authenticated_sessions.values()
+		.filter(|s| !s.is_expired() && can_see(cred, s))
+		.collect();
+	sessions.sort_by_key(|s| s.start_time);
+
+	let body = json!({
+		"sessions": sessions.iter().map(|s| json!({
+			"id": s.id,
+			"username": s.username,
+			"remote_addr": s.remote_addr.ip().to_string(),
+			"age_sec": s.start_time.elapsed().as_secs(),
+			"idle_sec": s.last_seen.elapsed().as_secs(),
+			"current": current.as_ref() == Some(&s.id)
+		})).collect::<Vec<Value>>()
+	});
+	Ok(body)
+}
+
+pub async fn lapi_session_revoke(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, params: Value) -> Result<Value, HTTPAPIError>
+{
+	let mut ud = d.lock().await;
+
+	let id = match params.get("id").and_then(Value::as_str) {
+		Some(i) => i.to_string(),
+		None => return Err(HTTPAPIError::BadRequest{description: String::from("Unable to find id in request parameters")})
+	};
+	/* The sessions of the others do not exist for the non admins */
+	let sid = match ud.authenticated_sessions.iter().find(|(_, s)| s.id == id && can_see(cred, s)) {
+		Some((sid, _)) => sid.clone(),
+		None => return Err(HTTPAPIError::NotFound{description: format!("Session {} does not exist", id)})
+	};
+	if let Some(s) = ud.authenticated_sessions.remove(&sid) {
+		let audit = ud.audit.clone();
+		drop(ud);
+		audit.record("session_revoke", &cred.describe(), None, json!({ "session": s.id, "username": s.username })).await;
+	}
+
+	Ok(json!({ "id": id }))
+}
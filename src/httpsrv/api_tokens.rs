@@ -0,0 +1,112 @@
+/* Bearer tokens for scripts and other non interactive clients.  The token
+ * is shown once when created, the configuration keeps only its hash. */
+
+use tokio::sync::Mutex;
+use std::sync::Arc;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::httpsrv::{LpfHttpServerData, random_hex};
+use crate::httpsrv::HTTPAPIError;
+use crate::httpsrv::Credential;
+use crate::lconfig::{ApiToken, Config, TokenScope, UserRole};
+
+const TOKEN_PREFIX: &str = "sts_";
+const TOKEN_BYTES: usize = 32;
+const TOKEN_ID_BYTES: usize = 6;
+
+/* The tokens are long random strings, a plain hash is enough to protect
+ * them, and it is fast enough to be checked on every request */
+fn hash_token(token: &str) -> String {
+	Sha256::digest(token.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn find_token<'a>(cfg: &'a Config, token: &str) -> Option<&'a ApiToken> {
+	if !token.starts_with(TOKEN_PREFIX) {
+		return None;
+	}
+	let h = hash_token(token);
+	cfg.api_tokens.iter().find(|t| t.token_hash == h)
+}
+
+fn describe_token(t: &ApiToken) -> Value {
+	json!({
+		"id": t.id,
+		"name": t.name,
+		"scope": t.scope,
+		"created": t.created
+	})
+}
+
+pub async fn lapi_token_list(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, _params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Admin)?;
+	let ud = d.lock().await;
+	let rgd = ud.runtime_global_data.lock().await;
+
+	let body = json!({
+		"tokens": rgd.cfg.api_tokens.iter().map(describe_token).collect::<Vec<Value>>()
+	});
+	Ok(body)
+}
+
+pub async fn lapi_token_create(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Admin)?;
+	let ud = d.lock().await;
+	let mut rgd = ud.runtime_global_data.lock().await;
+
+	let name = match params.get("name").and_then(Value::as_str) {
+		Some(n) if !n.trim().is_empty() => n.trim().to_string(),
+		_ => return Err(HTTPAPIError::BadRequest{description: String::from("Unable to find name in request parameters")})
+	};
+	let scope = match params.get("scope") {
+		Some(v) => match TokenScope::deserialize(v) {
+			Ok(s) => s,
+			Err(_) => return Err(HTTPAPIError::BadRequest{description: format!("Invalid scope {}, expected Full, ReadOnly or TextOnly", v)})
+		},
+		None => TokenScope::Full
+	};
+
+	let token = format!("{}{}", TOKEN_PREFIX, random_hex(TOKEN_BYTES));
+	let t = ApiToken {
+		id: random_hex(TOKEN_ID_BYTES),
+		name,
+		scope,
+		created: chrono::Utc::now().to_rfc3339(),
+		token_hash: hash_token(&token)
+	};
+	println!("Created API token {} ({})", t.id, t.name);
+
+	let mut body = describe_token(&t);
+	body["token"] = json!(token);
+
+	rgd.cfg.api_tokens.push(t);
+	rgd.cfg.save().await ?;
+
+	Ok(body)
+}
+
+pub async fn lapi_token_revoke(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Admin)?;
+	let ud = d.lock().await;
+	let mut rgd = ud.runtime_global_data.lock().await;
+
+	let id = match params.get("id").and_then(Value::as_str) {
+		Some(i) => i.to_string(),
+		None => return Err(HTTPAPIError::BadRequest{description: String::from("Unable to find id in request parameters")})
+	};
+	let idx = match rgd.cfg.api_tokens.iter().position(|t| t.id == id) {
+		Some(i) => i,
+		None => return Err(HTTPAPIError::NotFound{description: format!("API token {} does not exist", id)})
+	};
+	let t = rgd.cfg.api_tokens.remove(idx);
+	println!("Revoked API token {} ({})", t.id, t.name);
+
+	rgd.cfg.save().await ?;
+
+	Ok(json!({ "id": id }))
+}
@@ -0,0 +1,153 @@
+/* User accounts, managed by the admins */
+
+use tokio::sync::Mutex;
+use std::sync::Arc;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use pwhash::bcrypt;
+
+use crate::httpsrv::LpfHttpServerData;
+use crate::httpsrv::HTTPAPIError;
+use crate::httpsrv::Credential;
+use crate::httpsrv::revoke_user_sessions;
+use crate::lconfig::{Config, UserAccount, UserRole};
+
+fn describe_user(u: &UserAccount) -> Value {
+	json!({
+		"username": u.username,
+		"role": u.role
+	})
+}
+
+fn get_username(params: &Value) -> Result<String, HTTPAPIError> {
+	match params.get("username").and_then(Value::as_str) {
+		Some(u) if !u.is_empty() && !u.chars().any(|c| c.is_whitespace() || c.is_control()) => Ok(u.to_string()),
+		Some(u) => Err(HTTPAPIError::BadRequest{description: format!("Invalid username \"{}\"", u)}),
+		None => Err(HTTPAPIError::BadRequest{description: String::from("Unable to find username in request parameters")})
+	}
+}
+
+/* There must always be someone able to manage the users */
+fn check_admin_left(cfg: &Config, username: &str) -> Result<(), HTTPAPIError> {
+	if !cfg.users.iter().any(|u| u.role == UserRole::Admin && u.username != username) {
+		return Err(HTTPAPIError::BadRequest{description: String::from("The last admin cannot be removed")});
+	}
+	Ok(())
+}
+
+/* Who is making the request, so the UI can show only what they can do */
+pub async fn lapi_whoami(_d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, _params: Value) -> Result<Value, HTTPAPIError>
+{
+	Ok(json!({
+		"username": cred.username(),
+		"role": cred.role()
+	}))
+}
+
+pub async fn lapi_user_list(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, _params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Admin)?;
+	let ud = d.lock().await;
+	let rgd = ud.runtime_global_data.lock().await;
+
+	let body = json!({
+		"users": rgd.cfg.users.iter().map(describe_user).collect::<Vec<Value>>()
+	});
+	Ok(body)
+}
+
+/* Creates the user, or changes the role and password of an existing one */
+pub async fn lapi_user_set(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Admin)?;
+	let mut ud = d.lock().await;
+	let rgd_arc = ud.runtime_global_data.clone();
+	let mut rgd = rgd_arc.lock().await;
+
+	let username = get_username(&params)?;
+	let role = match params.get("role") {
+		Some(v) => match UserRole::deserialize(v) {
+			Ok(r) => Some(r),
+			Err(_) => return Err(HTTPAPIError::BadRequest{description: format!("Invalid role {}, expected Viewer, Editor or Admin", v)})
+		},
+		None => None
+	};
+	let encrypted_password = match params.get("password").and_then(Value::as_str) {
+		Some("") => return Err(HTTPAPIError::BadRequest{description: String::from("The password cannot be empty")}),
+		Some(p) => match bcrypt::hash(p) {
+			Ok(s) => Some(s),
+			Err(e) => return Err(HTTPAPIError::Internal{description: format!("Unable to encrypt the password: {}", e)})
+		},
+		None => None
+	};
+
+	if role.is_some_and(|r| r != UserRole::Admin) {
+		check_admin_left(&rgd.cfg, &username)?;
+	}
+
+	/* Whoever knew the old password, or had the old role, logs in again */
+	let mut revoke = false;
+	match rgd.cfg.users.iter_mut().find(|u| u.username == username) {
+		Some(u) => {
+			if let Some(r) = role {
+				revoke |= r < u.role;
+				u.role = r;
+			}
+			if let Some(p) = encrypted_password {
+				revoke = true;
+				u.encrypted_password = p;
+			}
+			println!("Changed user {}", username);
+		},
+		None => {
+			let encrypted_password = match encrypted_password {
+				Some(p) => p,
+				None => return Err(HTTPAPIError::BadRequest{description: String::from("A password is needed to create a user")})
+			};
+			rgd.cfg.users.push(UserAccount {
+				username: username.clone(),
+				role: role.unwrap_or(UserRole::Viewer),
+				encrypted_password
+			});
+			println!("Created user {}", username);
+		}
+	}
+
+	rgd.cfg.save().await ?;
+
+	if revoke {
+		let n = revoke_user_sessions(&mut ud, &username, cred.session_id());
+		println!("Closed {} sessions of {}", n, username);
+	}
+
+	let body = rgd.cfg.find_user(&username).map(describe_user).unwrap_or_default();
+	Ok(body)
+}
+
+pub async fn lapi_user_delete(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, params: Value) -> Result<Value, HTTPAPIError>
+{
+	cred.require(UserRole::Admin)?;
+	let mut ud = d.lock().await;
+	let rgd_arc = ud.runtime_global_data.clone();
+	let mut rgd = rgd_arc.lock().await;
+
+	let username = get_username(&params)?;
+	if cred.username() == Some(username.as_str()) {
+		return Err(HTTPAPIError::BadRequest{description: String::from("You cannot delete your own account")});
+	}
+	let idx = match rgd.cfg.users.iter().position(|u| u.username == username) {
+		Some(i) => i,
+		None => return Err(HTTPAPIError::NotFound{description: format!("User {} does not exist", username)})
+	};
+	check_admin_left(&rgd.cfg, &username)?;
+
+	rgd.cfg.users.remove(idx);
+	rgd.cfg.save().await ?;
+
+	/* Log them out everywhere */
+	revoke_user_sessions(&mut ud, &username, None);
+	println!("Deleted user {}", username);
+
+	Ok(json!({ "username": username }))
+}
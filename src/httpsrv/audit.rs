@@ -0,0 +1,113 @@
+/* Persistent record of the logins and of the changes, one JSON object per
+ * line.  When the file grows too big it is renamed to audit.log.1, the
+ * older ones shift to .2, .3 ... and the oldest is removed. */
+
+use std::net::IpAddr;
+use std::path::PathBuf;
+use serde_json::{json, Value};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+pub const AUDIT_FILE_NAME: &str = "audit.log";
+const AUDIT_MAX_SIZE: u64 = 1024 * 1024;
+/* Rotated files kept besides the current one */
+const AUDIT_KEEP_FILES: usize = 5;
+
+/* Conditions of a query, empty ones match everything */
+#[derive(Default)]
+pub struct AuditFilter {
+	pub event: Option<String>,
+	pub user: Option<String>,
+	/* Date or RFC 3339 time of the oldest entry */
+	pub since: Option<String>
+}
+
+impl AuditFilter {
+	fn matches(&self, entry: &Value) -> bool {
+		let field = |k: &str| entry.get(k).and_then(Value::as_str).unwrap_or_default();
+		self.event.as_ref().is_none_or(|e| field("event") == e) &&
+			self.user.as_ref().is_none_or(|u| field("user") == u) &&
+			self.since.as_ref().is_none_or(|s| field("time") >= s.as_str())
+	}
+}
+
+pub struct AuditLog {
+	path: PathBuf,
+	/* One writer at a time, so the rotation does not lose lines */
+	write_lock: Mutex<()>
+}
+
+impl AuditLog {
+	pub fn new(path: PathBuf) -> AuditLog {
+		AuditLog { path, write_lock: Mutex::new(()) }
+	}
+
+	/* n = 0 is the current file */
+	fn file_path(&self, n: usize) -> PathBuf {
+		if n == 0 {
+			self.path.clone()
+		} else {
+			let mut p = self.path.clone().into_os_string();
+			p.push(format!(".{}", n));
+			PathBuf::from(p)
+		}
+	}
+
+	async fn rotate(&self) -> std::io::Result<()> {
+		let _ = fs::remove_file(self.file_path(AUDIT_KEEP_FILES)).await;
+		for n in (0..AUDIT_KEEP_FILES).rev() {
+			let from = self.file_path(n);
+			if fs::metadata(&from).await.is_ok() {
+				fs::rename(&from, self.file_path(n + 1)).await?;
+			}
+		}
+		Ok(())
+	}
+
+	async fn append(&self, line: &str) -> std::io::Result<()> {
+		let _guard = self.write_lock.lock().await;
+		if fs::metadata(&self.path).await.is_ok_and(|m| m.len() > AUDIT_MAX_SIZE) {
+			self.rotate().await?;
+		}
+		let mut f = fs::OpenOptions::new().create(true).append(true).open(&self.path).await?;
+		f.write_all(line.as_bytes()).await?;
+		f.write_all(b"\n").await
+	}
+
+	pub async fn record(&self, event: &str, user: &str, remote_addr: Option<IpAddr>, details: Value) {
+		let entry = json!({
+			"time": chrono::Utc::now().to_rfc3339(),
+			"event": event,
+			"user": user,
+			"remote_addr": remote_addr.map(|a| a.to_string()),
+			"details": details
+		});
+		println!("Audit: {}", entry);
+		if let Err(e) = self.append(&entry.to_string()).await {
+			eprintln!("Unable to write the audit log {}: {}", self.path.display(), e);
+		}
+	}
+
+	/* The last limit entries matching filter, newest first */
+	pub async fn query(&self, filter: &AuditFilter, limit: usize) -> Vec<Value> {
+		let mut entries = Vec::new();
+		for n in 0..=AUDIT_KEEP_FILES {
+			let contents = match fs::read_to_string(self.file_path(n)).await {
+				Ok(c) => c,
+				Err(_) => continue
+			};
+			for line in contents.lines().rev() {
+				if entries.len() >= limit {
+					return entries;
+				}
+				if let Ok(entry) = serde_json::from_str::<Value>(line) {
+					if filter.matches(&entry) {
+						entries.push(entry);
+					}
+				}
+			}
+		}
+		entries
+	}
+}
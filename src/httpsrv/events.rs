@@ -0,0 +1,82 @@
+/* Server-Sent Events stream: pushes configuration, pause and playlist
+ * changes to the authenticated clients, so every control panel stays in
+ * sync without polling config_get. */
+
+use hyper::{Body, Request, Response, StatusCode};
+use hyper::body::Bytes;
+use hyper::header::HeaderValue;
+use serde_json::json;
+use tokio::sync::Mutex;
+use tokio::sync::broadcast::error::RecvError;
+use std::sync::Arc;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::httpsrv::{LpfHttpServerData, authenticate, is_credential_valid};
+use crate::lconfig::ServerEvent;
+
+/* Comment lines sent periodically, to keep proxies from closing the
+ * connection and to notice expired sessions */
+const KEEPALIVE_SEC: u64 = 15;
+
+fn format_event(ev: &ServerEvent) -> Bytes {
+	Bytes::from(format!("event: {}\ndata: {}\n\n", ev.name, ev.data))
+}
+
+pub async fn serve_events(d: Arc<Mutex<LpfHttpServerData>>, req: Request<Body>, response: &mut Response<Body>, client_addr: SocketAddr) {
+	let cred = match authenticate(&d, &req, client_addr).await {
+		Some(c) if c.allows(true, false) => c,
+		_ => {
+			let r = json!({ "auth": "not authenticated or session expired" }).to_string();
+			response.headers_mut().insert("Content-type", HeaderValue::from_static("application/json"));
+			*response.status_mut() = StatusCode::UNAUTHORIZED;
+			*response.body_mut() = Body::from(r);
+			return
+		}
+	};
+
+	let ud = d.lock().await;
+	let rgd = ud.runtime_global_data.lock().await;
+	let mut rx = rgd.events.subscribe();
+	let hello = ServerEvent { name: "state", data: rgd.state() };
+	drop(rgd);
+	drop(ud);
+
+	let (mut tx, body) = Body::channel();
+	response.headers_mut().insert("Content-type", HeaderValue::from_static("text/event-stream"));
+	response.headers_mut().insert("Cache-Control", HeaderValue::from_static("no-cache"));
+	*response.body_mut() = body;
+
+	println!("{} subscribed to the event stream", client_addr.ip());
+
+	tokio::spawn(async move {
+		if tx.send_data(format_event(&hello)).await.is_err() {
+			return;
+		}
+		let mut keepalive = tokio::time::interval(Duration::from_secs(KEEPALIVE_SEC));
+		loop {
+			let chunk = tokio::select! {
+				ev = rx.recv() => match ev {
+					Ok(e) => format_event(&e),
+					/* Some events were lost: let the client read the whole state again */
+					Err(RecvError::Lagged(_)) => {
+						let ud = d.lock().await;
+						let state = ud.runtime_global_data.lock().await.state();
+						format_event(&ServerEvent { name: "state", data: state })
+					},
+					Err(RecvError::Closed) => break
+				},
+				_ = keepalive.tick() => {
+					if !is_credential_valid(&d, &cred).await {
+						break;
+					}
+					Bytes::from_static(b": keepalive\n\n")
+				}
+			};
+			if tx.send_data(chunk).await.is_err() {
+				break;
+			}
+		}
+		println!("{} left the event stream", client_addr.ip());
+	});
+}
@@ -0,0 +1,344 @@
+/* The sockets the web interface listens on.  Every listen address of the
+ * configuration runs its own hyper server, so that the addresses can be
+ * added and removed while the others keep serving */
+
+use std::convert::Infallible;
+use std::fmt;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+#[cfg(unix)]
+use std::task::Poll;
+
+use hyper::{Body, Request, Response, Server};
+use hyper::server::accept::{self, Accept};
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use hyper::service::{make_service_fn, service_fn};
+use socket2::{Domain, Socket, Type};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tokio_rustls::server::TlsStream;
+
+use crate::httpsrv::{servicefn, LpfHttpServerData, HTTPAPIError};
+use crate::httpsrv::tls::{self, TlsConnection};
+use crate::lconfig::{Config, HttpsConfig};
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+#[derive(Clone, PartialEq)]
+pub enum ListenAddr {
+	Tcp(SocketAddr),
+	Unix(PathBuf)
+}
+
+impl ListenAddr {
+	/* An address of Config.listen, default_port is used when it has none */
+	pub fn parse(s: &str, default_port: u16) -> Result<ListenAddr, String> {
+		let s = s.trim();
+		if let Some(path) = s.strip_prefix("unix:") {
+			if path.is_empty() {
+				return Err(String::from("unix: needs the path of the socket"));
+			}
+			return Ok(ListenAddr::Unix(PathBuf::from(path)));
+		}
+		if let Ok(a) = s.parse::<SocketAddr>() {
+			return Ok(ListenAddr::Tcp(a));
+		}
+		let ip = s.strip_prefix('[').and_then(|v| v.strip_suffix(']')).unwrap_or(s);
+		match ip.parse::<IpAddr>() {
+			Ok(ip) => Ok(ListenAddr::Tcp(SocketAddr::new(ip, default_port))),
+			Err(_) => Err(format!("Invalid listen address {}", s))
+		}
+	}
+}
+
+impl fmt::Display for ListenAddr {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ListenAddr::Tcp(a) => write!(f, "{}", a),
+			ListenAddr::Unix(p) => write!(f, "unix:{}", p.display())
+		}
+	}
+}
+
+#[derive(Clone, PartialEq)]
+enum ListenerKind {
+	Http,
+	Https(HttpsConfig),
+	/* Sends the browsers to the HTTPS listener on this port */
+	Redirect(u16)
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ListenerSpec {
+	addr: ListenAddr,
+	kind: ListenerKind
+}
+
+impl fmt::Display for ListenerSpec {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.kind {
+			ListenerKind::Http => write!(f, "HTTP on {}", self.addr),
+			ListenerKind::Https(_) => write!(f, "HTTPS on {}", self.addr),
+			ListenerKind::Redirect(_) => write!(f, "HTTP to HTTPS redirect on {}", self.addr)
+		}
+	}
+}
+
+/* The listeners described by the configuration.  The Unix sockets are
+ * always plain HTTP */
+pub fn listener_specs(cfg: &Config) -> Result<Vec<ListenerSpec>, String> {
+	let mut specs: Vec<ListenerSpec> = Vec::new();
+	for s in &cfg.listen {
+		let addr = ListenAddr::parse(s, cfg.http_port)?;
+		let kind = match addr {
+			ListenAddr::Tcp(a) if cfg.https.enabled => {
+				if cfg.https.redirect_port != 0 {
+					let redirect = ListenerSpec {
+						addr: ListenAddr::Tcp(SocketAddr::new(a.ip(), cfg.https.redirect_port)),
+						kind: ListenerKind::Redirect(a.port())
+					};
+					if !specs.contains(&redirect) {
+						specs.push(redirect);
+					}
+				}
+				ListenerKind::Https(cfg.https.clone())
+			}
+			_ => ListenerKind::Http
+		};
+		let spec = ListenerSpec { addr, kind };
+		if !specs.contains(&spec) {
+			specs.push(spec);
+		}
+	}
+	Ok(specs)
+}
+
+struct RunningListener {
+	spec: ListenerSpec,
+	shutdown: oneshot::Sender<()>,
+	/* Fires when the server has closed the socket */
+	closed: oneshot::Receiver<()>,
+	/* Accepts the TLS connections */
+	accept_task: Option<JoinHandle<()>>
+}
+
+#[derive(Default)]
+pub struct Listeners {
+	running: Vec<RunningListener>
+}
+
+/* Unix socket clients are on this computer */
+fn local_client() -> SocketAddr {
+	SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)
+}
+
+/* Boxed, since config_set can start the listeners serving servicefn() */
+type ResponseFuture = Pin<Box<dyn Future<Output = Result<Response<Body>, Infallible>> + Send>>;
+
+fn handle(d: Arc<Mutex<LpfHttpServerData>>, mut req: Request<Body>, client_addr: SocketAddr, tls: bool) -> ResponseFuture {
+	if tls {
+		req.extensions_mut().insert(TlsConnection);
+	}
+	Box::pin(async move {
+		Ok(servicefn(d, req, client_addr).await)
+	})
+}
+
+fn bind_tcp(addr: SocketAddr) -> std::io::Result<TcpListener> {
+	let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+	/* Otherwise "::" also takes the IPv4 port, and "0.0.0.0" cannot be
+	 * listed too */
+	if addr.is_ipv6() {
+		socket.set_only_v6(true)?;
+	}
+	#[cfg(unix)]
+	socket.set_reuse_address(true)?;
+	socket.set_nonblocking(true)?;
+	socket.bind(&addr.into())?;
+	socket.listen(LISTEN_BACKLOG)?;
+	TcpListener::from_std(socket.into())
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> std::io::Result<UnixListener> {
+	use std::os::unix::fs::FileTypeExt;
+	/* Left behind by a previous run */
+	if std::fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+		std::fs::remove_file(path)?;
+	}
+	UnixListener::bind(path)
+}
+
+/* Accepts from incoming, and drops closed together with it: the socket is
+ * closed when the receiver of closed wakes up */
+fn guarded<A>(mut incoming: A, closed: oneshot::Sender<()>) -> impl Accept<Conn = A::Conn, Error = A::Error>
+	where A: Accept + Unpin
+{
+	accept::poll_fn(move |cx| {
+		let _closed = &closed;
+		Pin::new(&mut incoming).poll_accept(cx)
+	})
+}
+
+fn spawn_server<F>(server: F, spec: &ListenerSpec)
+	where F: Future<Output = Result<(), hyper::Error>> + Send + 'static
+{
+	let name = spec.to_string();
+	tokio::spawn(async move {
+		if let Err(e) = server.await {
+			eprintln!("{}: {}", name, e);
+		}
+	});
+}
+
+async fn start(d: &Arc<Mutex<LpfHttpServerData>>, spec: &ListenerSpec) -> Result<RunningListener, String> {
+	let fail = |e: std::io::Error| format!("Unable to start {}: {}", spec, e);
+	let (tx, rx) = oneshot::channel::<()>();
+	let shutdown = async {
+		let _ = rx.await;
+	};
+	let (closed_tx, closed) = oneshot::channel::<()>();
+	let d = d.clone();
+	let mut accept_task = None;
+
+	match (&spec.addr, &spec.kind) {
+		#[cfg(unix)]
+		(ListenAddr::Unix(path), _) => {
+			let listener = bind_unix(path).map_err(fail)?;
+			let incoming = accept::poll_fn(move |cx| match listener.poll_accept(cx) {
+				Poll::Ready(r) => Poll::Ready(Some(r.map(|(s, _)| s))),
+				Poll::Pending => Poll::Pending
+			});
+			let make_svc = make_service_fn(move |_: &UnixStream| {
+				let d = d.clone();
+				async move {
+					Ok::<_, Infallible>(service_fn(move |req| handle(d.clone(), req, local_client(), false)))
+				}
+			});
+			spawn_server(Server::builder(guarded(incoming, closed_tx)).serve(make_svc).with_graceful_shutdown(shutdown), spec);
+		}
+		#[cfg(not(unix))]
+		(ListenAddr::Unix(_), _) => {
+			return Err(format!("Unable to start {}: Unix sockets are not supported on this system", spec));
+		}
+		(ListenAddr::Tcp(addr), ListenerKind::Http) => {
+			let incoming = AddrIncoming::from_listener(bind_tcp(*addr).map_err(fail)?).map_err(|e| fail(std::io::Error::other(e)))?;
+			let make_svc = make_service_fn(move |conn: &AddrStream| {
+				let client_addr = conn.remote_addr();
+				let d = d.clone();
+				async move {
+					Ok::<_, Infallible>(service_fn(move |req| handle(d.clone(), req, client_addr, false)))
+				}
+			});
+			spawn_server(Server::builder(guarded(incoming, closed_tx)).serve(make_svc).with_graceful_shutdown(shutdown), spec);
+		}
+		(ListenAddr::Tcp(addr), ListenerKind::Https(https)) => {
+			let acceptor = tls::load_tls_acceptor(https).await.map_err(fail)?;
+			let (mut streams, task) = tls::tls_incoming(bind_tcp(*addr).map_err(fail)?, acceptor);
+			accept_task = Some(task);
+			let incoming = accept::poll_fn(move |cx| streams.poll_recv(cx).map(|s| s.map(Ok::<_, std::io::Error>)));
+			let make_svc = make_service_fn(move |conn: &TlsStream<TcpStream>| {
+				let client_addr = conn.get_ref().0.peer_addr().unwrap_or_else(|_| local_client());
+				let d = d.clone();
+				async move {
+					Ok::<_, Infallible>(service_fn(move |req| handle(d.clone(), req, client_addr, true)))
+				}
+			});
+			spawn_server(Server::builder(guarded(incoming, closed_tx)).serve(make_svc).with_graceful_shutdown(shutdown), spec);
+		}
+		(ListenAddr::Tcp(addr), ListenerKind::Redirect(https_port)) => {
+			let https_port = *https_port;
+			let incoming = AddrIncoming::from_listener(bind_tcp(*addr).map_err(fail)?).map_err(|e| fail(std::io::Error::other(e)))?;
+			let make_svc = make_service_fn(move |_: &AddrStream| async move {
+				Ok::<_, Infallible>(service_fn(move |req: Request<Body>| async move {
+					Ok::<_, Infallible>(tls::redirect_to_https(&req, https_port))
+				}))
+			});
+			spawn_server(Server::builder(guarded(incoming, closed_tx)).serve(make_svc).with_graceful_shutdown(shutdown), spec);
+		}
+	}
+
+	println!("Listening: {}", spec);
+	Ok(RunningListener { spec: spec.clone(), shutdown: tx, closed, accept_task })
+}
+
+/* Stops accepting connections and waits for the socket to be closed, the
+ * requests in progress are completed in the background */
+async fn stop(l: RunningListener) {
+	println!("Stopping: {}", l.spec);
+	let _ = l.shutdown.send(());
+	if let Some(t) = l.accept_task {
+		t.abort();
+		let _ = t.await;
+	}
+	let _ = l.closed.await;
+	if let ListenAddr::Unix(path) = &l.spec.addr {
+		let _ = std::fs::remove_file(path);
+	}
+}
+
+impl Listeners {
+	pub fn specs(&self) -> Vec<ListenerSpec> {
+		self.running.iter().map(|l| l.spec.clone()).collect()
+	}
+
+	/* Stops the listeners not in specs and starts the new ones.  When one
+	 * cannot be started the previous listeners are restored, unless there
+	 * were none */
+	pub async fn apply(&mut self, d: &Arc<Mutex<LpfHttpServerData>>, specs: &[ListenerSpec]) -> Result<(), Vec<String>> {
+		let previous = self.specs();
+		let mut errors = self.switch_to(d, specs).await;
+		if errors.is_empty() {
+			return Ok(());
+		}
+		if !previous.is_empty() {
+			println!("Restoring the previous listeners");
+			errors.extend(self.switch_to(d, &previous).await);
+		}
+		Err(errors)
+	}
+
+	/* Returns the listeners that could not be started */
+	async fn switch_to(&mut self, d: &Arc<Mutex<LpfHttpServerData>>, specs: &[ListenerSpec]) -> Vec<String> {
+		let (keep, remove): (Vec<_>, Vec<_>) = self.running.drain(..).partition(|l| specs.contains(&l.spec));
+		self.running = keep;
+		for l in remove {
+			stop(l).await;
+		}
+
+		let mut errors = Vec::new();
+		for spec in specs {
+			if self.running.iter().any(|l| &l.spec == spec) {
+				continue;
+			}
+			match start(d, spec).await {
+				Ok(l) => self.running.push(l),
+				Err(e) => {
+					eprintln!("{}", e);
+					errors.push(e);
+				}
+			}
+		}
+		errors
+	}
+}
+
+/* Moves the listeners to the addresses of cfg.  listeners is the one of
+ * d, passed apart so that d is not locked while they are switched: the
+ * requests to the listeners staying open must go on meanwhile */
+pub async fn apply_listen_config(d: &Arc<Mutex<LpfHttpServerData>>, listeners: &Mutex<Listeners>, cfg: &Config) -> Result<(), HTTPAPIError> {
+	let specs = listener_specs(cfg).map_err(|e| HTTPAPIError::BadRequest { description: e })?;
+	let mut l = listeners.lock().await;
+	if specs == l.specs() {
+		return Ok(());
+	}
+	l.apply(d, &specs).await.map_err(|e| HTTPAPIError::ListenFailed { description: e.join("; ") })
+}
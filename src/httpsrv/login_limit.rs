@@ -0,0 +1,114 @@
+/* Slows down password guessing: after a few failed logins from the same
+ * address or for the same account, the next attempts are refused for a
+ * time doubling at every failure. */
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/* Failures allowed before the lockout starts */
+const FREE_ATTEMPTS: u32 = 5;
+const FIRST_LOCKOUT_SEC: u64 = 2;
+const MAX_LOCKOUT_SEC: u64 = 3600;
+/* Failures older than this are forgotten */
+const FAILURE_MEMORY_SEC: u64 = 24 * 3600;
+/* Addresses and accounts remembered at most, the oldest are forgotten first */
+const MAX_RECORDS: usize = 4096;
+
+#[derive(Clone)]
+struct FailureRecord {
+	count: u32,
+	last_failure: Instant,
+	locked_until: Option<Instant>
+}
+
+#[derive(Clone, Default)]
+pub struct LoginLimiter {
+	failures: HashMap<String, FailureRecord>
+}
+
+fn ip_key(ip: IpAddr) -> String {
+	format!("ip:{}", ip)
+}
+
+fn user_key(username: &str) -> String {
+	format!("user:{}", username)
+}
+
+impl LoginLimiter {
+	/* Seconds to wait before trying again, None when the attempt can go on */
+	pub fn check(&mut self, ip: IpAddr, username: &str) -> Option<u64> {
+		let now = Instant::now();
+		self.failures.retain(|_, f| f.last_failure.elapsed().as_secs() < FAILURE_MEMORY_SEC);
+
+		[ip_key(ip), user_key(username)].iter()
+			.filter_map(|k| self.failures.get(k)?.locked_until)
+			.filter(|until| *until > now)
+			.map(|until| (until - now).as_secs() + 1)
+			.max()
+	}
+
+	/* username is None when there is no such account, the names tried
+	 * would fill the memory for nothing */
+	pub fn record_failure(&mut self, ip: IpAddr, username: Option<&str>) {
+		let now = Instant::now();
+		for k in std::iter::once(ip_key(ip)).chain(username.map(user_key)) {
+			if !self.failures.contains_key(&k) {
+				self.make_room();
+			}
+			let f = self.failures.entry(k).or_insert(FailureRecord { count: 0, last_failure: now, locked_until: None });
+			f.count += 1;
+			f.last_failure = now;
+			if f.count >= FREE_ATTEMPTS {
+				let exp = (f.count - FREE_ATTEMPTS).min(20);
+				let secs = (FIRST_LOCKOUT_SEC << exp).min(MAX_LOCKOUT_SEC);
+				f.locked_until = Some(now + Duration::from_secs(secs));
+			}
+		}
+	}
+
+	fn make_room(&mut self) {
+		if self.failures.len() < MAX_RECORDS {
+			return;
+		}
+		self.failures.retain(|_, f| f.last_failure.elapsed().as_secs() < FAILURE_MEMORY_SEC);
+		if self.failures.len() >= MAX_RECORDS {
+			let oldest = self.failures.iter().min_by_key(|(_, f)| f.last_failure).map(|(k, _)| k.clone());
+			if let Some(k) = oldest {
+				self.failures.remove(&k);
+			}
+		}
+	}
+
+	pub fn record_success(&mut self, ip: IpAddr, username: &str) {
+		self.failures.remove(&ip_key(ip));
+		self.failures.remove(&user_key(username));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn unknown_accounts_are_not_remembered() {
+		let mut l = LoginLimiter::default();
+		let ip: IpAddr = "192.0.2.1".parse().unwrap();
+		for i in 0..10 {
+			l.record_failure(ip, None);
+			l.record_failure(ip, Some(&format!("known{}", i % 2)));
+		}
+		assert_eq!(l.failures.len(), 3);
+		assert!(l.check(ip, "someone").is_some());
+	}
+
+	#[test]
+	fn memory_is_bounded() {
+		let mut l = LoginLimiter::default();
+		for i in 0..(MAX_RECORDS as u32 + 100) {
+			l.record_failure(IpAddr::from(i.to_be_bytes()), None);
+		}
+		assert_eq!(l.failures.len(), MAX_RECORDS);
+		assert!(!l.failures.contains_key(&ip_key(IpAddr::from(0u32.to_be_bytes()))));
+	}
+}
@@ -1,38 +1,39 @@
-use std::convert::Infallible;
-
-
-use hyper::{Body, Request, Response, Server, Method, StatusCode};
-use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Method, StatusCode};
 use hyper::header::HeaderValue;
-use hyper::server::conn::AddrStream;
 
 use pwhash::bcrypt;
 
 use std::str;
 
-use tokio::fs;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use serde_json::Value;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, OwnedMutexGuard};
 use std::sync::Arc;
 use std::collections::HashMap;
 use std::time::{Instant};
-use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::ffi::OsStr;
+use std::net::{IpAddr, SocketAddr};
 use std::fmt;
 
 use cookie::Cookie;
+use rand::RngCore;
+use rand::rngs::OsRng;
 use chrono::{Utc};
 
-use crate::lconfig::{RuntimeGlobalData};
+use crate::lconfig::{Config, RuntimeGlobalData, TokenScope, UserRole};
 
 const COOKIE_HEADER_NAME : &str = "Cookie";
 const _USER_AGENT_HEADER_NAME : &str = "User-Agent";
 const AUTH_COOKIE_NAME : &str = "TXTSCROLLSESSID";
 const AUTHENTICATION_PAGE : &str = "/auth.html";
 const SESSION_TIMEOUT: u64 = 1800;
+/* Sessions end after this time even when they are in use */
+const SESSION_MAX_LIFETIME: u64 = 12 * 3600;
+const SESSION_ID_BYTES: usize = 32;
+const SESSION_PUBLIC_ID_BYTES: usize = 6;
+
+/* The lapi commands a read only API token can use */
+const LAPI_READ_COMMANDS: &[&str] = &["config_get", "playlist_get", "schedule_get", "schedule_now", "whoami", "font_list", "image_list"];
 
 mod api_config;
 use api_config::lapi_config_get;
@@ -41,6 +42,56 @@ use api_config::lapi_config_set;
 mod api_pwd;
 use api_pwd::lapi_password_change;
 
+mod api_playlist;
+use api_playlist::{lapi_playlist_get, lapi_playlist_set, lapi_playlist_next, lapi_playlist_jump};
+
+mod api_schedule;
+use api_schedule::{lapi_schedule_get, lapi_schedule_set, lapi_schedule_now};
+
+mod api_pause;
+use api_pause::{lapi_pause, lapi_resume};
+
+mod api_tokens;
+use api_tokens::{lapi_token_list, lapi_token_create, lapi_token_revoke};
+
+mod audit;
+use audit::{AuditLog, AUDIT_FILE_NAME};
+
+mod login_limit;
+use login_limit::LoginLimiter;
+
+mod api_audit;
+use api_audit::lapi_audit_get;
+
+mod api_sessions;
+use api_sessions::{lapi_session_list, lapi_session_revoke};
+
+mod api_users;
+use api_users::{lapi_whoami, lapi_user_list, lapi_user_set, lapi_user_delete};
+
+mod api_fonts;
+use api_fonts::{lapi_font_list, lapi_font_delete};
+mod api_images;
+use api_images::{lapi_image_list, lapi_image_delete};
+
+mod tls;
+use tls::TlsConnection;
+
+mod static_files;
+use static_files::{read_static_file, decode_request_path, StaticFileError};
+
+mod static_response;
+use static_response::{static_file_response, CompressedCache};
+
+mod listen;
+use listen::{Listeners, listener_specs};
+
+mod events;
+use events::serve_events;
+
+mod rest;
+use rest::serve_rest_api;
+
 use crate::{APP_NAME_APPLICATION, APP_VERSION};
 
 
@@ -50,49 +101,109 @@ struct AuthParams {
 	password: String
 }
 
-#[allow(dead_code)]
 #[derive(Clone)]
 struct BrowserSession {
+	/* Shown in the session list instead of the session ID, which is as
+	 * secret as a password */
+	id: String,
 	username: String,
 	start_time: Instant,
 	last_seen: Instant,
 	remote_addr: SocketAddr,
 }
 
+impl BrowserSession {
+	fn is_expired(&self) -> bool {
+		self.last_seen.elapsed().as_secs() > SESSION_TIMEOUT ||
+			self.start_time.elapsed().as_secs() > SESSION_MAX_LIFETIME
+	}
+}
+
 #[derive(Clone)]
 pub struct LpfHttpServerData {
-	server_start_time: Instant,
 	authenticated_sessions: HashMap<String, BrowserSession>,
+	login_limiter: LoginLimiter,
+	audit: Arc<AuditLog>,
+	listeners: Arc<Mutex<Listeners>>,
+	compressed_cache: Arc<Mutex<CompressedCache>>,
+	/* Held by the commands changing something, one at a time */
+	config_writes: Arc<Mutex<()>>,
 	runtime_global_data: Arc<Mutex<RuntimeGlobalData>>
 }
 
 #[derive(Debug)]
 pub enum HTTPAPIError {
-    Message { description: String },
-    IoError(std::io::Error),
+	/* Missing or invalid parameters */
+	BadRequest { description: String },
+	Unauthorized { description: String },
+	Forbidden { description: String },
+	NotFound { description: String },
+	/* allow lists the methods accepted by the resource */
+	MethodNotAllowed { allow: &'static str },
+	/* The configuration changed since the client read it */
+	VersionMismatch { description: String },
+	/* A listen address of the configuration cannot be used */
+	ListenFailed { description: String },
+	Internal { description: String },
+	IoError(std::io::Error),
+}
+
+impl HTTPAPIError {
+	pub fn status(&self) -> StatusCode {
+		match self {
+			HTTPAPIError::BadRequest { .. } => StatusCode::BAD_REQUEST,
+			HTTPAPIError::Unauthorized { .. } => StatusCode::UNAUTHORIZED,
+			HTTPAPIError::Forbidden { .. } => StatusCode::FORBIDDEN,
+			HTTPAPIError::NotFound { .. } => StatusCode::NOT_FOUND,
+			HTTPAPIError::MethodNotAllowed { .. } => StatusCode::METHOD_NOT_ALLOWED,
+			HTTPAPIError::VersionMismatch { .. } => StatusCode::PRECONDITION_FAILED,
+			HTTPAPIError::ListenFailed { .. } => StatusCode::CONFLICT,
+			HTTPAPIError::Internal { .. } | HTTPAPIError::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+		}
+	}
+
+	/* Stable identifier of the error, for the scripts: the descriptions
+	 * may change, the codes must not */
+	pub fn code(&self) -> &'static str {
+		match self {
+			HTTPAPIError::BadRequest { .. } => "invalid_request",
+			HTTPAPIError::Unauthorized { .. } => "unauthorized",
+			HTTPAPIError::Forbidden { .. } => "forbidden",
+			HTTPAPIError::NotFound { .. } => "not_found",
+			HTTPAPIError::MethodNotAllowed { .. } => "method_not_allowed",
+			HTTPAPIError::VersionMismatch { .. } => "version_mismatch",
+			HTTPAPIError::ListenFailed { .. } => "listen_failed",
+			HTTPAPIError::Internal { .. } => "internal_error",
+			HTTPAPIError::IoError(_) => "io_error",
+		}
+	}
 }
 
 impl From<std::io::Error> for HTTPAPIError {
-    fn from(err: std::io::Error) -> Self {
-        HTTPAPIError::IoError(err)
-    }
+	fn from(err: std::io::Error) -> Self {
+		HTTPAPIError::IoError(err)
+	}
 }
 
 impl fmt::Display for HTTPAPIError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // write!(f, "{}", self.details)
-        match &*self {
-			HTTPAPIError::Message { description } => write!(f, "{}", description),
-            HTTPAPIError::IoError(ref e) => e.fmt(f),
-        }
-    }
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			HTTPAPIError::BadRequest { description } |
+			HTTPAPIError::Unauthorized { description } |
+			HTTPAPIError::Forbidden { description } |
+			HTTPAPIError::NotFound { description } |
+			HTTPAPIError::VersionMismatch { description } |
+			HTTPAPIError::ListenFailed { description } |
+			HTTPAPIError::Internal { description } => write!(f, "{}", description),
+			HTTPAPIError::MethodNotAllowed { allow } => write!(f, "Method not allowed, use one of: {}", allow),
+			HTTPAPIError::IoError(e) => e.fmt(f),
+		}
+	}
 }
+
 fn get_session_sid(req: &Request<Body>) -> Option<String> {
     /* Get the Cookie: header as a string */
-    let ch = match req.headers().get(COOKIE_HEADER_NAME) {
-        Some(v) => v,
-        None => return None
-    };
+    let ch = req.headers().get(COOKIE_HEADER_NAME)?;
     let s = match ch.to_str() {
         Ok(v) => v,
         Err(_e) => return None
@@ -121,7 +232,7 @@ fn get_session_sid(req: &Request<Body>) -> Option<String> {
 
 }
 
-async fn is_authenticated(d: Arc<Mutex<LpfHttpServerData>>, req: &Request<Body>) -> Option<String> {
+async fn is_authenticated(d: Arc<Mutex<LpfHttpServerData>>, req: &Request<Body>, client_addr: SocketAddr) -> Option<String> {
 
 	let sid = match get_session_sid(req) {
 		Some(sid) => sid,
@@ -134,11 +245,14 @@ async fn is_authenticated(d: Arc<Mutex<LpfHttpServerData>>, req: &Request<Body>)
 	/* Check if session exists and is not expired */
 	match rd.authenticated_sessions.get_mut(&sid) {
 		Some(ses) => {
-			let dur_sec = ses.last_seen.elapsed().as_secs();
-			if dur_sec > SESSION_TIMEOUT {
-				println!("Session {} expired", sid);
+			if ses.is_expired() {
+				println!("Session {} expired", ses.id);
 				rd.authenticated_sessions.remove(&sid);
 				None
+			} else if ses.remote_addr.ip() != client_addr.ip() {
+				/* A stolen cookie is useless from another computer */
+				println!("Session {} of {} used from {}, refused", ses.id, ses.remote_addr.ip(), client_addr.ip());
+				None
 			} else {
 				// update last_seen
 				ses.last_seen = Instant::now();
@@ -146,13 +260,140 @@ async fn is_authenticated(d: Arc<Mutex<LpfHttpServerData>>, req: &Request<Body>)
 			}
 		},
 		None => {
-			println!("Session {} not found in authenticated_session table", sid);
+			println!("Session not found in authenticated_session table");
 			None
 		}
 	}
 
 }
 
+/* Like is_authenticated(), without refreshing the session */
+async fn is_session_valid(d: &Arc<Mutex<LpfHttpServerData>>, sid: &str) -> bool {
+	let rd = d.lock().await;
+	match rd.authenticated_sessions.get(sid) {
+		Some(ses) => !ses.is_expired(),
+		None => false
+	}
+}
+
+/* How the client of an API request has authenticated */
+#[derive(Clone)]
+pub enum Credential {
+	Session { sid: String, username: String, role: UserRole },
+	Token { id: String, scope: TokenScope }
+}
+
+impl Credential {
+	/* Limits of the token scopes on the routes. read: the request only
+	 * reads, text: it is about the text only */
+	fn allows(&self, read: bool, text: bool) -> bool {
+		match self {
+			Credential::Session { .. } => true,
+			Credential::Token { scope, .. } => match scope {
+				TokenScope::Full => true,
+				TokenScope::ReadOnly => read,
+				TokenScope::TextOnly => text
+			}
+		}
+	}
+
+	pub fn role(&self) -> UserRole {
+		match self {
+			Credential::Session { role, .. } => *role,
+			Credential::Token { scope, .. } => match scope {
+				TokenScope::Full => UserRole::Admin,
+				TokenScope::ReadOnly => UserRole::Viewer,
+				TokenScope::TextOnly => UserRole::Editor
+			}
+		}
+	}
+
+	/* Called by the handlers before doing anything */
+	pub fn require(&self, role: UserRole) -> Result<(), HTTPAPIError> {
+		if self.role() >= role {
+			Ok(())
+		} else {
+			Err(HTTPAPIError::Forbidden{description: format!("This needs the {:?} role", role)})
+		}
+	}
+
+	/* Who made the request, for the audit log */
+	pub fn describe(&self) -> String {
+		match self {
+			Credential::Session { username, .. } => username.clone(),
+			Credential::Token { id, .. } => format!("token:{}", id)
+		}
+	}
+
+	pub fn username(&self) -> Option<&str> {
+		match self {
+			Credential::Session { username, .. } => Some(username),
+			Credential::Token { .. } => None
+		}
+	}
+
+	pub fn session_id(&self) -> Option<&str> {
+		match self {
+			Credential::Session { sid, .. } => Some(sid),
+			Credential::Token { .. } => None
+		}
+	}
+
+	fn forbidden() -> HTTPAPIError {
+		HTTPAPIError::Forbidden{description: String::from("The API token does not allow this request")}
+	}
+}
+
+fn get_bearer_token(req: &Request<Body>) -> Option<&str> {
+	let h = req.headers().get("Authorization")?.to_str().ok()?;
+	h.strip_prefix("Bearer ").map(str::trim)
+}
+
+/* Accepts both the session cookie and the API tokens.  A request with an
+ * Authorization header is judged by the token only */
+async fn authenticate(d: &Arc<Mutex<LpfHttpServerData>>, req: &Request<Body>, client_addr: SocketAddr) -> Option<Credential> {
+	if let Some(token) = get_bearer_token(req) {
+		let ud = d.lock().await;
+		let rgd = ud.runtime_global_data.lock().await;
+		return api_tokens::find_token(&rgd.cfg, token)
+			.map(|t| Credential::Token { id: t.id.clone(), scope: t.scope });
+	}
+	let sid = is_authenticated(d.clone(), req, client_addr).await?;
+	let ud = d.lock().await;
+	let username = ud.authenticated_sessions.get(&sid)?.username.clone();
+	let rgd = ud.runtime_global_data.lock().await;
+	/* The role may have changed since the login, and the user may be gone */
+	let role = rgd.cfg.find_user(&username)?.role;
+	Some(Credential::Session { sid, username, role })
+}
+
+/* Logs username out of their sessions but keep, the one making the
+ * request.  Returns how many were closed */
+fn revoke_user_sessions(ud: &mut LpfHttpServerData, username: &str, keep: Option<&str>) -> usize {
+	let before = ud.authenticated_sessions.len();
+	ud.authenticated_sessions.retain(|sid, s| s.username != username || Some(sid.as_str()) == keep);
+	before - ud.authenticated_sessions.len()
+}
+
+/* Checks that the session is still open or the token not revoked */
+async fn is_credential_valid(d: &Arc<Mutex<LpfHttpServerData>>, cred: &Credential) -> bool {
+	match cred {
+		Credential::Session { sid, username, .. } => {
+			if !is_session_valid(d, sid).await {
+				return false;
+			}
+			let ud = d.lock().await;
+			let rgd = ud.runtime_global_data.lock().await;
+			rgd.cfg.find_user(username).is_some()
+		},
+		Credential::Token { id, .. } => {
+			let ud = d.lock().await;
+			let rgd = ud.runtime_global_data.lock().await;
+			rgd.cfg.api_tokens.iter().any(|t| &t.id == id)
+		}
+	}
+}
+
 async fn collect_json_post_request(req: Request<Body>) -> Result<Value, String> {
 	// to do: versione generalizzata di collect_auth_params
 	let body = match hyper::body::to_bytes(req.into_body()).await {
@@ -162,7 +403,7 @@ async fn collect_json_post_request(req: Request<Body>) -> Result<Value, String>
 
 	let v: Value = match serde_json::from_slice(body.as_ref()) {
 		Ok(v) => v,
-		Err(e) => return Err(format!("Unable to parse json POST body: {}", e.to_string()))
+		Err(e) => return Err(format!("Unable to parse json POST body: {}", e))
 	};
 
 	Ok(v)
@@ -176,7 +417,7 @@ async fn collect_auth_params(req: Request<Body>) -> Result<AuthParams, String> {
 	};
 	let j : AuthParams = match serde_json::from_slice(body.as_ref()) {
 		Ok(v) => v,
-		Err(e) => return Err(format!("Unable to parse json POST body: {}", e.to_string()))
+		Err(e) => return Err(format!("Unable to parse json POST body: {}", e))
 	};
 
 	// println!("Auth requested: {} {}", j.username, j.password);
@@ -189,66 +430,66 @@ async fn validate_credentials(d: &Arc<Mutex<LpfHttpServerData>>, ap: &AuthParams
 
 	let ud = d.lock().await;
 	let rgd = ud.runtime_global_data.lock().await;
-	let encrypted_admin_password = rgd.cfg.encrypted_admin_password.clone();
+	let encrypted_password = match rgd.cfg.find_user(&ap.username) {
+		Some(u) => u.encrypted_password.clone(),
+		None => return false
+	};
 	drop(rgd);
 
-	if ap.username != "admin" {
-		return false;
-	}
-
-	if !bcrypt::verify(ap.password.clone(), &encrypted_admin_password) {
-		return false;
-	}
-
-	true
+	bcrypt::verify(ap.password.clone(), &encrypted_password)
 
 }
 
-fn content_type(filename: PathBuf) -> &'static str
-{
-
-	match filename.extension().and_then(OsStr::to_str) {
-		Some("png") => "image/png",
-		Some("html") => "text/html",
-		Some("js") => "text/javascript",
-		Some("jpg") => "image/jpeg",
-		Some("txt") => "text/plain",
-		Some("css") => "text/css",
-		_ => "application/octet-stream"
-	}
 
+async fn expire_auth_sessions(d: Arc<Mutex<LpfHttpServerData>>) {
+	let mut rd = d.lock().await;
+	rd.authenticated_sessions.retain(|_key, bs| !bs.is_expired());
 }
 
+fn random_hex(len: usize) -> String {
+	let mut bytes = vec![0u8; len];
+	OsRng.fill_bytes(&mut bytes);
+	bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
 
-async fn expire_auth_sessions(d: Arc<Mutex<LpfHttpServerData>>) {
-	let mut rd = d.lock().await;
-	rd.authenticated_sessions.retain(|_key, bs| {
-		let age_sec = bs.last_seen.elapsed().as_secs();
-		if age_sec > SESSION_TIMEOUT {
-// 			println!("Expiring old session {},{} IP:{:?}", key, bs.last_seen.elapsed().as_secs(), bs.remote_addr);
-		}
-		!(age_sec > SESSION_TIMEOUT)
-	});
+fn generate_new_sid() -> String {
+	random_hex(SESSION_ID_BYTES)
 }
 
-fn generate_new_sid(start_ref_time: std::time::Instant) -> String {
-	/* Generate a session ID just from current time.  To be improved. */
-	let sid = start_ref_time.elapsed().as_secs();
-	println!("New SID {} generated", sid);
-	format!("{}", sid)
+/* Whether the browser reached us with HTTPS, directly or through a
+ * reverse proxy.  Anyone could send X-Forwarded-Proto, only the one of
+ * the configured proxies counts */
+async fn is_secure_request(d: &Arc<Mutex<LpfHttpServerData>>, req: &Request<Body>, client_addr: SocketAddr) -> bool {
+	if req.extensions().get::<TlsConnection>().is_some() {
+		return true;
+	}
+	if req.headers().get("X-Forwarded-Proto").is_none_or(|v| v != "https") {
+		return false;
+	}
+	let ud = d.lock().await;
+	let rgd = ud.runtime_global_data.lock().await;
+	let ip = client_addr.ip().to_canonical();
+	rgd.cfg.trusted_proxies.iter().any(|p| p.parse::<IpAddr>().is_ok_and(|p| p == ip))
 }
 
-fn append_session_cookie_to_response(response: &mut Response<Body>, sid: String) {
-	let cookieval = format!("S-{}", sid);
-	/* let cookie_header_str = format!("{}={}; SameSite=Strict; Max-Age={}", AUTH_COOKIE_NAME, cookieval, COOKIE_MAX_AGE); */
-	let cookie_header_str = format!("{}={}; SameSite=Strict", AUTH_COOKIE_NAME, cookieval);
+/* An empty sid removes the cookie */
+fn append_session_cookie_to_response(response: &mut Response<Body>, sid: String, secure: bool) {
+	let mut cookie_header_str = if sid.is_empty() {
+		format!("{}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0", AUTH_COOKIE_NAME)
+	} else {
+		format!("{}=S-{}; Path=/; HttpOnly; SameSite=Strict", AUTH_COOKIE_NAME, sid)
+	};
+	if secure {
+		cookie_header_str.push_str("; Secure");
+	}
 	let cookie_header = HeaderValue::from_str(&cookie_header_str).unwrap();
-	response.headers_mut().insert("Set-Cookie", HeaderValue::from(cookie_header));
+	response.headers_mut().insert("Set-Cookie", cookie_header);
 }
 
 
 async fn serve_authservice(d: Arc<Mutex<LpfHttpServerData>>, req: Request<Body>, response: &mut Response<Body>, client_addr: SocketAddr) {
 	response.headers_mut().insert("Content-type", HeaderValue::from_static("application/json"));
+	let secure = is_secure_request(&d, &req, client_addr).await;
 	let ap = match collect_auth_params(req).await {
 		Ok(a) => a,
 		Err(e) => {
@@ -260,44 +501,64 @@ async fn serve_authservice(d: Arc<Mutex<LpfHttpServerData>>, req: Request<Body>,
 
 	expire_auth_sessions(d.clone()).await;
 
+	let ip = client_addr.ip();
+	let wait = d.lock().await.login_limiter.check(ip, &ap.username);
+	if let Some(sec) = wait {
+		audit(&d, "login_locked", &ap.username, ip, json!({ "retry_after": sec })).await;
+		let r = json!({ "err": format!("Too many failed logins, retry in {} seconds", sec) }).to_string();
+		*response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+		response.headers_mut().insert("Retry-After", HeaderValue::from(sec));
+		*response.body_mut() = Body::from(r);
+		return;
+	}
 
 	if validate_credentials(&d, &ap).await {
 		let mut rd = d.lock().await;
-		let sid = generate_new_sid(rd.server_start_time);
-		append_session_cookie_to_response(response, sid.clone());
+		rd.login_limiter.record_success(ip, &ap.username);
+		let sid = generate_new_sid();
+		append_session_cookie_to_response(response, sid.clone(), secure);
 		*response.body_mut() = Body::from(r#"{ "auth": "ok"}"#);
 		let ses = BrowserSession {
-			username: ap.username,
+			id: random_hex(SESSION_PUBLIC_ID_BYTES),
+			username: ap.username.clone(),
 			start_time: Instant::now(),
 			last_seen: Instant::now(),
 			remote_addr: client_addr
 		};
-		rd.authenticated_sessions.insert(sid.clone(), ses);
-		println!("Authenticated new session with sid = {}", sid);
+		println!("Authenticated new session {} of {} from {}", ses.id, ses.username, client_addr.ip());
+		let details = json!({ "session": ses.id });
+		rd.authenticated_sessions.insert(sid, ses);
+		drop(rd);
+		audit(&d, "login", &ap.username, ip, details).await;
 	} else {
+		let mut rd = d.lock().await;
+		let known = rd.runtime_global_data.lock().await.cfg.find_user(&ap.username).is_some();
+		rd.login_limiter.record_failure(ip, known.then_some(ap.username.as_str()));
+		drop(rd);
+		audit(&d, "login_failed", &ap.username, ip, json!({})).await;
 		let r = json!({ "err": "Invalid username or password"}).to_string();
 		*response.body_mut() = Body::from(r);
 	}
 }
 
-async fn serve_logoff(d: Arc<Mutex<LpfHttpServerData>>, req: Request<Body>, response: &mut Response<Body>, _client_addr: SocketAddr)
+async fn serve_logoff(d: Arc<Mutex<LpfHttpServerData>>, req: Request<Body>, response: &mut Response<Body>, client_addr: SocketAddr)
 {
 	let sid = match get_session_sid(&req) {
 		Some(sid) => sid,
-		None => return ()
+		None => return 
 	};
+	let secure = is_secure_request(&d, &req, client_addr).await;
 
    	/* Lock global configuration data */
     let mut rd = d.lock().await;
 
 	/* Check if session exists and is not expired */
-	match rd.authenticated_sessions.get(&sid) {
-		Some(_ses) => {
-			println!("Logging off session {}", sid);
-			rd.authenticated_sessions.remove(&sid);
-		},
-		None => ()
+	if let Some(ses) = rd.authenticated_sessions.remove(&sid) {
+		println!("Logging off session {}", ses.id);
+		drop(rd);
+		audit(&d, "logoff", &ses.username, client_addr.ip(), json!({ "session": ses.id })).await;
 	}
+	append_session_cookie_to_response(response, String::new(), secure);
 
 	*response.body_mut() = Body::from(format!("Redirecting to {}", AUTHENTICATION_PAGE));
 	response.headers_mut().insert("Location", HeaderValue::from_static(AUTHENTICATION_PAGE));
@@ -305,11 +566,60 @@ async fn serve_logoff(d: Arc<Mutex<LpfHttpServerData>>, req: Request<Body>, resp
 
 }
 
-async fn serve_lapi(d: Arc<Mutex<LpfHttpServerData>>, req: Request<Body>, response: &mut Response<Body>, _client_addr: SocketAddr) {
+/* Writes an entry of the audit log.  Must be called without holding the
+ * lock on d */
+async fn audit(d: &Arc<Mutex<LpfHttpServerData>>, event: &str, user: &str, remote_addr: IpAddr, details: Value) {
+	let log = d.lock().await.audit.clone();
+	log.record(event, user, Some(remote_addr), details).await;
+}
+
+/* The configuration before running a command, to find out what it has
+ * changed.  The commands changing something run one at a time while it
+ * is kept, the differences found at the end are theirs */
+struct ConfigSnapshot {
+	version: u64,
+	cfg: Value,
+	_writing: OwnedMutexGuard<()>
+}
+
+async fn cfg_snapshot(d: &Arc<Mutex<LpfHttpServerData>>) -> ConfigSnapshot {
+	let writes = d.lock().await.config_writes.clone();
+	let writing = writes.lock_owned().await;
+	let ud = d.lock().await;
+	let rgd = ud.runtime_global_data.lock().await;
+	ConfigSnapshot { version: rgd.cfg.version, cfg: json!(rgd.cfg), _writing: writing }
+}
+
+/* Tells the other clients the configuration has changed, and records who
+ * changed which fields */
+async fn config_changed(d: &Arc<Mutex<LpfHttpServerData>>, before: ConfigSnapshot, cred: &Credential, action: &str, remote_addr: IpAddr) {
+	let cfg = {
+		let ud = d.lock().await;
+		let rgd = ud.runtime_global_data.lock().await;
+		if rgd.cfg.version != before.version {
+			rgd.notify("config", json!({ "version": rgd.cfg.version }));
+		}
+		json!(rgd.cfg)
+	};
+
+	let fields: Vec<&String> = match cfg.as_object() {
+		Some(m) => m.iter()
+			.filter(|(k, v)| *k != "version" && before.cfg.get(k.as_str()) != Some(v))
+			.map(|(k, _)| k)
+			.collect(),
+		None => Vec::new()
+	};
+	if !fields.is_empty() {
+		let event = if action.contains("password") { "password_change" } else { "config_change" };
+		audit(d, event, &cred.describe(), remote_addr, json!({ "action": action, "fields": fields })).await;
+	}
+}
+
+async fn serve_lapi(d: Arc<Mutex<LpfHttpServerData>>, req: Request<Body>, response: &mut Response<Body>, client_addr: SocketAddr) {
 	response.headers_mut().insert("Content-type", HeaderValue::from_static("application/json"));
 
-	let _sid = match is_authenticated(d.clone(), &req).await {
-		Some(s) => s,
+	let cred = match authenticate(&d, &req, client_addr).await {
+		Some(c) => c,
 		None => {
 			let r = json!({ "auth": "not authenticated or session expired" }).to_string();
 			*response.body_mut() = Body::from(r);
@@ -338,8 +648,8 @@ async fn serve_lapi(d: Arc<Mutex<LpfHttpServerData>>, req: Request<Body>, respon
 		}
 	};
 
-	let cmd: &str = match cmdval.as_str() {
-		Some(c) => c,
+	let cmd: String = match cmdval.as_str() {
+		Some(c) => c.to_string(),
 		None => {
 			let r = json!({ "err": "cmd is not a string" }).to_string();
 			*response.body_mut() = Body::from(r);
@@ -347,100 +657,129 @@ async fn serve_lapi(d: Arc<Mutex<LpfHttpServerData>>, req: Request<Body>, respon
 		}
 	};
 
-	let lapi_result = match cmd {
-		"config_get" => lapi_config_get(d, v).await,
-		"config_set" => lapi_config_set(d, v).await,
-		"password_change" => lapi_password_change(d, v).await,
+	if !cred.allows(LAPI_READ_COMMANDS.contains(&cmd.as_str()), false) {
+		let herr = Credential::forbidden();
+		let r = json!({ "err": herr.to_string(), "code": herr.code() }).to_string();
+		*response.body_mut() = Body::from(r);
+		return;
+	}
+
+	/* Reading commands change nothing, there is nothing to record */
+	let cfg_before = match LAPI_READ_COMMANDS.contains(&cmd.as_str()) {
+		true => None,
+		false => Some(cfg_snapshot(&d).await)
+	};
+
+	let lapi_result = match cmd.as_str() {
+		"config_get" => lapi_config_get(d.clone(), &cred, v).await,
+		"config_set" => lapi_config_set(d.clone(), &cred, v).await,
+		"password_change" => lapi_password_change(d.clone(), &cred, v).await,
+		"playlist_get" => lapi_playlist_get(d.clone(), &cred, v).await,
+		"playlist_set" => lapi_playlist_set(d.clone(), &cred, v).await,
+		"playlist_next" => lapi_playlist_next(d.clone(), &cred, v).await,
+		"playlist_jump" => lapi_playlist_jump(d.clone(), &cred, v).await,
+		"schedule_get" => lapi_schedule_get(d.clone(), &cred, v).await,
+		"schedule_set" => lapi_schedule_set(d.clone(), &cred, v).await,
+		"schedule_now" => lapi_schedule_now(d.clone(), &cred, v).await,
+		"pause" => lapi_pause(d.clone(), &cred, v).await,
+		"resume" => lapi_resume(d.clone(), &cred, v).await,
+		"token_list" => lapi_token_list(d.clone(), &cred, v).await,
+		"token_create" => lapi_token_create(d.clone(), &cred, v).await,
+		"token_revoke" => lapi_token_revoke(d.clone(), &cred, v).await,
+		"whoami" => lapi_whoami(d.clone(), &cred, v).await,
+		"user_list" => lapi_user_list(d.clone(), &cred, v).await,
+		"user_set" => lapi_user_set(d.clone(), &cred, v).await,
+		"user_delete" => lapi_user_delete(d.clone(), &cred, v).await,
+		"session_list" => lapi_session_list(d.clone(), &cred, v).await,
+		"session_revoke" => lapi_session_revoke(d.clone(), &cred, v).await,
+		"audit_get" => lapi_audit_get(d.clone(), &cred, v).await,
+		"font_list" => lapi_font_list(d.clone(), &cred, v).await,
+		"font_delete" => lapi_font_delete(d.clone(), &cred, v).await,
+		"image_list" => lapi_image_list(d.clone(), &cred, v).await,
+		"image_delete" => lapi_image_delete(d.clone(), &cred, v).await,
 		_ => {
-			let r = json!({ "err": format!("{} is not recognized as a lapi cmd", cmd) }).to_string();
+			let r = json!({ "err": format!("{} is not recognized as a lapi cmd", cmd), "code": "unknown_command" }).to_string();
 			*response.body_mut() = Body::from(r);
 			return;
 		}
 	};
 
 
+	if let Some(before) = cfg_before {
+		config_changed(&d, before, &cred, &cmd, client_addr.ip()).await;
+	}
+
 	match lapi_result {
 		Ok(jresult) => *response.body_mut() = Body::from(jresult.to_string()),
 		Err(herr) => {
-			let errj = json!({"err": herr.to_string() });
+			let errj = json!({"err": herr.to_string(), "code": herr.code() });
 			*response.body_mut() = Body::from(errj.to_string());
 		}
 	}
 }
 
-fn strip_heading_slashes(u: &str) -> String {
-	let mut idx = 0;
-	let ubytes = u.as_bytes();
-	static SLASH: u8 = '/' as u8;
-	while idx < ubytes.len() && ubytes[idx] == SLASH {
-		idx += 1;
-	}
-	u[idx..].to_owned()
-}
-
 
 async fn serve_page(d: Arc<Mutex<LpfHttpServerData>>, req: Request<Body>, response: &mut Response<Body>, client_addr: SocketAddr) {
 		let now = Utc::now();
 		println!("{} {} URI: {} Method: {}",
 				now.to_rfc3339(),
 				client_addr.ip(), req.uri().path(), req.method());
-		/* Missing authentication redirect is required for everything *.html  */
-		let p = req.uri().path();
+		/* Missing authentication redirect is required for everything *.html,
+		 * also when written as %2ehtml.  Not for the REST API, its clients
+		 * get a 401 they can understand */
+		let p = decode_request_path(req.uri().path()).unwrap_or_default();
 		let dc1 = d.clone();
-		if (p == "/" || p.to_lowercase().ends_with(".html")) &&
-				is_authenticated(dc1, &req).await == None &&
+		if (p.ends_with('/') || p.to_lowercase().ends_with(".html")) &&
+				!req.uri().path().starts_with(rest::API_PREFIX) &&
+				is_authenticated(dc1, &req, client_addr).await.is_none() &&
 				p != AUTHENTICATION_PAGE
 		{
 				*response.body_mut() = Body::from(format!("While serving static page, authentication is needed. Redirecting to {}", AUTHENTICATION_PAGE));
 				response.headers_mut().insert("Location", HeaderValue::from_static(AUTHENTICATION_PAGE));
 				*response.status_mut() = StatusCode::FOUND;
 		} else {
-				if req.uri() == "/authservice" && req.method() == &Method::POST {
+				if req.uri() == "/authservice" && req.method() == Method::POST {
 						serve_authservice(d, req, response, client_addr).await;
-				} else if req.uri() == "/lapi" && req.method() == &Method::POST {
+				} else if req.uri() == "/lapi" && req.method() == Method::POST {
 						serve_lapi(d, req, response, client_addr).await;
-				} else if req.uri() == "/logoff.do" && req.method() == &Method::GET {
+				} else if req.uri().path().starts_with(rest::API_PREFIX) {
+						serve_rest_api(d, req, response, client_addr).await;
+				} else if req.uri() == "/events" && req.method() == Method::GET {
+						serve_events(d, req, response, client_addr).await;
+				} else if req.uri() == "/logoff.do" && req.method() == Method::GET {
 						serve_logoff(d, req, response, client_addr).await;
 				} else {
-						/* Serves a static file.
-							/ is translated to /index.html
-							and /fonts is translated into runtime_data_dir+"/fonts"
-							and /media is translated into runtime_data_dir+"/media"
-						*/
-						let ld = d.lock().await;
-						let rgd = ld.runtime_global_data.lock().await;
-						let mut filename = rgd.html_dir.clone();
-						let slash_stripped_uri = strip_heading_slashes(req.uri().path());
-						if req.uri() == "/" {
-								filename.push("index.html");
-						} else {
-								filename.push(slash_stripped_uri);
-						}
-						drop(rgd);
-
-						let mut content = match fs::read(&filename).await {
-								Ok(s) => s,
+						/* Serves a static file of the web root, / is translated to /index.html */
+						let webroot = d.lock().await.runtime_global_data.lock().await.webroot.clone();
+						let mut file = match read_static_file(&webroot, req.uri().path()).await {
+								Ok(f) => f,
 								Err(e) => {
-								*response.status_mut() = StatusCode::NOT_FOUND;
-								println!("Unalbe to read file {}: {}", filename.display(), e.to_string());
-								format!("Unalbe to read file {}: {}", filename.display(), e.to_string()).into_bytes()
+									/* The client does not learn where the files are */
+									let (status, msg) = match e {
+										StaticFileError::NotFound => (StatusCode::NOT_FOUND, "Not found"),
+										StaticFileError::Forbidden => (StatusCode::FORBIDDEN, "Forbidden")
+									};
+									println!("Refused static file {}: {}", req.uri().path(), msg);
+									*response.status_mut() = status;
+									response.headers_mut().insert("Content-type", HeaderValue::from_static("text/plain"));
+									*response.body_mut() = Body::from(msg);
+									return;
 								}
 						};
 						/* Change some variables in the static file, like {appname} */
-						let content_type = content_type(filename);
-						if content_type == "text/html" {
-							let mut cs = match str::from_utf8(&content) {
+						if file.content_type.starts_with("text/html") {
+							let mut cs = match str::from_utf8(&file.contents) {
 								Ok(v) => v.to_string(),
 								Err(_e) => String::from("Unable to convert html file to UTF8 string. Invalid UTF-8 chars?")
 							};
 							cs = cs
 								.replace("{appname}", APP_NAME_APPLICATION)
 								.replace("{appversion}", APP_VERSION);
-							content = Vec::from(cs);
+							file.contents = Vec::from(cs);
 						}
 
-						response.headers_mut().insert("Content-type", HeaderValue::from_static(content_type));
-						*response.body_mut() = Body::from(content);
+						let cache = d.lock().await.compressed_cache.clone();
+						static_file_response(&req, file, &cache, response).await;
 				}
 	}
 }
@@ -450,7 +789,8 @@ async fn servicefn(d: Arc<Mutex<LpfHttpServerData>>, req: Request<Body>, client_
 	let method = req.method();
 
 
-    if method == &Method::GET || method == &Method::POST {
+    /* The REST API also uses PUT and PATCH */
+    if method == Method::GET || method == Method::POST || req.uri().path().starts_with(rest::API_PREFIX) {
         // serve_page(d, req, &mut response, client_addr).await;
         serve_page(d, req, &mut response, client_addr).await;
     } else {
@@ -466,39 +806,25 @@ pub async fn httpd_main(runtime_global_data: &Arc<Mutex<RuntimeGlobalData>>) ->
 	let d = Arc::new(Mutex::new(
 			LpfHttpServerData {
 					authenticated_sessions: HashMap::new(),
-					server_start_time: Instant::now(),
+					login_limiter: LoginLimiter::default(),
+					audit: Arc::new(AuditLog::new(Config::get_config_file_name(true).with_file_name(AUDIT_FILE_NAME))),
+					listeners: Arc::new(Mutex::new(Listeners::default())),
+					compressed_cache: Arc::new(Mutex::new(CompressedCache::default())),
+					config_writes: Arc::new(Mutex::new(())),
 					runtime_global_data: runtime_global_data.clone()
 	}));
 
-	let http_port = cf.cfg.http_port;
+	let specs = listener_specs(&cf.cfg);
 	drop(cf);
 
-	let addr = ([0,0,0,0], http_port).into();
-
-	let make_svc = make_service_fn(move |conn: &AddrStream| {
-		let addr = conn.remote_addr();
-		let d = d.clone();
-		async move {
-			Ok::<_, Infallible>(service_fn(move |req: hyper::Request<Body>| {
-				let addr = addr.clone();
-				let d = d.clone();
-				async move {
-					Ok::<_,Infallible>(servicefn(d, req, addr.clone()).await)
-				}
-			}))
+	/* A failure leaves running what could be started.  The following
+	 * changes are applied by config_set */
+	match specs {
+		Ok(specs) => {
+			let listeners = d.lock().await.listeners.clone();
+			let _ = listeners.lock().await.apply(&d, &specs).await;
 		}
-
-	});
-
-
-	let server = Server::bind(&addr).serve(make_svc);
-	println!("HTTP server listening on port {}", http_port);
-
-	// Run this server for... forever!
-	if let Err(e) = server.await {
-		eprintln!("server error: {}", e);
+		Err(e) => eprintln!("{}", e)
 	}
-	println!("Gio#2");
 	Ok(())
-
 }
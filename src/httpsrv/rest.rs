@@ -0,0 +1,379 @@
+/* Resource style API under /api/v1, for scripts and other programs.  It
+ * runs the same handlers as /lapi, with proper HTTP status codes and
+ * errors as {"error": {"code", "message"}}.  The routes are described by
+ * the OpenAPI document served at /api/v1/openapi.json */
+
+use hyper::{Body, Request, Response, Method, StatusCode};
+use hyper::body::HttpBody;
+use hyper::header::HeaderValue;
+use serde_json::{json, Map, Value};
+use percent_encoding::percent_decode_str;
+use tokio::sync::Mutex;
+use std::sync::Arc;
+use std::net::SocketAddr;
+
+use crate::httpsrv::{LpfHttpServerData, HTTPAPIError, Credential, authenticate, cfg_snapshot, config_changed};
+use crate::httpsrv::api_config::{lapi_config_get, lapi_config_set};
+use crate::httpsrv::api_pwd::lapi_password_change;
+use crate::httpsrv::api_playlist::{lapi_playlist_get, lapi_playlist_set, lapi_playlist_next, lapi_playlist_jump};
+use crate::httpsrv::api_schedule::{lapi_schedule_get, lapi_schedule_set, lapi_schedule_now};
+use crate::httpsrv::api_pause::{lapi_pause, lapi_resume};
+use crate::httpsrv::api_tokens::{lapi_token_list, lapi_token_create, lapi_token_revoke};
+use crate::httpsrv::api_users::{lapi_whoami, lapi_user_list, lapi_user_set, lapi_user_delete};
+use crate::httpsrv::api_sessions::{lapi_session_list, lapi_session_revoke};
+use crate::httpsrv::api_audit::lapi_audit_get;
+use crate::httpsrv::api_fonts::{lapi_font_list, lapi_font_delete, font_upload};
+use crate::httpsrv::api_images::{lapi_image_list, lapi_image_delete, image_upload};
+use crate::httpsrv::events::serve_events;
+use crate::fonts::MAX_FONT_FILE_SIZE;
+use crate::images::MAX_IMAGE_FILE_SIZE;
+use crate::lconfig::{Config, UserRole};
+
+pub const API_PREFIX: &str = "/api/v1/";
+
+const OPENAPI_DOCUMENT: &str = include_str!("openapi.json");
+
+/* Resources and the methods they accept, for the Allow header */
+const ROUTES: &[(&str, &str)] = &[
+	("config", "GET, PUT, PATCH"),
+	("state", "GET"),
+	("text", "GET, PUT"),
+	("pause", "POST"),
+	("resume", "POST"),
+	("playlist", "GET, PUT"),
+	("playlist/next", "POST"),
+	("playlist/jump", "POST"),
+	("schedule", "GET, PUT"),
+	("schedule/now", "GET"),
+	("password", "PUT"),
+	("tokens", "GET, POST"),
+	("users", "GET, POST"),
+	("whoami", "GET"),
+	("sessions", "GET"),
+	("audit", "GET"),
+	("fonts", "GET, POST"),
+	("images", "GET, POST"),
+	("events", "GET"),
+	("openapi.json", "GET"),
+];
+
+fn set_json_body(response: &mut Response<Body>, status: StatusCode, body: &Value) {
+	response.headers_mut().insert("Content-type", HeaderValue::from_static("application/json"));
+	*response.status_mut() = status;
+	*response.body_mut() = Body::from(body.to_string());
+}
+
+fn set_error(response: &mut Response<Body>, err: &HTTPAPIError) {
+	match err {
+		HTTPAPIError::MethodNotAllowed { allow } => {
+			response.headers_mut().insert("Allow", HeaderValue::from_static(allow));
+		},
+		HTTPAPIError::Unauthorized { .. } => {
+			response.headers_mut().insert("WWW-Authenticate", HeaderValue::from_static("Bearer"));
+		},
+		_ => ()
+	}
+	let body = json!({ "error": { "code": err.code(), "message": err.to_string() } });
+	set_json_body(response, err.status(), &body);
+}
+
+/* The request body as JSON.  An empty body is an empty object, and a
+ * text/plain body is taken as {"text": body} */
+async fn collect_body(req: Request<Body>) -> Result<Value, HTTPAPIError> {
+	let is_text = req.headers().get("Content-Type")
+		.and_then(|v| v.to_str().ok())
+		.is_some_and(|v| v.starts_with("text/plain"));
+
+	let body = match hyper::body::to_bytes(req.into_body()).await {
+		Ok(b) => b,
+		Err(e) => return Err(HTTPAPIError::BadRequest{description: e.to_string()})
+	};
+	if is_text {
+		return match String::from_utf8(body.to_vec()) {
+			Ok(s) => Ok(json!({ "text": s })),
+			Err(_) => Err(HTTPAPIError::BadRequest{description: String::from("The text is not valid UTF-8")})
+		};
+	}
+	if body.iter().all(u8::is_ascii_whitespace) {
+		return Ok(json!({}));
+	}
+	serde_json::from_slice(body.as_ref())
+		.map_err(|e| HTTPAPIError::BadRequest{description: format!("Unable to parse json body: {}", e)})
+}
+
+/* The body of a file upload, refused when longer than max */
+async fn collect_file_body(req: Request<Body>, max: usize) -> Result<Vec<u8>, HTTPAPIError> {
+	let mut body = req.into_body();
+	let mut data = Vec::new();
+	while let Some(chunk) = body.data().await {
+		let chunk = chunk.map_err(|e| HTTPAPIError::BadRequest{description: e.to_string()})?;
+		if data.len() + chunk.len() > max {
+			return Err(HTTPAPIError::BadRequest{description: format!("The file is larger than {} bytes", max)});
+		}
+		data.extend_from_slice(&chunk);
+	}
+	Ok(data)
+}
+
+/* Parameters in the query string, like /audit?event=login&limit=10 */
+fn query_params(req: &Request<Body>) -> Map<String, Value> {
+	let decode = |s: &str| percent_decode_str(&s.replace('+', " ")).decode_utf8_lossy().into_owned();
+	req.uri().query().unwrap_or_default()
+		.split('&')
+		.filter(|p| !p.is_empty())
+		.map(|p| {
+			let (k, v) = p.split_once('=').unwrap_or((p, ""));
+			(decode(k), Value::String(decode(v)))
+		})
+		.collect()
+}
+
+/* Name of the resource after collection/ in path, like users/john%20doe.
+ * Names are a single path segment, they cannot contain a / */
+fn resource_id(path: &str, collection: &str) -> Result<String, HTTPAPIError> {
+	let raw = &path[collection.len()..];
+	let id = percent_decode_str(raw).decode_utf8()
+		.map_err(|_| HTTPAPIError::BadRequest{description: format!("{} is not valid UTF-8", raw)})?;
+	if id.is_empty() || id.contains('/') {
+		return Err(HTTPAPIError::BadRequest{description: format!("Invalid name {}", id)});
+	}
+	Ok(id.into_owned())
+}
+
+/* Version the client has read, from If-Match: "<version>" */
+fn if_match_version(req: &Request<Body>) -> Result<Option<u64>, HTTPAPIError> {
+	let v = match req.headers().get("If-Match").and_then(|v| v.to_str().ok()) {
+		Some(v) => v.trim(),
+		None => return Ok(None)
+	};
+	if v == "*" {
+		return Ok(None);
+	}
+	v.trim_start_matches("W/").trim_matches('"').parse::<u64>()
+		.map(Some)
+		.map_err(|_| HTTPAPIError::BadRequest{description: format!("Invalid If-Match value {}", v)})
+}
+
+/* PATCH changes only the settings in the body, PUT also puts back the
+ * defaults of the display settings it does not mention */
+async fn config_update(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, body: Value, version: Option<u64>, replace: bool) -> Result<Value, HTTPAPIError> {
+	let changes = match body {
+		Value::Object(m) => m,
+		_ => return Err(HTTPAPIError::BadRequest{description: String::from("The configuration must be a JSON object")})
+	};
+	let mut cfg = Map::new();
+	if replace {
+		if let Value::Object(defaults) = json!(Config::new()) {
+			cfg.extend(defaults.into_iter().filter(|(k, _)| k.starts_with("disp_") || k == "zones" || k == "overlays"));
+		}
+	}
+	cfg.extend(changes);
+
+	let mut params = json!({ "cfg": cfg });
+	if let Some(v) = version {
+		params["version"] = json!(v);
+	}
+	lapi_config_set(d.clone(), cred, params).await?;
+	lapi_config_get(d, cred, json!({})).await
+}
+
+async fn text_get(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential) -> Result<Value, HTTPAPIError> {
+	let cfg = lapi_config_get(d, cred, json!({})).await?;
+	Ok(json!({ "text": cfg["disp_text"] }))
+}
+
+async fn text_set(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential, body: Value) -> Result<Value, HTTPAPIError> {
+	let text = match body.get("text").and_then(Value::as_str) {
+		Some(t) => t.to_string(),
+		None => return Err(HTTPAPIError::BadRequest{description: String::from("Unable to find text in the request body")})
+	};
+	lapi_config_set(d, cred, json!({ "cfg": { "disp_text": text } })).await?;
+	Ok(json!({ "text": text }))
+}
+
+async fn state_get(d: Arc<Mutex<LpfHttpServerData>>, cred: &Credential) -> Result<Value, HTTPAPIError> {
+	cred.require(UserRole::Viewer)?;
+	let ud = d.lock().await;
+	let rgd = ud.runtime_global_data.lock().await;
+	Ok(rgd.state())
+}
+
+pub async fn serve_rest_api(d: Arc<Mutex<LpfHttpServerData>>, req: Request<Body>, response: &mut Response<Body>, client_addr: SocketAddr) {
+	let path = req.uri().path().trim_start_matches(API_PREFIX).trim_end_matches('/').to_string();
+	let method = req.method().clone();
+
+	/* The description of the API is public */
+	if path == "openapi.json" && method == Method::GET {
+		response.headers_mut().insert("Content-type", HeaderValue::from_static("application/json"));
+		*response.body_mut() = Body::from(OPENAPI_DOCUMENT);
+		return;
+	}
+
+	let cred = match authenticate(&d, &req, client_addr).await {
+		Some(c) => c,
+		None => {
+			set_error(response, &HTTPAPIError::Unauthorized{description: String::from("Not authenticated, session expired or invalid API token")});
+			return;
+		}
+	};
+	/* Listing the tokens needs full access, like changing them */
+	let read = method == Method::GET && !["tokens", "users", "audit"].contains(&path.as_str());
+	if !cred.allows(read, path == "text") {
+		set_error(response, &Credential::forbidden());
+		return;
+	}
+
+	if path == "events" && method == Method::GET {
+		serve_events(d, req, response, client_addr).await;
+		return;
+	}
+
+	let version = match if_match_version(&req) {
+		Ok(v) => v,
+		Err(e) => return set_error(response, &e)
+	};
+	let query = query_params(&req);
+	/* Uploaded files are the body, their name is in the query string */
+	let max_file_size = match path.as_str() {
+		"fonts" => MAX_FONT_FILE_SIZE,
+		"images" => MAX_IMAGE_FILE_SIZE,
+		_ => 0
+	};
+	let upload = method == Method::POST && max_file_size > 0;
+	let (mut body, file) = if upload {
+		match collect_file_body(req, max_file_size).await {
+			Ok(f) => (json!({}), f),
+			Err(e) => return set_error(response, &e)
+		}
+	} else {
+		match collect_body(req).await {
+			Ok(b) => (b, Vec::new()),
+			Err(e) => return set_error(response, &e)
+		}
+	};
+	if let Value::Object(m) = &mut body {
+		for (k, v) in query {
+			m.entry(k).or_insert(v);
+		}
+	}
+
+	let cfg_before = match method {
+		Method::GET => None,
+		_ => Some(cfg_snapshot(&d).await)
+	};
+
+	let result = match (&method, path.as_str()) {
+		(&Method::GET, "config") => lapi_config_get(d.clone(), &cred, body).await,
+		(&Method::PUT, "config") => config_update(d.clone(), &cred, body, version, true).await,
+		(&Method::PATCH, "config") => config_update(d.clone(), &cred, body, version, false).await,
+		(&Method::GET, "state") => state_get(d.clone(), &cred).await,
+		(&Method::GET, "text") => text_get(d.clone(), &cred).await,
+		(&Method::PUT, "text") => text_set(d.clone(), &cred, body).await,
+		(&Method::POST, "pause") => lapi_pause(d.clone(), &cred, body).await,
+		(&Method::POST, "resume") => lapi_resume(d.clone(), &cred, body).await,
+		(&Method::GET, "playlist") => lapi_playlist_get(d.clone(), &cred, body).await,
+		(&Method::PUT, "playlist") => lapi_playlist_set(d.clone(), &cred, json!({ "playlist": body })).await,
+		(&Method::POST, "playlist/next") => lapi_playlist_next(d.clone(), &cred, body).await,
+		(&Method::POST, "playlist/jump") => lapi_playlist_jump(d.clone(), &cred, body).await,
+		(&Method::GET, "schedule") => lapi_schedule_get(d.clone(), &cred, body).await,
+		(&Method::PUT, "schedule") => lapi_schedule_set(d.clone(), &cred, json!({ "schedule": body })).await,
+		(&Method::GET, "schedule/now") => lapi_schedule_now(d.clone(), &cred, body).await,
+		(&Method::PUT, "password") => lapi_password_change(d.clone(), &cred, body).await,
+		(&Method::GET, "whoami") => lapi_whoami(d.clone(), &cred, body).await,
+		(&Method::GET, "users") => lapi_user_list(d.clone(), &cred, body).await,
+		(&Method::POST, "users") => lapi_user_set(d.clone(), &cred, body).await,
+		(&Method::PUT, p) if p.starts_with("users/") => match resource_id(p, "users/") {
+			Ok(name) => {
+				let mut params = body;
+				params["username"] = json!(name);
+				lapi_user_set(d.clone(), &cred, params).await
+			},
+			Err(e) => Err(e)
+		},
+		(&Method::DELETE, p) if p.starts_with("users/") => match resource_id(p, "users/") {
+			Ok(name) => lapi_user_delete(d.clone(), &cred, json!({ "username": name })).await,
+			Err(e) => Err(e)
+		},
+		(&Method::GET, "audit") => lapi_audit_get(d.clone(), &cred, body).await,
+		(&Method::GET, "fonts") => lapi_font_list(d.clone(), &cred, body).await,
+		(&Method::POST, "fonts") => match body.get("name").and_then(Value::as_str) {
+			Some(name) => font_upload(d.clone(), &cred, name, &file).await,
+			None => Err(HTTPAPIError::BadRequest{description: String::from("The file name is missing, add ?name=<file> to the URL")})
+		},
+		(&Method::DELETE, p) if p.starts_with("fonts/") => match resource_id(p, "fonts/") {
+			Ok(file) => lapi_font_delete(d.clone(), &cred, json!({ "file": file })).await,
+			Err(e) => Err(e)
+		},
+		(&Method::GET, "images") => lapi_image_list(d.clone(), &cred, body).await,
+		(&Method::POST, "images") => match body.get("name").and_then(Value::as_str) {
+			Some(name) => image_upload(d.clone(), &cred, name, &file).await,
+			None => Err(HTTPAPIError::BadRequest{description: String::from("The file name is missing, add ?name=<file> to the URL")})
+		},
+		(&Method::DELETE, p) if p.starts_with("images/") => match resource_id(p, "images/") {
+			Ok(file) => lapi_image_delete(d.clone(), &cred, json!({ "file": file })).await,
+			Err(e) => Err(e)
+		},
+		(&Method::GET, "sessions") => lapi_session_list(d.clone(), &cred, body).await,
+		(&Method::DELETE, p) if p.starts_with("sessions/") => match resource_id(p, "sessions/") {
+			Ok(id) => lapi_session_revoke(d.clone(), &cred, json!({ "id": id })).await,
+			Err(e) => Err(e)
+		},
+		(&Method::GET, "tokens") => lapi_token_list(d.clone(), &cred, body).await,
+		(&Method::POST, "tokens") => lapi_token_create(d.clone(), &cred, body).await,
+		(&Method::DELETE, p) if p.starts_with("tokens/") => match resource_id(p, "tokens/") {
+			Ok(id) => lapi_token_revoke(d.clone(), &cred, json!({ "id": id })).await,
+			Err(e) => Err(e)
+		},
+		(_, p) => match ROUTES.iter().find(|(r, _)| *r == p) {
+			Some((_, allow)) => Err(HTTPAPIError::MethodNotAllowed{allow}),
+			None if p.starts_with("tokens/") => Err(HTTPAPIError::MethodNotAllowed{allow: "DELETE"}),
+			None if p.starts_with("users/") => Err(HTTPAPIError::MethodNotAllowed{allow: "PUT, DELETE"}),
+			None if p.starts_with("sessions/") => Err(HTTPAPIError::MethodNotAllowed{allow: "DELETE"}),
+			None if p.starts_with("fonts/") => Err(HTTPAPIError::MethodNotAllowed{allow: "DELETE"}),
+			None if p.starts_with("images/") => Err(HTTPAPIError::MethodNotAllowed{allow: "DELETE"}),
+			None => Err(HTTPAPIError::NotFound{description: format!("No such resource {}{}", API_PREFIX, p)})
+		}
+	};
+
+	if let Some(before) = cfg_before {
+		config_changed(&d, before, &cred, &format!("{} {}{}", method, API_PREFIX, path), client_addr.ip()).await;
+	}
+
+	match result {
+		Ok(v) => {
+			if path == "config" {
+				if let Some(version) = v.get("version").and_then(Value::as_u64) {
+					response.headers_mut().insert("ETag", HeaderValue::from_str(&format!("\"{}\"", version)).unwrap());
+				}
+			}
+			let status = if method == Method::POST && (path == "tokens" || path == "users" || path == "fonts" || path == "images") {
+				StatusCode::CREATED
+			} else {
+				StatusCode::OK
+			};
+			set_json_body(response, status, &v);
+		},
+		Err(e) => set_error(response, &e)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn resource_ids_are_decoded() {
+		assert_eq!(resource_id("users/john", "users/").unwrap(), "john");
+		assert_eq!(resource_id("users/john%20doe", "users/").unwrap(), "john doe");
+		assert_eq!(resource_id("fonts/Caf%C3%A9.ttf", "fonts/").unwrap(), "Café.ttf");
+	}
+
+	#[test]
+	fn resource_ids_are_single_segments() {
+		assert!(resource_id("users/", "users/").is_err());
+		assert!(resource_id("fonts/a/b.ttf", "fonts/").is_err());
+		assert!(resource_id("images/..%2Fcfg.json", "images/").is_err());
+		assert!(resource_id("images/%2F", "images/").is_err());
+		assert!(resource_id("tokens/%FF", "tokens/").is_err());
+	}
+}
@@ -0,0 +1,290 @@
+/* Maps the path of a request to a file of the web root, on disk or
+ * embedded.  The path is decoded before it is checked, and a file on disk
+ * must still be inside the root once the symbolic links are resolved */
+
+use std::ffi::OsStr;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+use percent_encoding::percent_decode_str;
+use tokio::fs;
+
+use crate::webroot::{self, WebRoot};
+
+#[derive(Debug, PartialEq)]
+pub enum StaticFileError {
+	NotFound,
+	/* The path tries to leave the web root */
+	Forbidden
+}
+
+/* A file of the web interface, ready to be sent */
+pub struct StaticFile {
+	pub content_type: &'static str,
+	pub contents: Vec<u8>,
+	/* For Last-Modified */
+	pub modified: Option<SystemTime>
+}
+
+const INDEX_FILE: &str = "index.html";
+/* Directory of runtime-data holding the embedded web interface */
+const EMBEDDED_HTML_DIR: &str = "html";
+
+/* Content types by file extension, text with its charset */
+const MIME_TYPES: &[(&str, &str)] = &[
+	("html", "text/html; charset=utf-8"),
+	("htm", "text/html; charset=utf-8"),
+	("css", "text/css; charset=utf-8"),
+	("js", "text/javascript; charset=utf-8"),
+	("mjs", "text/javascript; charset=utf-8"),
+	("json", "application/json"),
+	("map", "application/json"),
+	("txt", "text/plain; charset=utf-8"),
+	("md", "text/markdown; charset=utf-8"),
+	("csv", "text/csv; charset=utf-8"),
+	("xml", "application/xml"),
+	("svg", "image/svg+xml"),
+	("png", "image/png"),
+	("jpg", "image/jpeg"),
+	("jpeg", "image/jpeg"),
+	("gif", "image/gif"),
+	("webp", "image/webp"),
+	("avif", "image/avif"),
+	("bmp", "image/bmp"),
+	("ico", "image/x-icon"),
+	("woff", "font/woff"),
+	("woff2", "font/woff2"),
+	("ttf", "font/ttf"),
+	("otf", "font/otf"),
+	("eot", "application/vnd.ms-fontobject"),
+	("pdf", "application/pdf"),
+	("wasm", "application/wasm"),
+	("mp3", "audio/mpeg"),
+	("ogg", "audio/ogg"),
+	("wav", "audio/wav"),
+	("mp4", "video/mp4"),
+	("webm", "video/webm")
+];
+
+pub fn content_type(filename: &Path) -> &'static str {
+	let ext = filename.extension().and_then(OsStr::to_str).unwrap_or_default().to_ascii_lowercase();
+	MIME_TYPES.iter()
+		.find(|(e, _)| *e == ext)
+		.map(|(_, t)| *t)
+		.unwrap_or("application/octet-stream")
+}
+
+/* The embedded files are as old as the executable */
+fn embedded_modified() -> Option<SystemTime> {
+	static MODIFIED: OnceLock<Option<SystemTime>> = OnceLock::new();
+	*MODIFIED.get_or_init(|| {
+		std::env::current_exe().and_then(std::fs::metadata).and_then(|m| m.modified()).ok()
+	})
+}
+
+/* The request path as the file system will see it */
+pub fn decode_request_path(uri_path: &str) -> Option<String> {
+	percent_decode_str(uri_path).decode_utf8().ok().map(|p| p.into_owned())
+}
+
+/* The names in the request path, refusing the ones going out of the root */
+fn request_segments(uri_path: &str) -> Result<Vec<String>, StaticFileError> {
+	let path = decode_request_path(uri_path).ok_or(StaticFileError::NotFound)?;
+	if path.contains('\0') || path.contains('\\') {
+		return Err(StaticFileError::Forbidden);
+	}
+
+	let mut segments = Vec::new();
+	for segment in path.split('/').filter(|s| !s.is_empty() && *s != ".") {
+		/* Also .. and the hidden files */
+		if segment.starts_with('.') {
+			return Err(StaticFileError::Forbidden);
+		}
+		segments.push(segment.to_string());
+	}
+	Ok(segments)
+}
+
+pub async fn resolve_static_path(root: &Path, uri_path: &str) -> Result<PathBuf, StaticFileError> {
+	let mut filename = root.to_path_buf();
+	filename.extend(request_segments(uri_path)?);
+
+	let canonical_root = fs::canonicalize(root).await.map_err(|_| StaticFileError::NotFound)?;
+	let mut canonical = fs::canonicalize(&filename).await.map_err(|e| match e.kind() {
+		ErrorKind::PermissionDenied => StaticFileError::Forbidden,
+		_ => StaticFileError::NotFound
+	})?;
+	if !canonical.starts_with(&canonical_root) {
+		return Err(StaticFileError::Forbidden);
+	}
+
+	if fs::metadata(&canonical).await.is_ok_and(|m| m.is_dir()) {
+		canonical.push(INDEX_FILE);
+	}
+	match fs::metadata(&canonical).await {
+		Ok(m) if m.is_file() => Ok(canonical),
+		_ => Err(StaticFileError::NotFound)
+	}
+}
+
+/* Name in runtime-data and contents of an embedded file */
+pub fn resolve_embedded(uri_path: &str) -> Result<(String, &'static [u8]), StaticFileError> {
+	let mut segments = request_segments(uri_path)?;
+	segments.insert(0, String::from(EMBEDDED_HTML_DIR));
+	if webroot::embedded_dir_exists(&segments.join("/")) {
+		segments.push(String::from(INDEX_FILE));
+	}
+	let name = segments.join("/");
+	match webroot::embedded_file(&name) {
+		Some(contents) => Ok((name, contents)),
+		None => Err(StaticFileError::NotFound)
+	}
+}
+
+/* The file of the web interface at uri_path */
+pub async fn read_static_file(root: &WebRoot, uri_path: &str) -> Result<StaticFile, StaticFileError> {
+	match root {
+		WebRoot::Dir(dir) => {
+			let filename = resolve_static_path(dir, uri_path).await?;
+			match fs::read(&filename).await {
+				Ok(contents) => Ok(StaticFile {
+					content_type: content_type(&filename),
+					contents,
+					modified: fs::metadata(&filename).await.and_then(|m| m.modified()).ok()
+				}),
+				Err(e) => {
+					println!("Unable to read file {}: {}", filename.display(), e);
+					Err(StaticFileError::NotFound)
+				}
+			}
+		}
+		WebRoot::Embedded => {
+			let (name, contents) = resolve_embedded(uri_path)?;
+			Ok(StaticFile {
+				content_type: content_type(Path::new(&name)),
+				contents: contents.to_vec(),
+				modified: embedded_modified()
+			})
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+	/* A web root with index.html and css/style.css, next to secret.txt */
+	struct TestRoot {
+		base: PathBuf,
+		root: PathBuf
+	}
+
+	impl TestRoot {
+		fn new() -> TestRoot {
+			let base = std::env::temp_dir().join(format!("sts-static-{}-{}", std::process::id(), NEXT_DIR.fetch_add(1, Ordering::SeqCst)));
+			let root = base.join("html");
+			std::fs::create_dir_all(root.join("css")).unwrap();
+			std::fs::write(root.join("index.html"), "index").unwrap();
+			std::fs::write(root.join("css").join("style.css"), "style").unwrap();
+			std::fs::write(root.join(".hidden"), "hidden").unwrap();
+			std::fs::write(base.join("secret.txt"), "secret").unwrap();
+			TestRoot { base, root }
+		}
+	}
+
+	impl Drop for TestRoot {
+		fn drop(&mut self) {
+			let _ = std::fs::remove_dir_all(&self.base);
+		}
+	}
+
+	async fn resolve(t: &TestRoot, path: &str) -> Result<PathBuf, StaticFileError> {
+		resolve_static_path(&t.root, path).await
+	}
+
+	#[tokio::test]
+	async fn serves_files_inside_the_root() {
+		let t = TestRoot::new();
+		let root = std::fs::canonicalize(&t.root).unwrap();
+		assert_eq!(resolve(&t, "/index.html").await, Ok(root.join("index.html")));
+		assert_eq!(resolve(&t, "/css/style.css").await, Ok(root.join("css/style.css")));
+		assert_eq!(resolve(&t, "/css/./style.css").await, Ok(root.join("css/style.css")));
+		assert_eq!(resolve(&t, "//css//style.css").await, Ok(root.join("css/style.css")));
+		assert_eq!(resolve(&t, "/%63ss/style.css").await, Ok(root.join("css/style.css")));
+	}
+
+	#[tokio::test]
+	async fn directories_serve_their_index() {
+		let t = TestRoot::new();
+		let root = std::fs::canonicalize(&t.root).unwrap();
+		assert_eq!(resolve(&t, "/").await, Ok(root.join("index.html")));
+		assert_eq!(resolve(&t, "/css/").await, Err(StaticFileError::NotFound));
+	}
+
+	#[tokio::test]
+	async fn missing_files_are_not_found() {
+		let t = TestRoot::new();
+		assert_eq!(resolve(&t, "/missing.html").await, Err(StaticFileError::NotFound));
+		assert_eq!(resolve(&t, "/css/missing/style.css").await, Err(StaticFileError::NotFound));
+		/* Invalid UTF-8 */
+		assert_eq!(resolve(&t, "/%ff.html").await, Err(StaticFileError::NotFound));
+	}
+
+	#[tokio::test]
+	async fn rejects_traversal() {
+		let t = TestRoot::new();
+		assert_eq!(resolve(&t, "/../secret.txt").await, Err(StaticFileError::Forbidden));
+		assert_eq!(resolve(&t, "/css/../../secret.txt").await, Err(StaticFileError::Forbidden));
+		assert_eq!(resolve(&t, "/css/../index.html").await, Err(StaticFileError::Forbidden));
+		assert_eq!(resolve(&t, "..").await, Err(StaticFileError::Forbidden));
+	}
+
+	#[tokio::test]
+	async fn rejects_encoded_traversal() {
+		let t = TestRoot::new();
+		assert_eq!(resolve(&t, "/%2e%2e/secret.txt").await, Err(StaticFileError::Forbidden));
+		assert_eq!(resolve(&t, "/%2E%2E%2Fsecret.txt").await, Err(StaticFileError::Forbidden));
+		assert_eq!(resolve(&t, "/css%2f..%2f..%2fsecret.txt").await, Err(StaticFileError::Forbidden));
+		assert_eq!(resolve(&t, "/..%5csecret.txt").await, Err(StaticFileError::Forbidden));
+		assert_eq!(resolve(&t, "/index.html%00.css").await, Err(StaticFileError::Forbidden));
+		/* Decoded only once: the file name is "%2e%2e" */
+		assert_eq!(resolve(&t, "/%252e%252e/secret.txt").await, Err(StaticFileError::NotFound));
+	}
+
+	#[tokio::test]
+	async fn rejects_hidden_files() {
+		let t = TestRoot::new();
+		assert_eq!(resolve(&t, "/.hidden").await, Err(StaticFileError::Forbidden));
+		assert_eq!(resolve(&t, "/%2ehidden").await, Err(StaticFileError::Forbidden));
+	}
+
+	#[cfg(feature = "embedded-ui")]
+	#[test]
+	fn embedded_files_stay_in_html() {
+		assert_eq!(resolve_embedded("/").map(|f| f.0), Ok(String::from("html/index.html")));
+		assert_eq!(resolve_embedded("/auth.html").map(|f| f.0), Ok(String::from("html/auth.html")));
+		assert_eq!(resolve_embedded("/missing.html").map(|f| f.0), Err(StaticFileError::NotFound));
+		assert_eq!(resolve_embedded("/../icons/main_icon.png").map(|f| f.0), Err(StaticFileError::Forbidden));
+		assert_eq!(resolve_embedded("/%2e%2e/icons/main_icon.png").map(|f| f.0), Err(StaticFileError::Forbidden));
+		/* The icon is there, but not under html */
+		assert_eq!(resolve_embedded("/icons/main_icon.png").map(|f| f.0), Err(StaticFileError::NotFound));
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn rejects_symlink_escapes() {
+		let t = TestRoot::new();
+		std::os::unix::fs::symlink(t.base.join("secret.txt"), t.root.join("secret.txt")).unwrap();
+		std::os::unix::fs::symlink(&t.base, t.root.join("up")).unwrap();
+		std::os::unix::fs::symlink(t.root.join("css"), t.root.join("styles")).unwrap();
+		assert_eq!(resolve(&t, "/secret.txt").await, Err(StaticFileError::Forbidden));
+		assert_eq!(resolve(&t, "/up/secret.txt").await, Err(StaticFileError::Forbidden));
+		/* Links staying inside the root are fine */
+		let root = std::fs::canonicalize(&t.root).unwrap();
+		assert_eq!(resolve(&t, "/styles/style.css").await, Ok(root.join("css/style.css")));
+	}
+}
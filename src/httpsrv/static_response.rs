@@ -0,0 +1,386 @@
+/* The response for a file of the web interface: validators and 304 Not
+ * Modified, gzip or brotli compression, and byte ranges.  All of it to
+ * keep the control panel usable over slow links */
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::{Body, Request, Response, StatusCode};
+use hyper::header::{self, HeaderMap, HeaderValue};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use super::static_files::StaticFile;
+
+/* Smaller files are not worth compressing */
+const MIN_COMPRESS_SIZE: usize = 1024;
+/* The files are compressed once and then cached */
+const BROTLI_QUALITY: u32 = 9;
+const BROTLI_WINDOW: u32 = 22;
+const COMPRESSED_CACHE_MAX: usize = 128;
+/* Fonts and images do not change, the rest changes with the application */
+const LONG_CACHE: &str = "public, max-age=604800";
+const REVALIDATE: &str = "no-cache";
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Encoding {
+	Brotli,
+	Gzip
+}
+
+impl Encoding {
+	fn name(&self) -> &'static str {
+		match self {
+			Encoding::Brotli => "br",
+			Encoding::Gzip => "gzip"
+		}
+	}
+}
+
+/* Compressed files by ETag */
+#[derive(Default)]
+pub struct CompressedCache {
+	entries: HashMap<(String, Encoding), Arc<Vec<u8>>>
+}
+
+#[derive(PartialEq, Debug)]
+enum RangeRequest {
+	Full,
+	/* First and last byte */
+	Partial(u64, u64),
+	Unsatisfiable
+}
+
+fn compressible(content_type: &str) -> bool {
+	content_type.starts_with("text/") ||
+		["javascript", "json", "xml", "font/ttf", "font/otf", "vnd.ms-fontobject"].iter().any(|t| content_type.contains(t))
+}
+
+fn cache_control(content_type: &str) -> &'static str {
+	if content_type.starts_with("font/") || content_type.starts_with("image/") || content_type.contains("fontobject") {
+		LONG_CACHE
+	} else {
+		REVALIDATE
+	}
+}
+
+/* The encoding with the highest q value, brotli when they are equal */
+fn preferred_encoding(headers: &HeaderMap) -> Option<Encoding> {
+	let accept = headers.get(header::ACCEPT_ENCODING)?.to_str().ok()?;
+	let mut best: Option<(Encoding, f32)> = None;
+	for part in accept.split(',') {
+		let mut params = part.split(';');
+		let enc = match params.next().unwrap_or_default().trim().to_ascii_lowercase().as_str() {
+			"br" => Encoding::Brotli,
+			"gzip" | "x-gzip" => Encoding::Gzip,
+			_ => continue
+		};
+		let q = params
+			.find_map(|p| p.trim().strip_prefix("q="))
+			.and_then(|q| q.trim().parse::<f32>().ok())
+			.unwrap_or(1.0);
+		if q > 0.0 && best.is_none_or(|(_, bq)| q > bq || (q == bq && enc == Encoding::Brotli)) {
+			best = Some((enc, q));
+		}
+	}
+	best.map(|(enc, _)| enc)
+}
+
+fn compress(data: &[u8], enc: Encoding) -> std::io::Result<Vec<u8>> {
+	match enc {
+		Encoding::Gzip => {
+			let mut e = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+			e.write_all(data)?;
+			e.finish()
+		}
+		Encoding::Brotli => {
+			let mut out = Vec::new();
+			{
+				let mut w = brotli::CompressorWriter::new(&mut out, 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+				w.write_all(data)?;
+			}
+			Ok(out)
+		}
+	}
+}
+
+async fn compressed(cache: &Mutex<CompressedCache>, etag: &str, contents: &[u8], enc: Encoding) -> Option<Arc<Vec<u8>>> {
+	let key = (etag.to_string(), enc);
+	if let Some(c) = cache.lock().await.entries.get(&key) {
+		return Some(c.clone());
+	}
+
+	let data = contents.to_vec();
+	let c = match tokio::task::spawn_blocking(move || compress(&data, enc)).await {
+		Ok(Ok(c)) => Arc::new(c),
+		_ => return None
+	};
+	let mut cache = cache.lock().await;
+	/* Old versions of the files, while working on the web interface */
+	if cache.entries.len() >= COMPRESSED_CACHE_MAX {
+		cache.entries.clear();
+	}
+	cache.entries.insert(key, c.clone());
+	Some(c)
+}
+
+/* The ETags of the compressed versions have the encoding appended */
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+	let hash = etag.trim_matches('"');
+	if_none_match.split(',').map(str::trim).any(|t| {
+		let t = t.strip_prefix("W/").unwrap_or(t).trim_matches('"');
+		t == "*" || t == hash || t.strip_suffix("-br").or_else(|| t.strip_suffix("-gzip")) == Some(hash)
+	})
+}
+
+/* Dates in HTTP have no fractions of second */
+fn truncate_to_secs(t: SystemTime) -> SystemTime {
+	let secs = t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+	UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn not_modified(headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+	/* If-None-Match wins over If-Modified-Since */
+	if let Some(inm) = headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+		return etag_matches(inm, etag);
+	}
+	let since = headers.get(header::IF_MODIFIED_SINCE)
+		.and_then(|v| v.to_str().ok())
+		.and_then(|v| httpdate::parse_http_date(v).ok());
+	match (since, modified) {
+		(Some(since), Some(modified)) => truncate_to_secs(modified) <= since,
+		_ => false
+	}
+}
+
+/* A single range, more ranges in a request get the whole file */
+fn parse_range(range: &str, len: u64) -> RangeRequest {
+	let Some(spec) = range.trim().strip_prefix("bytes=") else {
+		return RangeRequest::Full;
+	};
+	let Some((first, last)) = spec.split_once('-') else {
+		return RangeRequest::Full;
+	};
+	if spec.contains(',') {
+		return RangeRequest::Full;
+	}
+	let (first, last) = (first.trim(), last.trim());
+
+	if first.is_empty() {
+		/* The last bytes */
+		return match last.parse::<u64>() {
+			Ok(n) if n > 0 && len > 0 => RangeRequest::Partial(len.saturating_sub(n), len - 1),
+			Ok(_) => RangeRequest::Unsatisfiable,
+			Err(_) => RangeRequest::Full
+		};
+	}
+	let Ok(first) = first.parse::<u64>() else {
+		return RangeRequest::Full;
+	};
+	let last = if last.is_empty() {
+		u64::MAX
+	} else {
+		match last.parse::<u64>() {
+			Ok(l) if l >= first => l,
+			_ => return RangeRequest::Full
+		}
+	};
+	if first >= len {
+		RangeRequest::Unsatisfiable
+	} else {
+		RangeRequest::Partial(first, last.min(len - 1))
+	}
+}
+
+/* If-Range holds the ETag or the date the client has */
+fn if_range_matches(headers: &HeaderMap, etag: &str, last_modified: Option<&str>) -> bool {
+	match headers.get(header::IF_RANGE).and_then(|v| v.to_str().ok()) {
+		Some(v) => v == etag || Some(v) == last_modified,
+		None => true
+	}
+}
+
+fn insert_header(response: &mut Response<Body>, name: header::HeaderName, value: &str) {
+	if let Ok(v) = HeaderValue::from_str(value) {
+		response.headers_mut().insert(name, v);
+	}
+}
+
+pub async fn static_file_response(req: &Request<Body>, file: StaticFile, cache: &Mutex<CompressedCache>, response: &mut Response<Body>) {
+	let hash = Sha256::digest(&file.contents);
+	let hex: String = hash.iter().take(16).map(|b| format!("{:02x}", b)).collect();
+	let etag = format!("\"{}\"", hex);
+	let last_modified = file.modified.map(httpdate::fmt_http_date);
+	let can_compress = compressible(file.content_type) && file.contents.len() >= MIN_COMPRESS_SIZE;
+
+	response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static(file.content_type));
+	response.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control(file.content_type)));
+	response.headers_mut().insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+	if can_compress {
+		response.headers_mut().insert(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+	}
+	if let Some(lm) = &last_modified {
+		insert_header(response, header::LAST_MODIFIED, lm);
+	}
+
+	if not_modified(req.headers(), &etag, file.modified) {
+		insert_header(response, header::ETAG, &etag);
+		*response.status_mut() = StatusCode::NOT_MODIFIED;
+		return;
+	}
+
+	/* The ranges are of the uncompressed file */
+	let len = file.contents.len() as u64;
+	let range = match req.headers().get(header::RANGE).and_then(|v| v.to_str().ok()) {
+		Some(r) if if_range_matches(req.headers(), &etag, last_modified.as_deref()) => parse_range(r, len),
+		_ => RangeRequest::Full
+	};
+	match range {
+		RangeRequest::Partial(first, last) => {
+			insert_header(response, header::ETAG, &etag);
+			insert_header(response, header::CONTENT_RANGE, &format!("bytes {}-{}/{}", first, last, len));
+			*response.status_mut() = StatusCode::PARTIAL_CONTENT;
+			*response.body_mut() = Body::from(file.contents[first as usize..=last as usize].to_vec());
+			return;
+		}
+		RangeRequest::Unsatisfiable => {
+			insert_header(response, header::CONTENT_RANGE, &format!("bytes */{}", len));
+			*response.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+			return;
+		}
+		RangeRequest::Full => ()
+	}
+
+	if can_compress {
+		if let Some(enc) = preferred_encoding(req.headers()) {
+			if let Some(c) = compressed(cache, &etag, &file.contents, enc).await {
+				insert_header(response, header::ETAG, &format!("\"{}-{}\"", hex, enc.name()));
+				response.headers_mut().insert(header::CONTENT_ENCODING, HeaderValue::from_static(enc.name()));
+				*response.body_mut() = Body::from(c.as_ref().clone());
+				return;
+			}
+		}
+	}
+	insert_header(response, header::ETAG, &etag);
+	*response.body_mut() = Body::from(file.contents);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn headers(list: &[(header::HeaderName, &str)]) -> HeaderMap {
+		let mut h = HeaderMap::new();
+		for (name, value) in list {
+			h.insert(name.clone(), HeaderValue::from_str(value).unwrap());
+		}
+		h
+	}
+
+	#[test]
+	fn ranges() {
+		assert_eq!(parse_range("bytes=0-9", 100), RangeRequest::Partial(0, 9));
+		assert_eq!(parse_range("bytes=90-200", 100), RangeRequest::Partial(90, 99));
+		assert_eq!(parse_range("bytes=10-", 100), RangeRequest::Partial(10, 99));
+		/* Suffix ranges: the last bytes, all of them when longer */
+		assert_eq!(parse_range("bytes=-10", 100), RangeRequest::Partial(90, 99));
+		assert_eq!(parse_range("bytes=-500", 100), RangeRequest::Partial(0, 99));
+		assert_eq!(parse_range("bytes=-0", 100), RangeRequest::Unsatisfiable);
+		assert_eq!(parse_range("bytes=-5", 0), RangeRequest::Unsatisfiable);
+		assert_eq!(parse_range("bytes=100-", 100), RangeRequest::Unsatisfiable);
+		assert_eq!(parse_range("bytes=150-200", 100), RangeRequest::Unsatisfiable);
+	}
+
+	#[test]
+	fn ranges_served_as_the_whole_file() {
+		assert_eq!(parse_range("bytes=0-9,20-29", 100), RangeRequest::Full);
+		assert_eq!(parse_range("bytes=9-0", 100), RangeRequest::Full);
+		assert_eq!(parse_range("items=0-9", 100), RangeRequest::Full);
+		assert_eq!(parse_range("bytes=a-9", 100), RangeRequest::Full);
+		assert_eq!(parse_range("bytes=10", 100), RangeRequest::Full);
+	}
+
+	#[test]
+	fn encodings() {
+		let pref = |v: &str| preferred_encoding(&headers(&[(header::ACCEPT_ENCODING, v)]));
+		assert_eq!(preferred_encoding(&HeaderMap::new()), None);
+		assert_eq!(pref("gzip, deflate"), Some(Encoding::Gzip));
+		assert_eq!(pref("gzip, deflate, br"), Some(Encoding::Brotli));
+		/* Equal q values: brotli, whatever the order */
+		assert_eq!(pref("br;q=0.5, gzip;q=0.5"), Some(Encoding::Brotli));
+		assert_eq!(pref("gzip;q=0.5, br;q=0.5"), Some(Encoding::Brotli));
+		assert_eq!(pref("br;q=0.4, gzip;q=0.8"), Some(Encoding::Gzip));
+		/* q=0 refuses the encoding */
+		assert_eq!(pref("br;q=0, gzip"), Some(Encoding::Gzip));
+		assert_eq!(pref("br;q=0, gzip;q=0"), None);
+		assert_eq!(pref("identity"), None);
+	}
+
+	#[test]
+	fn etags() {
+		let etag = "\"0123abcd\"";
+		assert!(etag_matches("\"0123abcd\"", etag));
+		assert!(etag_matches("W/\"0123abcd\"", etag));
+		assert!(etag_matches("\"other\", \"0123abcd\"", etag));
+		assert!(etag_matches("*", etag));
+		/* The ETags of the compressed responses */
+		assert!(etag_matches("\"0123abcd-br\"", etag));
+		assert!(etag_matches("\"0123abcd-gzip\"", etag));
+		assert!(!etag_matches("\"0123abcd-zstd\"", etag));
+		assert!(!etag_matches("\"other\"", etag));
+	}
+
+	#[test]
+	fn validators() {
+		let etag = "\"0123abcd\"";
+		let modified = UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+		let date = httpdate::fmt_http_date(modified);
+		let earlier = httpdate::fmt_http_date(modified - Duration::from_secs(10));
+
+		assert!(!not_modified(&HeaderMap::new(), etag, Some(modified)));
+		assert!(not_modified(&headers(&[(header::IF_NONE_MATCH, etag)]), etag, Some(modified)));
+		/* The fraction of second of the file is not in the date */
+		assert!(not_modified(&headers(&[(header::IF_MODIFIED_SINCE, &date)]), etag, Some(modified)));
+		assert!(!not_modified(&headers(&[(header::IF_MODIFIED_SINCE, &earlier)]), etag, Some(modified)));
+		assert!(!not_modified(&headers(&[(header::IF_MODIFIED_SINCE, &date)]), etag, None));
+		/* If-None-Match wins */
+		assert!(!not_modified(&headers(&[(header::IF_NONE_MATCH, "\"other\""), (header::IF_MODIFIED_SINCE, &date)]), etag, Some(modified)));
+
+		assert!(if_range_matches(&HeaderMap::new(), etag, Some(&date)));
+		assert!(if_range_matches(&headers(&[(header::IF_RANGE, etag)]), etag, Some(&date)));
+		assert!(if_range_matches(&headers(&[(header::IF_RANGE, &date)]), etag, Some(&date)));
+		assert!(!if_range_matches(&headers(&[(header::IF_RANGE, "\"other\"")]), etag, Some(&date)));
+		assert!(!if_range_matches(&headers(&[(header::IF_RANGE, &earlier)]), etag, None));
+	}
+
+	#[tokio::test]
+	async fn compressed_responses_have_their_own_etag() {
+		let cache = Mutex::new(CompressedCache::default());
+		let file = || StaticFile { content_type: "text/css", contents: "body { margin: 0 }\n".repeat(100).into_bytes(), modified: None };
+		let get = |h: &[(header::HeaderName, &str)]| {
+			let mut req = Request::new(Body::empty());
+			*req.headers_mut() = headers(h);
+			req
+		};
+
+		let mut response = Response::new(Body::empty());
+		static_file_response(&get(&[(header::ACCEPT_ENCODING, "gzip, br")]), file(), &cache, &mut response).await;
+		assert_eq!(response.headers()[header::CONTENT_ENCODING], "br");
+		let etag = response.headers()[header::ETAG].to_str().unwrap().to_string();
+		assert!(etag.ends_with("-br\""));
+
+		let mut response = Response::new(Body::empty());
+		static_file_response(&get(&[(header::IF_NONE_MATCH, &etag)]), file(), &cache, &mut response).await;
+		assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+		/* Ranges are of the uncompressed file */
+		let mut response = Response::new(Body::empty());
+		static_file_response(&get(&[(header::ACCEPT_ENCODING, "br"), (header::RANGE, "bytes=0-3")]), file(), &cache, &mut response).await;
+		assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+		assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert_eq!(&body[..], b"body");
+	}
+}
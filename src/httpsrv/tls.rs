@@ -0,0 +1,161 @@
+/* HTTPS: certificates, and the TLS connections handed to the same
+ * servicefn() as the plain HTTP ones */
+
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::{Body, Request, Response, StatusCode};
+use hyper::header::HeaderValue;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+use crate::lconfig::{Config, HttpsConfig};
+
+const SELF_SIGNED_CERT_FILE: &str = "tls-cert.pem";
+const SELF_SIGNED_KEY_FILE: &str = "tls-key.pem";
+/* Clients not completing the handshake in time are dropped */
+const HANDSHAKE_TIMEOUT_SEC: u64 = 10;
+
+/* Added to the requests received over TLS */
+#[derive(Clone, Copy)]
+pub struct TlsConnection;
+
+fn invalid_data(file: &Path, what: &str) -> Error {
+	Error::new(ErrorKind::InvalidData, format!("{}: {}", file.display(), what))
+}
+
+/* Certificate and key to use, generating the self signed ones when
+ * no files are configured */
+async fn certificate_files(https: &HttpsConfig) -> Result<(PathBuf, PathBuf), Error> {
+	if !https.cert_file.is_empty() || !https.key_file.is_empty() {
+		if https.cert_file.is_empty() || https.key_file.is_empty() {
+			return Err(Error::new(ErrorKind::InvalidInput, "https needs both cert_file and key_file"));
+		}
+		return Ok((PathBuf::from(&https.cert_file), PathBuf::from(&https.key_file)));
+	}
+
+	let cfg_file = Config::get_config_file_name(true);
+	let cert_file = cfg_file.with_file_name(SELF_SIGNED_CERT_FILE);
+	let key_file = cfg_file.with_file_name(SELF_SIGNED_KEY_FILE);
+	if fs::metadata(&cert_file).await.is_err() || fs::metadata(&key_file).await.is_err() {
+		generate_self_signed(&cert_file, &key_file).await?;
+	}
+	Ok((cert_file, key_file))
+}
+
+async fn generate_self_signed(cert_file: &Path, key_file: &Path) -> Result<(), Error> {
+	let mut names = vec![String::from("localhost")];
+	if let Ok(h) = fs::read_to_string("/etc/hostname").await {
+		let h = h.trim();
+		if !h.is_empty() {
+			names.push(h.to_string());
+		}
+	}
+	println!("Generating a self signed certificate for {} in {}", names.join(", "), cert_file.display());
+
+	let cert = rcgen::generate_simple_self_signed(names)
+		.map_err(|e| Error::other(format!("Unable to generate the certificate: {}", e)))?;
+	let cert_pem = cert.serialize_pem()
+		.map_err(|e| Error::other(format!("Unable to generate the certificate: {}", e)))?;
+
+	/* Only the owner can read the key */
+	let mut opts = fs::OpenOptions::new();
+	opts.write(true).create(true).truncate(true);
+	#[cfg(unix)]
+	opts.mode(0o600);
+	let mut f = opts.open(key_file).await?;
+	f.write_all(cert.serialize_private_key_pem().as_bytes()).await?;
+	fs::write(cert_file, cert_pem).await
+}
+
+pub async fn load_tls_acceptor(https: &HttpsConfig) -> Result<TlsAcceptor, Error> {
+	let (cert_file, key_file) = certificate_files(https).await?;
+
+	let pem = fs::read(&cert_file).await?;
+	let certs: Vec<Certificate> = rustls_pemfile::certs(&mut pem.as_slice())?
+		.into_iter()
+		.map(Certificate)
+		.collect();
+	if certs.is_empty() {
+		return Err(invalid_data(&cert_file, "no certificate found"));
+	}
+
+	let pem = fs::read(&key_file).await?;
+	let key = rustls_pemfile::read_all(&mut pem.as_slice())?
+		.into_iter()
+		.find_map(|item| match item {
+			rustls_pemfile::Item::PKCS8Key(k) | rustls_pemfile::Item::RSAKey(k) | rustls_pemfile::Item::ECKey(k) => Some(PrivateKey(k)),
+			_ => None
+		})
+		.ok_or_else(|| invalid_data(&key_file, "no private key found"))?;
+
+	let mut config = ServerConfig::builder()
+		.with_safe_defaults()
+		.with_no_client_auth()
+		.with_single_cert(certs, key)
+		.map_err(|e| invalid_data(&cert_file, &e.to_string()))?;
+	config.alpn_protocols = vec![b"http/1.1".to_vec()];
+	Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/* Connections of listener after the TLS handshake.  The handshakes run in
+ * their own tasks, so a slow client does not hold up the others.  The
+ * returned task accepts the connections until it is aborted */
+pub fn tls_incoming(listener: TcpListener, acceptor: TlsAcceptor) -> (mpsc::Receiver<TlsStream<TcpStream>>, JoinHandle<()>) {
+	let (tx, rx) = mpsc::channel(16);
+	let task = tokio::spawn(async move {
+		loop {
+			let (stream, client_addr) = match listener.accept().await {
+				Ok(c) => c,
+				Err(e) => {
+					/* Usually too many open files, wait for some to be closed */
+					eprintln!("Unable to accept a connection: {}", e);
+					tokio::time::sleep(Duration::from_secs(1)).await;
+					continue;
+				}
+			};
+			let acceptor = acceptor.clone();
+			let tx = tx.clone();
+			tokio::spawn(async move {
+				match tokio::time::timeout(Duration::from_secs(HANDSHAKE_TIMEOUT_SEC), acceptor.accept(stream)).await {
+					Ok(Ok(s)) => {
+						let _ = tx.send(s).await;
+					}
+					Ok(Err(e)) => eprintln!("TLS handshake with {} failed: {}", client_addr, e),
+					Err(_) => eprintln!("TLS handshake with {} timed out", client_addr)
+				}
+			});
+		}
+	});
+	(rx, task)
+}
+
+pub fn redirect_to_https(req: &Request<Body>, https_port: u16) -> Response<Body> {
+	let host = req.headers().get("Host").and_then(|h| h.to_str().ok()).unwrap_or("localhost");
+	/* Drop the port, keeping the brackets of an IPv6 address */
+	let host = match host.rfind(':') {
+		Some(i) if !host[i..].contains(']') => &host[..i],
+		_ => host
+	};
+	let path = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+	let location = if https_port == 443 {
+		format!("https://{}{}", host, path)
+	} else {
+		format!("https://{}:{}{}", host, https_port, path)
+	};
+
+	let mut response = Response::new(Body::from(format!("Moved to {}", location)));
+	*response.status_mut() = StatusCode::MOVED_PERMANENTLY;
+	if let Ok(v) = HeaderValue::from_str(&location) {
+		response.headers_mut().insert("Location", v);
+	}
+	response
+}
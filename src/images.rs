@@ -0,0 +1,117 @@
+/* The pictures uploaded through the web interface, for the background of
+ * the window and the logos shown over it.  They are saved next to the
+ * configuration file and decoded when the display uses them. */
+
+use std::io::{Cursor, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::lconfig::Config;
+
+const USER_IMAGES_DIR: &str = "images";
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "bmp", "webp"];
+pub const MAX_IMAGE_FILE_SIZE: usize = 32 * 1024 * 1024;
+/* Decoded, larger images would take too much memory */
+const MAX_IMAGE_PIXELS: u64 = 64 * 1024 * 1024;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ImageInfo {
+	pub file: String,
+	pub width: u32,
+	pub height: u32,
+	#[serde(skip)]
+	pub path: PathBuf
+}
+
+/* The uploaded images, by file name */
+#[derive(Clone)]
+pub struct ImageCatalog {
+	pub images: Vec<ImageInfo>,
+	/* Changes every time an image is added or removed */
+	pub generation: u64
+}
+
+fn is_image_file(path: &Path) -> bool {
+	path.extension()
+		.and_then(|e| e.to_str())
+		.is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+pub fn user_images_dir() -> PathBuf {
+	Config::get_config_file_name(false).with_file_name(USER_IMAGES_DIR)
+}
+
+impl ImageCatalog {
+	pub fn new() -> ImageCatalog {
+		ImageCatalog { images: Vec::new(), generation: 0 }
+	}
+
+	/* Only the headers of the files are read */
+	pub fn scan() -> ImageCatalog {
+		let mut c = ImageCatalog::new();
+		let Ok(entries) = std::fs::read_dir(user_images_dir()) else {
+			return c;
+		};
+		for path in entries.filter_map(|e| e.ok().map(|e| e.path())) {
+			if !is_image_file(&path) {
+				continue;
+			}
+			if let Ok((width, height)) = image::image_dimensions(&path) {
+				c.images.push(ImageInfo {
+					file: path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+					width,
+					height,
+					path
+				});
+			}
+		}
+		c.images.sort_by(|a, b| a.file.cmp(&b.file));
+		c
+	}
+
+	pub fn find(&self, file_name: &str) -> Option<&ImageInfo> {
+		self.images.iter().find(|i| i.file == file_name)
+	}
+
+	/* Adds an uploaded image, replacing the one with the same file */
+	pub fn add(&mut self, info: ImageInfo) {
+		self.images.retain(|i| i.file != info.file);
+		let pos = self.images.partition_point(|i| i.file < info.file);
+		self.images.insert(pos, info);
+		self.generation += 1;
+	}
+
+	pub fn remove(&mut self, file_name: &str) {
+		self.images.retain(|i| i.file != file_name);
+		self.generation += 1;
+	}
+}
+
+/* Checks an image uploaded by a user, returning where to save it */
+pub fn check_user_image(file_name: &str, data: &[u8]) -> Result<ImageInfo, Error> {
+	let invalid = |msg: &str| Error::new(ErrorKind::InvalidInput, msg.to_string());
+	let path = Path::new(file_name);
+	if file_name.starts_with('.') || path.file_name() != Some(path.as_os_str()) {
+		return Err(invalid("Invalid image file name"));
+	}
+	if !is_image_file(path) {
+		return Err(invalid("Only PNG, JPEG, GIF, BMP and WebP images are supported"));
+	}
+	if data.len() > MAX_IMAGE_FILE_SIZE {
+		return Err(invalid("The image file is too large"));
+	}
+	let (width, height) = image::io::Reader::new(Cursor::new(data))
+		.with_guessed_format().ok()
+		.and_then(|r| r.into_dimensions().ok())
+		.ok_or_else(|| invalid("Not a supported image"))?;
+	if width as u64 * height as u64 > MAX_IMAGE_PIXELS {
+		return Err(invalid("The image has too many pixels"));
+	}
+	Ok(ImageInfo {
+		file: file_name.to_string(),
+		width,
+		height,
+		path: user_images_dir().join(file_name)
+	})
+}
@@ -1,11 +1,16 @@
 use tokio::fs;
 use serde_json::Value;
 use serde_json::json;
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
 use serde::{Deserialize, Serialize};
 use pwhash::bcrypt;
 
 use directories_next::{ProjectDirs};
+use tokio::sync::broadcast;
+
+use crate::webroot::WebRoot;
+use crate::fonts::{FontCatalog, DEFAULT_FONT_WEIGHT, DEFAULT_FALLBACK_FONTS};
+use crate::images::ImageCatalog;
 
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub enum TextScrollOrientation {
@@ -13,29 +18,669 @@ pub enum TextScrollOrientation {
 	ScrollHorizontal
 }
 
+/* How the text of a zone is shown:
+ *   Scroll      scrolls in the direction of the orientation
+ *   Static      centered, with the largest font size fitting the zone
+ *   Bounce      goes back and forth between the margins
+ *   Typewriter  appears one character after the other
+ *   Blink       centered like Static, shown and hidden in turn
+ *   Paging      the lines fitting the zone, a page after the other */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum DisplayMode {
+	Scroll,
+	Static,
+	Bounce,
+	Typewriter,
+	Blink,
+	Paging
+}
+
+/* Alignment of the lines of text.  Auto is left for the left-to-right
+ * paragraphs and right for the right-to-left ones */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum TextAlign {
+	Auto,
+	Left,
+	Center,
+	Right,
+	Justify
+}
+
+/* What is drawn behind the zones */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum BackgroundMode {
+	Color,
+	LinearGradient,
+	RadialGradient,
+	Image
+}
+
+/* How the background image covers the window: Fit shows all of it, Fill
+ * covers the whole window cutting its sides, Tile repeats it unscaled */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ImageScaling {
+	Fit,
+	Fill,
+	Tile
+}
+
+/* A logo drawn over the background and under the text, fitted to rect
+ * keeping its proportions */
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Overlay {
+	pub image: String,
+	pub rect: ZoneRect,
+	/* 0 is transparent, 1 opaque */
+	pub opacity: f32
+}
+
 const DEFAULT_HTTP_PORT: u16 = 3000;
+/* All the IPv4 interfaces */
+const DEFAULT_LISTEN_ADDRESS: &str = "0.0.0.0";
+
+/* Version of the on-disk configuration format. Bump it when the meaning of
+ * an existing field changes, and add the conversion to migrate_cfg_format() */
+const CONFIG_FORMAT_VERSION: u64 = 3;
+
+/* Scroll speed is in pixels per second */
+const DEFAULT_SCROLLSPEED: i16 = 60;
+const MAX_SCROLLSPEED: i16 = 3000;
+
+/* Config format 1 expressed the scroll speed in pixels per frame, and
+ * almost every display was running at this refresh rate */
+const LEGACY_FRAMES_PER_SECOND: i64 = 60;
+
+/* Between the copies of the text of a continuous ticker */
+const DEFAULT_SEPARATOR: &str = " • ";
+
+/* Seconds a page is shown, or the text once typed */
+const DEFAULT_DWELL: f32 = 3.0;
+const MAX_DWELL: f64 = 3600.0;
+/* Characters per second */
+const DEFAULT_TYPESPEED: f32 = 15.0;
+const MAX_TYPESPEED: f64 = 1000.0;
+/* Seconds shown, then hidden */
+const DEFAULT_BLINK_INTERVAL: f32 = 0.5;
+const MIN_BLINK_INTERVAL: f64 = 0.05;
+const MAX_BLINK_INTERVAL: f64 = 60.0;
+
+/* Lines of text an auto-sized vertical zone shows */
+const DEFAULT_AUTOSIZE_LINES: u16 = 3;
+const MAX_AUTOSIZE_LINES: u64 = 100;
+
+const DEFAULT_TRANSITION_TIME: f32 = 1.0;
+const MAX_TRANSITION_TIME: f64 = 10.0;
+
+/* Position and size of a zone, in percent of the window size */
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct ZoneRect {
+	pub x: f32,
+	pub y: f32,
+	pub w: f32,
+	pub h: f32
+}
+
+/* A rectangular area of the screen showing its own scrolling text */
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct ZoneConfig {
+	pub name: String,
+	pub rect: ZoneRect,
+	pub text: String,
+	pub mode: DisplayMode,
+	pub scrollspeed: i16,
+	pub orientation: TextScrollOrientation,
+	/* Paging and Typewriter */
+	pub dwell: f32,
+	/* Typewriter, in characters per second */
+	pub typespeed: f32,
+	/* Blink */
+	pub blinkinterval: f32,
+	pub textcolor: String,
+	/* Empty to show the window background */
+	pub backgroundcolor: String,
+	pub hmargin: u16,
+	pub vmargin: u16,
+	pub fontsize: u16,
+	/* Instead of fontsize, the largest size with which the text fills the
+	 * height of the zone, showing lines lines in vertical orientation */
+	pub autosize: bool,
+	pub lines: u16,
+	/* Empty for the embedded font */
+	pub fontfamily: String,
+	pub fontweight: u16,
+	pub textalign: TextAlign,
+	/* Right-to-left text scrolls horizontally the other way, entering from
+	 * the left side */
+	pub followdirection: bool,
+	/* The text is repeated without gaps, the copies separated by separator
+	 * with spacing pixels around it */
+	pub continuous: bool,
+	pub separator: String,
+	pub spacing: u16
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum PlaylistTransition {
+	Cut,
+	Fade,
+	Slide
+}
+
+/* A message of the playlist.  The optional fields override the style of
+ * the zone showing the playlist while the message is shown */
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct PlaylistItem {
+	pub text: String,
+	/* Seconds the message is shown, 0 to use passes */
+	pub duration: f32,
+	/* Number of times the text scrolls across the zone, 0 to use duration */
+	pub passes: u32,
+	/* Transition from the previous message to this one */
+	pub transition: PlaylistTransition,
+	pub textcolor: Option<String>,
+	pub backgroundcolor: Option<String>,
+	pub fontsize: Option<u16>,
+	pub scrollspeed: Option<i16>,
+	pub orientation: Option<TextScrollOrientation>,
+	pub mode: Option<DisplayMode>
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Playlist {
+	/* Name of the zone showing the playlist, empty for the first zone */
+	pub zone: String,
+	pub items: Vec<PlaylistItem>,
+	/* Length of fade and slide transitions, in seconds */
+	pub transition_time: f32
+}
+
+/* Replaces the display settings while the local time matches all the
+ * conditions.  Empty conditions always match. */
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScheduleRule {
+	pub name: String,
+	/* "Mon", "Tue", ... */
+	pub weekdays: Vec<String>,
+	/* "HH:MM", time_to before time_from spans midnight */
+	pub time_from: String,
+	pub time_to: String,
+	/* "YYYY-MM-DD", both included */
+	pub date_from: String,
+	pub date_to: String,
+	/* Display settings to use, same keys as config_set (disp_*, zones, playlist) */
+	pub content: Value
+}
+
+/* TLS of the web interface.  Without cert_file and key_file a self signed
+ * certificate is generated in the configuration directory */
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct HttpsConfig {
+	pub enabled: bool,
+	/* PEM files */
+	pub cert_file: String,
+	pub key_file: String,
+	/* Plain HTTP port redirecting the browsers to HTTPS, 0 for none */
+	pub redirect_port: u16
+}
+
+#[derive(Clone, Copy)]
+pub enum PlaylistRequest {
+	Next,
+	Jump(usize)
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
 	pub version: u64,
+	pub config_format: u64,
 	pub http_port: u16,
+	/* "0.0.0.0", "::1", "192.168.1.2:8080", "[::]:8080" or "unix:/path".
+	 * Without a port http_port is used */
+	pub listen: Vec<String>,
+	pub https: HttpsConfig,
+	/* Addresses of the reverse proxies whose X-Forwarded-Proto is believed */
+	pub trusted_proxies: Vec<String>,
 	pub disp_text: String,
+	pub disp_mode: DisplayMode,
 	pub disp_scrollspeed: i16,
 	pub disp_orientation: TextScrollOrientation,
+	pub disp_dwell: f32,
+	pub disp_typespeed: f32,
+	pub disp_blinkinterval: f32,
 	pub disp_textcolor: String,
 	pub disp_backgroundcolor: String,
 	pub disp_hmargin: u16,
 	pub disp_vmargin: u16,
 	pub disp_fontsize: u16,
+	pub disp_autosize: bool,
+	pub disp_lines: u16,
+	pub disp_fontfamily: String,
+	pub disp_fontweight: u16,
+	/* Families used for the characters missing from the font of a zone,
+	 * the first one having the character is used */
+	pub fallback_fonts: Vec<String>,
+	pub disp_textalign: TextAlign,
+	pub disp_followdirection: bool,
+	pub disp_continuous: bool,
+	pub disp_separator: String,
+	pub disp_spacing: u16,
+	pub disp_background: BackgroundMode,
+	/* Evenly spaced, the first one at the start of the gradient */
+	pub disp_gradientcolors: Vec<String>,
+	/* Direction of the linear gradient in degrees, 0 is towards the top and
+	 * 90 towards the right */
+	pub disp_gradientangle: f32,
+	/* File name of an uploaded image */
+	pub disp_backgroundimage: String,
+	pub disp_imagescaling: ImageScaling,
+	pub overlays: Vec<Overlay>,
 	pub disp_fullscreen: bool,
-	pub encrypted_admin_password: String
+	/* When empty, the whole window is a single zone using the disp_* fields */
+	pub zones: Vec<ZoneConfig>,
+	/* When not empty, the messages are shown in turn instead of the zone text */
+	pub playlist: Playlist,
+	/* The first matching rule changes what is shown */
+	pub schedule: Vec<ScheduleRule>,
+	pub users: Vec<UserAccount>,
+	pub api_tokens: Vec<ApiToken>
+}
+
+impl ZoneRect {
+	pub fn full() -> ZoneRect {
+		ZoneRect { x: 0.0, y: 0.0, w: 100.0, h: 100.0 }
+	}
+
+	pub fn set_partial(&mut self, rect: &Value) {
+		let get = |name: &str| rect.get(name).and_then(Value::as_f64).map(|v| v.clamp(0.0, 100.0) as f32);
+		if let Some(v) = get("x") {
+			self.x = v;
+		}
+		if let Some(v) = get("y") {
+			self.y = v;
+		}
+		if let Some(v) = get("w") {
+			self.w = v;
+		}
+		if let Some(v) = get("h") {
+			self.h = v;
+		}
+	}
+}
+
+impl ZoneConfig {
+	pub fn new() -> ZoneConfig {
+		ZoneConfig {
+			name: String::from(""),
+			rect: ZoneRect::full(),
+			text: String::from("Text"),
+			mode: DisplayMode::Scroll,
+			scrollspeed: DEFAULT_SCROLLSPEED,
+			orientation: TextScrollOrientation::ScrollHorizontal,
+			dwell: DEFAULT_DWELL,
+			typespeed: DEFAULT_TYPESPEED,
+			blinkinterval: DEFAULT_BLINK_INTERVAL,
+			textcolor: String::from("#ffffff"),
+			backgroundcolor: String::from(""),
+			hmargin: 10,
+			vmargin: 10,
+			fontsize: 18,
+			autosize: false,
+			lines: DEFAULT_AUTOSIZE_LINES,
+			fontfamily: String::from(""),
+			fontweight: DEFAULT_FONT_WEIGHT,
+			textalign: TextAlign::Auto,
+			followdirection: false,
+			continuous: false,
+			separator: String::from(DEFAULT_SEPARATOR),
+			spacing: 0
+		}
+	}
+
+	pub fn set_partial(&mut self, zcfg: &Value) {
+		if let Some(v) = zcfg.get("name").and_then(Value::as_str) {
+			self.name = v.to_string();
+		}
+		if let Some(v) = zcfg.get("rect") {
+			self.rect.set_partial(v);
+		}
+		if let Some(v) = zcfg.get("text").and_then(Value::as_str) {
+			self.text = v.to_string();
+		}
+		if let Some(v) = zcfg.get("mode") {
+			if let Ok(vv) = DisplayMode::deserialize(v) {
+				self.mode = vv;
+			}
+		}
+		if let Some(v) = zcfg.get("scrollspeed").and_then(Value::as_i64) {
+			self.scrollspeed = v.clamp(-MAX_SCROLLSPEED as i64, MAX_SCROLLSPEED as i64) as i16;
+		}
+		if let Some(v) = zcfg.get("dwell").and_then(Value::as_f64) {
+			self.dwell = v.clamp(0.0, MAX_DWELL) as f32;
+		}
+		if let Some(v) = zcfg.get("typespeed").and_then(Value::as_f64) {
+			self.typespeed = v.clamp(0.1, MAX_TYPESPEED) as f32;
+		}
+		if let Some(v) = zcfg.get("blinkinterval").and_then(Value::as_f64) {
+			self.blinkinterval = v.clamp(MIN_BLINK_INTERVAL, MAX_BLINK_INTERVAL) as f32;
+		}
+		if let Some(v) = zcfg.get("orientation") {
+			if let Ok(vv) = TextScrollOrientation::deserialize(v) {
+				self.orientation = vv;
+			}
+		}
+		if let Some(v) = zcfg.get("textcolor").and_then(Value::as_str) {
+			self.textcolor = v.to_string();
+		}
+		if let Some(v) = zcfg.get("backgroundcolor").and_then(Value::as_str) {
+			self.backgroundcolor = v.to_string();
+		}
+		if let Some(v) = zcfg.get("hmargin").and_then(Value::as_u64) {
+			self.hmargin = v as u16;
+		}
+		if let Some(v) = zcfg.get("vmargin").and_then(Value::as_u64) {
+			self.vmargin = v as u16;
+		}
+		if let Some(v) = zcfg.get("fontsize").and_then(Value::as_u64) {
+			self.fontsize = v as u16;
+		}
+		if let Some(v) = zcfg.get("autosize").and_then(Value::as_bool) {
+			self.autosize = v;
+		}
+		if let Some(v) = zcfg.get("lines").and_then(Value::as_u64) {
+			self.lines = v.clamp(1, MAX_AUTOSIZE_LINES) as u16;
+		}
+		if let Some(v) = zcfg.get("fontfamily").and_then(Value::as_str) {
+			self.fontfamily = v.to_string();
+		}
+		if let Some(v) = zcfg.get("fontweight").and_then(Value::as_u64) {
+			self.fontweight = v.clamp(1, 1000) as u16;
+		}
+		if let Some(v) = zcfg.get("textalign") {
+			if let Ok(vv) = TextAlign::deserialize(v) {
+				self.textalign = vv;
+			}
+		}
+		if let Some(v) = zcfg.get("followdirection").and_then(Value::as_bool) {
+			self.followdirection = v;
+		}
+		if let Some(v) = zcfg.get("continuous").and_then(Value::as_bool) {
+			self.continuous = v;
+		}
+		if let Some(v) = zcfg.get("separator").and_then(Value::as_str) {
+			self.separator = v.to_string();
+		}
+		if let Some(v) = zcfg.get("spacing").and_then(Value::as_u64) {
+			self.spacing = v.min(u16::MAX as u64) as u16;
+		}
+	}
+}
+
+impl PlaylistItem {
+	pub fn new() -> PlaylistItem {
+		PlaylistItem {
+			text: String::from(""),
+			duration: 0.0,
+			passes: 0,
+			transition: PlaylistTransition::Cut,
+			textcolor: None,
+			backgroundcolor: None,
+			fontsize: None,
+			scrollspeed: None,
+			orientation: None,
+			mode: None
+		}
+	}
+
+	pub fn set_partial(&mut self, item: &Value) {
+		if let Some(v) = item.get("text").and_then(Value::as_str) {
+			self.text = v.to_string();
+		}
+		if let Some(v) = item.get("duration").and_then(Value::as_f64) {
+			self.duration = v.max(0.0) as f32;
+		}
+		if let Some(v) = item.get("passes").and_then(Value::as_u64) {
+			self.passes = v as u32;
+		}
+		if let Some(v) = item.get("transition") {
+			if let Ok(vv) = PlaylistTransition::deserialize(v) {
+				self.transition = vv;
+			}
+		}
+		if let Some(v) = item.get("textcolor") {
+			self.textcolor = v.as_str().map(str::to_string);
+		}
+		if let Some(v) = item.get("backgroundcolor") {
+			self.backgroundcolor = v.as_str().map(str::to_string);
+		}
+		if let Some(v) = item.get("fontsize") {
+			self.fontsize = v.as_u64().map(|v| v as u16);
+		}
+		if let Some(v) = item.get("scrollspeed") {
+			self.scrollspeed = v.as_i64().map(|v| v.clamp(-MAX_SCROLLSPEED as i64, MAX_SCROLLSPEED as i64) as i16);
+		}
+		if let Some(v) = item.get("orientation") {
+			self.orientation = TextScrollOrientation::deserialize(v).ok();
+		}
+		if let Some(v) = item.get("mode") {
+			self.mode = DisplayMode::deserialize(v).ok();
+		}
+	}
+
+	/* The configuration of the zone while showing this item */
+	pub fn apply_to(&self, zcfg: &mut ZoneConfig) {
+		zcfg.text = self.text.clone();
+		if let Some(v) = &self.textcolor {
+			zcfg.textcolor = v.clone();
+		}
+		if let Some(v) = &self.backgroundcolor {
+			zcfg.backgroundcolor = v.clone();
+		}
+		if let Some(v) = self.fontsize {
+			/* The size of the message wins over the one of the zone */
+			zcfg.fontsize = v;
+			zcfg.autosize = false;
+		}
+		if let Some(v) = self.scrollspeed {
+			zcfg.scrollspeed = v;
+		}
+		if let Some(v) = &self.orientation {
+			zcfg.orientation = v.clone();
+		}
+		if let Some(v) = self.mode {
+			zcfg.mode = v;
+		}
+	}
+}
+
+impl Playlist {
+	pub fn new() -> Playlist {
+		Playlist {
+			zone: String::from(""),
+			items: Vec::new(),
+			transition_time: DEFAULT_TRANSITION_TIME
+		}
+	}
+
+	pub fn set_partial(&mut self, playlist: &Value) {
+		if let Some(v) = playlist.get("zone").and_then(Value::as_str) {
+			self.zone = v.to_string();
+		}
+		if let Some(v) = playlist.get("transition_time").and_then(Value::as_f64) {
+			self.transition_time = v.clamp(0.0, MAX_TRANSITION_TIME) as f32;
+		}
+		if let Some(v) = playlist.get("items").and_then(Value::as_array) {
+			self.items = v.iter().map(|iv| {
+				let mut item = PlaylistItem::new();
+				item.set_partial(iv);
+				item
+			}).collect();
+		}
+	}
+}
+
+impl HttpsConfig {
+	pub fn new() -> HttpsConfig {
+		HttpsConfig {
+			enabled: false,
+			cert_file: String::from(""),
+			key_file: String::from(""),
+			redirect_port: 0
+		}
+	}
+
+	pub fn set_partial(&mut self, https: &Value) {
+		if let Some(v) = https.get("enabled").and_then(Value::as_bool) {
+			self.enabled = v;
+		}
+		if let Some(v) = https.get("cert_file").and_then(Value::as_str) {
+			self.cert_file = v.to_string();
+		}
+		if let Some(v) = https.get("key_file").and_then(Value::as_str) {
+			self.key_file = v.to_string();
+		}
+		if let Some(v) = https.get("redirect_port").and_then(Value::as_u64) {
+			self.redirect_port = v as u16;
+		}
+	}
+}
+
+impl Overlay {
+	pub fn new() -> Overlay {
+		Overlay {
+			image: String::from(""),
+			rect: ZoneRect::full(),
+			opacity: 1.0
+		}
+	}
+
+	pub fn set_partial(&mut self, overlay: &Value) {
+		if let Some(v) = overlay.get("image").and_then(Value::as_str) {
+			self.image = v.to_string();
+		}
+		if let Some(v) = overlay.get("rect") {
+			self.rect.set_partial(v);
+		}
+		if let Some(v) = overlay.get("opacity").and_then(Value::as_f64) {
+			self.opacity = v.clamp(0.0, 1.0) as f32;
+		}
+	}
+}
+
+impl ScheduleRule {
+	pub fn new() -> ScheduleRule {
+		ScheduleRule {
+			name: String::from(""),
+			weekdays: Vec::new(),
+			time_from: String::from(""),
+			time_to: String::from(""),
+			date_from: String::from(""),
+			date_to: String::from(""),
+			content: json!({})
+		}
+	}
+
+	pub fn set_partial(&mut self, rule: &Value) {
+		let get_str = |name: &str| rule.get(name).and_then(Value::as_str).map(str::to_string);
+		if let Some(v) = get_str("name") {
+			self.name = v;
+		}
+		if let Some(v) = rule.get("weekdays").and_then(Value::as_array) {
+			self.weekdays = v.iter().filter_map(Value::as_str).map(str::to_string).collect();
+		}
+		if let Some(v) = get_str("time_from") {
+			self.time_from = v;
+		}
+		if let Some(v) = get_str("time_to") {
+			self.time_to = v;
+		}
+		if let Some(v) = get_str("date_from") {
+			self.date_from = v;
+		}
+		if let Some(v) = get_str("date_to") {
+			self.date_to = v;
+		}
+		if let Some(v) = rule.get("content").filter(|v| v.is_object()) {
+			self.content = v.clone();
+		}
+	}
+}
+
+/* Ordered by what they can do, each role can do all the things of the
+ * ones before it */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum UserRole {
+	Viewer,
+	/* Can change the text and the playlist */
+	Editor,
+	/* Can change everything, including ports, users and passwords */
+	Admin
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UserAccount {
+	pub username: String,
+	pub role: UserRole,
+	pub encrypted_password: String
+}
+
+/* What an API token is allowed to do */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum TokenScope {
+	Full,
+	ReadOnly,
+	/* Only read and change the text */
+	TextOnly
+}
+
+/* Long lived credential for scripts, sent as Authorization: Bearer.  Only
+ * the SHA-256 of the token is kept */
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ApiToken {
+	pub id: String,
+	pub name: String,
+	pub scope: TokenScope,
+	pub created: String,
+	pub token_hash: String
+}
+
+/* A change pushed to the clients connected to the event stream */
+#[derive(Clone)]
+pub struct ServerEvent {
+	pub name: &'static str,
+	pub data: Value
 }
 
 // Global data, will be wrapped on an Arc<Mutex<>>
 pub struct RuntimeGlobalData {
 	pub runtime_data_dir: PathBuf,
-	pub html_dir: PathBuf,
-	pub cfg: Config
+	pub webroot: WebRoot,
+	pub cfg: Config,
+	/* Set by the API, executed by the render loop */
+	pub playlist_request: Option<PlaylistRequest>,
+	/* Playlist item shown now, updated by the render loop */
+	pub playlist_current: usize,
+	pub paused: bool,
+	/* The fonts the zones can use */
+	pub fonts: FontCatalog,
+	/* The images the background and the overlays can use */
+	pub images: ImageCatalog,
+	pub events: broadcast::Sender<ServerEvent>
+}
+
+impl RuntimeGlobalData {
+	pub fn notify(&self, name: &'static str, data: Value) {
+		/* Fails only when nobody is listening */
+		let _ = self.events.send(ServerEvent { name, data });
+	}
+
+	/* Everything a client needs to know when it connects */
+	pub fn state(&self) -> Value {
+		json!({
+			"version": self.cfg.version,
+			"paused": self.paused,
+			"playlist_current": self.playlist_current
+		})
+	}
 }
 
 impl Config {
@@ -43,17 +688,83 @@ impl Config {
 	pub fn new() -> Config {
 		Config {
 			version: 0,
+			config_format: CONFIG_FORMAT_VERSION,
 			http_port: DEFAULT_HTTP_PORT,
+			listen: vec![String::from(DEFAULT_LISTEN_ADDRESS)],
+			https: HttpsConfig::new(),
+			trusted_proxies: Vec::new(),
 			disp_text: String::from("Text"),
-			disp_scrollspeed: 1,
+			disp_mode: DisplayMode::Scroll,
+			disp_scrollspeed: DEFAULT_SCROLLSPEED,
 			disp_orientation: TextScrollOrientation::ScrollVertical,
+			disp_dwell: DEFAULT_DWELL,
+			disp_typespeed: DEFAULT_TYPESPEED,
+			disp_blinkinterval: DEFAULT_BLINK_INTERVAL,
 			disp_textcolor: String::from("#ffffff"),
 			disp_backgroundcolor: String::from("#202020"),
 			disp_hmargin: 10,
 			disp_vmargin: 10,
 			disp_fontsize: 18,
+			disp_autosize: false,
+			disp_lines: DEFAULT_AUTOSIZE_LINES,
+			disp_fontfamily: String::from(""),
+			disp_fontweight: DEFAULT_FONT_WEIGHT,
+			fallback_fonts: DEFAULT_FALLBACK_FONTS.iter().map(|f| f.to_string()).collect(),
+			disp_textalign: TextAlign::Auto,
+			disp_followdirection: false,
+			disp_continuous: false,
+			disp_separator: String::from(DEFAULT_SEPARATOR),
+			disp_spacing: 0,
+			disp_background: BackgroundMode::Color,
+			disp_gradientcolors: vec![String::from("#303030"), String::from("#000000")],
+			disp_gradientangle: 180.0,
+			disp_backgroundimage: String::from(""),
+			disp_imagescaling: ImageScaling::Fill,
+			overlays: Vec::new(),
 			disp_fullscreen: false,
-			encrypted_admin_password: String::from("")
+			zones: Vec::new(),
+			playlist: Playlist::new(),
+			schedule: Vec::new(),
+			users: Vec::new(),
+			api_tokens: Vec::new()
+		}
+	}
+
+	/* The zone covering the whole window, described by the disp_* fields */
+	pub fn main_zone(&self) -> ZoneConfig {
+		ZoneConfig {
+			name: String::from("main"),
+			rect: ZoneRect::full(),
+			text: self.disp_text.clone(),
+			mode: self.disp_mode,
+			scrollspeed: self.disp_scrollspeed,
+			orientation: self.disp_orientation.clone(),
+			dwell: self.disp_dwell,
+			typespeed: self.disp_typespeed,
+			blinkinterval: self.disp_blinkinterval,
+			textcolor: self.disp_textcolor.clone(),
+			backgroundcolor: String::from(""),
+			hmargin: self.disp_hmargin,
+			vmargin: self.disp_vmargin,
+			fontsize: self.disp_fontsize,
+			autosize: self.disp_autosize,
+			lines: self.disp_lines,
+			fontfamily: self.disp_fontfamily.clone(),
+			fontweight: self.disp_fontweight,
+			textalign: self.disp_textalign,
+			followdirection: self.disp_followdirection,
+			continuous: self.disp_continuous,
+			separator: self.disp_separator.clone(),
+			spacing: self.disp_spacing
+		}
+	}
+
+	/* The zones to be shown on the screen */
+	pub fn effective_zones(&self) -> Vec<ZoneConfig> {
+		if self.zones.is_empty() {
+			vec![self.main_zone()]
+		} else {
+			self.zones.clone()
 		}
 	}
 
@@ -74,30 +785,81 @@ impl Config {
 	}
 
 	pub async fn load() -> Config {
+		Config::load_from(&Config::get_config_file_name(false)).await
+	}
+
+	pub async fn load_from(filepath: &Path) -> Config {
 
-		let filepath = Config::get_config_file_name(false);
 		let default_config = r#"
 			{
 			}
 		"#.to_owned();
 
 		println!("Loading {}", filepath.display());
-		let contents = match fs::read_to_string(&filepath).await {
+		let contents = match fs::read_to_string(filepath).await {
 			Ok(s) => s,
 			Err(_) => default_config.clone()
 		};
 
 		let jconf = match serde_json::from_str::<Value>(&contents) {
 			Ok(parsed) => parsed,
-			Err(_) => json!(default_config.clone())
+			Err(_) => json!(default_config)
 		};
+		Config::from_json(jconf)
+
+	}
+
+	/* Like load_from, for a file asked for explicitly: when it cannot be
+	 * used the defaults would hide the mistake */
+	pub async fn load_existing(filepath: &Path) -> Result<Config, String> {
+		let contents = fs::read_to_string(filepath).await
+			.map_err(|e| format!("Unable to read {}: {}", filepath.display(), e))?;
+		match serde_json::from_str::<Value>(&contents) {
+			Ok(jconf) if jconf.is_object() => Ok(Config::from_json(jconf)),
+			Ok(_) => Err(format!("{} does not contain a JSON object", filepath.display())),
+			Err(e) => Err(format!("{} is not valid JSON: {}", filepath.display(), e))
+		}
+	}
+
+	fn from_json(mut jconf: Value) -> Config {
+		Config::migrate_cfg_format(&mut jconf);
 
 		let mut cf = Config::new();
 		cf.set_partial_cfg(&jconf);
 		cf.version = 1;
 
 		cf
+	}
+
+	/* Converts a configuration saved by an older version of the application
+	 * to the current CONFIG_FORMAT_VERSION, before it is applied */
+	fn migrate_cfg_format(jconf: &mut Value) {
+		let Some(obj) = jconf.as_object_mut() else {
+			return;
+		};
+		let format = obj.get("config_format").and_then(Value::as_u64).unwrap_or(1);
+
+		if format < 2 {
+			/* Scroll speed was in pixels per frame */
+			if let Some(v) = obj.get("disp_scrollspeed").and_then(Value::as_i64) {
+				let pps = v * LEGACY_FRAMES_PER_SECOND;
+				println!("Migrating disp_scrollspeed from {} pixels/frame to {} pixels/second", v, pps);
+				obj.insert("disp_scrollspeed".to_string(), json!(pps));
+			}
+		}
+		if format < 3 {
+			/* There was only the admin user */
+			if let Some(v) = obj.remove("encrypted_admin_password") {
+				println!("Migrating the admin password to the user accounts");
+				obj.insert("users".to_string(), json!([
+					{ "username": "admin", "role": UserRole::Admin, "encrypted_password": v }
+				]));
+			}
+		}
+	}
 
+	pub fn find_user(&self, username: &str) -> Option<&UserAccount> {
+		self.users.iter().find(|u| u.username == username)
 	}
 
 	/*
@@ -115,6 +877,15 @@ impl Config {
 		if let Some(v) = cfg.get("http_port").and_then(Value::as_u64) {
 			self.http_port = v as u16;
 		}
+		if let Some(v) = cfg.get("listen").and_then(Value::as_array) {
+			self.listen = v.iter().filter_map(Value::as_str).map(str::to_string).collect();
+		}
+		if let Some(v) = cfg.get("trusted_proxies").and_then(Value::as_array) {
+			self.trusted_proxies = v.iter().filter_map(Value::as_str).map(str::to_string).collect();
+		}
+		if let Some(v) = cfg.get("https") {
+			self.https.set_partial(v);
+		}
 		if let Some(v) = cfg.get("disp_text").and_then(Value::as_str) {
 			self.disp_text = v.to_string();
 		}
@@ -133,34 +904,125 @@ impl Config {
 		if let Some(v) = cfg.get("disp_fontsize").and_then(Value::as_u64) {
 			self.disp_fontsize = v as u16;
 		}
+		if let Some(v) = cfg.get("disp_autosize").and_then(Value::as_bool) {
+			self.disp_autosize = v;
+		}
+		if let Some(v) = cfg.get("disp_lines").and_then(Value::as_u64) {
+			self.disp_lines = v.clamp(1, MAX_AUTOSIZE_LINES) as u16;
+		}
+		if let Some(v) = cfg.get("disp_fontfamily").and_then(Value::as_str) {
+			self.disp_fontfamily = v.to_string();
+		}
+		if let Some(v) = cfg.get("disp_fontweight").and_then(Value::as_u64) {
+			self.disp_fontweight = v.clamp(1, 1000) as u16;
+		}
+		if let Some(v) = cfg.get("disp_textalign") {
+			if let Ok(vv) = TextAlign::deserialize(v) {
+				self.disp_textalign = vv;
+			}
+		}
+		if let Some(v) = cfg.get("disp_followdirection").and_then(Value::as_bool) {
+			self.disp_followdirection = v;
+		}
+		if let Some(v) = cfg.get("disp_continuous").and_then(Value::as_bool) {
+			self.disp_continuous = v;
+		}
+		if let Some(v) = cfg.get("disp_separator").and_then(Value::as_str) {
+			self.disp_separator = v.to_string();
+		}
+		if let Some(v) = cfg.get("disp_spacing").and_then(Value::as_u64) {
+			self.disp_spacing = v.min(u16::MAX as u64) as u16;
+		}
+		if let Some(v) = cfg.get("fallback_fonts").and_then(Value::as_array) {
+			self.fallback_fonts = v.iter().filter_map(Value::as_str).map(str::to_string).collect();
+		}
 		if let Some(v) = cfg.get("disp_scrollspeed").and_then(Value::as_i64) {
-			self.disp_scrollspeed = v as i16;
-			if self.disp_scrollspeed < -50 {
-				self.disp_scrollspeed = -50;
+			self.disp_scrollspeed = v.clamp(-MAX_SCROLLSPEED as i64, MAX_SCROLLSPEED as i64) as i16;
+		}
+		if let Some(v) = cfg.get("disp_mode") {
+			if let Ok(vv) = DisplayMode::deserialize(v) {
+				self.disp_mode = vv;
 			}
-			if self.disp_scrollspeed > 50 {
-				self.disp_scrollspeed = 50;
+		}
+		if let Some(v) = cfg.get("disp_dwell").and_then(Value::as_f64) {
+			self.disp_dwell = v.clamp(0.0, MAX_DWELL) as f32;
+		}
+		if let Some(v) = cfg.get("disp_typespeed").and_then(Value::as_f64) {
+			self.disp_typespeed = v.clamp(0.1, MAX_TYPESPEED) as f32;
+		}
+		if let Some(v) = cfg.get("disp_blinkinterval").and_then(Value::as_f64) {
+			self.disp_blinkinterval = v.clamp(MIN_BLINK_INTERVAL, MAX_BLINK_INTERVAL) as f32;
+		}
+		if let Some(v) = cfg.get("disp_background") {
+			if let Ok(vv) = BackgroundMode::deserialize(v) {
+				self.disp_background = vv;
 			}
 		}
+		if let Some(v) = cfg.get("disp_gradientcolors").and_then(Value::as_array) {
+			self.disp_gradientcolors = v.iter().filter_map(Value::as_str).map(str::to_string).collect();
+		}
+		if let Some(v) = cfg.get("disp_gradientangle").and_then(Value::as_f64) {
+			self.disp_gradientangle = v.rem_euclid(360.0) as f32;
+		}
+		if let Some(v) = cfg.get("disp_backgroundimage").and_then(Value::as_str) {
+			self.disp_backgroundimage = v.to_string();
+		}
+		if let Some(v) = cfg.get("disp_imagescaling") {
+			if let Ok(vv) = ImageScaling::deserialize(v) {
+				self.disp_imagescaling = vv;
+			}
+		}
+		if let Some(v) = cfg.get("overlays").and_then(Value::as_array) {
+			self.overlays = v.iter().map(|ov| {
+				let mut o = Overlay::new();
+				o.set_partial(ov);
+				o
+			}).collect();
+		}
 		if let Some(v) = cfg.get("disp_fullscreen").and_then(Value::as_bool) {
 			self.disp_fullscreen = v;
 		}
 		if let Some(v) = cfg.get("disp_orientation") {
-			match TextScrollOrientation::deserialize(v) {
-				Ok(vv) => self.disp_orientation = vv,
-				Err(_e) => {}
+			if let Ok(vv) = TextScrollOrientation::deserialize(v) {
+				self.disp_orientation = vv;
 			}
 		}
-		if let Some(v) = cfg.get("encrypted_admin_password").and_then(Value::as_str) {
-			self.encrypted_admin_password = v.to_string();
+		if let Some(v) = cfg.get("zones").and_then(Value::as_array) {
+			self.zones = v.iter().map(|zv| {
+				let mut z = ZoneConfig::new();
+				z.set_partial(zv);
+				z
+			}).collect();
+		}
+		if let Some(v) = cfg.get("playlist") {
+			self.playlist.set_partial(v);
+		}
+		if let Some(v) = cfg.get("schedule").and_then(Value::as_array) {
+			self.schedule = v.iter().map(|rv| {
+				let mut r = ScheduleRule::new();
+				r.set_partial(rv);
+				r
+			}).collect();
+		}
+		if let Some(v) = cfg.get("users") {
+			if let Ok(vv) = Vec::<UserAccount>::deserialize(v) {
+				self.users = vv;
+			}
+		}
+
+		if let Some(v) = cfg.get("api_tokens") {
+			if let Ok(vv) = Vec::<ApiToken>::deserialize(v) {
+				self.api_tokens = vv;
+			}
 		}
 
-		/* Create a default admin password "admin" */
-		if self.encrypted_admin_password.is_empty() {
-			self.encrypted_admin_password = match bcrypt::hash("admin") {
-				Ok(s) => s,
-				Err(_e) => String::from("")
-			};
+		/* Create a default user "admin" with password "admin" */
+		if self.users.is_empty() {
+			self.users.push(UserAccount {
+				username: String::from("admin"),
+				role: UserRole::Admin,
+				encrypted_password: bcrypt::hash("admin").unwrap_or_default()
+			});
 		}
 
 		self.version += 1;
@@ -1,18 +1,10 @@
 
 use speedy2d::Window;
-use speedy2d::color::Color;
 use speedy2d::window::{WindowHandler, WindowHelper, WindowStartupInfo, WindowFullscreenMode, MouseButton};
 use speedy2d::Graphics2D;
-use speedy2d::font::Font;
-use speedy2d::font::TextLayout;
-use speedy2d::font::TextOptions;
-use speedy2d::font::TextAlignment;
-use speedy2d::font::FormattedTextBlock;
 use speedy2d::dimen::Vector2;
-use speedy2d::shape::Rectangle;
 
 
-use std::rc::Rc;
 use tokio::task;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -23,13 +15,26 @@ use image::io::Reader;
 use image::error::ImageError;
 
 
-use lconfig::{RuntimeGlobalData, TextScrollOrientation};
+use lconfig::RuntimeGlobalData;
 use lconfig::Config;
+use webroot::WebRoot;
+use fonts::FontCatalog;
+use images::ImageCatalog;
+use render::Renderer;
+use render::layout::FontSet;
+use render::window_canvas::{PictureCache, WindowCanvas};
 
 use std::time::Instant;
 
 mod lconfig;
 mod httpsrv;
+mod render;
+mod headless;
+mod cmdline;
+mod schedule;
+mod webroot;
+mod fonts;
+mod images;
 
 const APP_NAME_APPLICATION: &str = env!("CARGO_PKG_NAME");
 const APP_NAME_ORGANIZATION: &str = "giox069";
@@ -38,20 +43,26 @@ const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 const FULLSCREEN_MOUSE_HIDE_MILLISEC: u128 = 3000;
 
+/* Longest time step applied to the scroll position in a single frame, so
+ * the text does not jump away after the window has been blocked for a while */
+const MAX_FRAME_TIME_SEC: f32 = 0.25;
+
+/* How often the schedule rules are checked against the clock */
+const SCHEDULE_CHECK_MILLISEC: u128 = 1000;
+
+/* Events not yet sent to a slow client of the event stream */
+const EVENT_QUEUE_LEN: usize = 64;
+
 struct MyWindowHandler {
-    font: Font,
-    y: f32,
-	x: f32,
-    size: Vector2<u32>,
+    renderer: Renderer,
+    /* The background and overlay images, kept on the GPU */
+    pictures: PictureCache,
     rgd: Arc<Mutex<RuntimeGlobalData>>,
-    current_cfg_copy: Config,
-    pause: bool,
-    block: Option<Rc<FormattedTextBlock>>,
-    min_x: f32,
-    max_x: f32,
-    min_y: f32,
-    max_y: f32,
     last_mouse_move: Instant,
+    last_frame_time: Option<Instant>,
+    /* Schedule rule currently applied to the configuration */
+    schedule_rule: Option<usize>,
+    last_schedule_check: Option<Instant>,
     mouse_hidden: bool
 }
 
@@ -59,60 +70,33 @@ struct MyWindowHandler {
 // https://docs.rs/speedy2d/latest/speedy2d/
 
 impl MyWindowHandler {
-	fn rebuild_text_block(&mut self) {
-		
-		let mut text_options = TextOptions::new();
-		if self.current_cfg_copy.disp_orientation == TextScrollOrientation::ScrollVertical {
-			text_options = text_options.with_wrap_to_width(
-				(self.size.x - 2 * self.current_cfg_copy.disp_hmargin as u32) as f32,
-				TextAlignment::Left);
-		};
-
-		let block = self.font.layout_text(&self.current_cfg_copy.disp_text,
-			self.current_cfg_copy.disp_fontsize as f32,
-			text_options);
-
-		self.max_y = self.size.y as f32 - self.current_cfg_copy.disp_vmargin as f32;
-		self.min_y = self.current_cfg_copy.disp_vmargin as f32 - block.height();
-		self.max_x = self.size.x as f32 - self.current_cfg_copy.disp_hmargin as f32;
-		self.min_x = self.current_cfg_copy.disp_hmargin as f32 - block.width();
-
-		self.block = Some(block);
-
-
-	}
-	fn new(rgd: Arc<Mutex<RuntimeGlobalData>>) -> MyWindowHandler {
-		let bytes = include_bytes!("../assets/fonts/Ubuntu-R.ttf");
-		let font = Font::new(bytes).unwrap();
-
+	fn new(rgd: Arc<Mutex<RuntimeGlobalData>>, fonts: FontCatalog, images: ImageCatalog) -> MyWindowHandler {
 		MyWindowHandler {
-			font: font,
-			y: 0.0,
-			x: 0.0,
-			size: Vector2{x: 10, y:10},
-			rgd: rgd,
-			current_cfg_copy: Config::new(),
-			pause: false,
-			block: None,
-			min_x: 0.0,
-			max_x: 100.0,
-			min_y: 0.0,
-			max_y: 100.0,
+			renderer: Renderer::new(FontSet::new(fonts), images),
+			pictures: PictureCache::default(),
+			rgd,
 			last_mouse_move: Instant::now(),
+			last_frame_time: None,
+			schedule_rule: None,
+			last_schedule_check: None,
 			mouse_hidden: false
 			}
 	}
 
 	fn load_icon(&self, helper: &mut WindowHelper) -> std::result::Result<(), ImageError> {
 
-		/* Load the icon */
-		let rgd = self.rgd.blocking_lock();
-		let mut icon_filename = rgd.runtime_data_dir.clone();
-		drop(rgd);
-		icon_filename.push("icons");
-		icon_filename.push("main_icon.png");
-
-		let image = Reader::open(icon_filename)?.decode()?;
+		/* Load the icon, from the executable when it is embedded */
+		let image = match webroot::embedded_file("icons/main_icon.png") {
+			Some(bytes) => image::load_from_memory(bytes)?,
+			None => {
+				let rgd = self.rgd.blocking_lock();
+				let mut icon_filename = rgd.runtime_data_dir.clone();
+				drop(rgd);
+				icon_filename.push("icons");
+				icon_filename.push("main_icon.png");
+				Reader::open(icon_filename)?.decode()?
+			}
+		};
 
 		let s = (image.width(), image.height());
 		let imga32 = image.into_rgba8();
@@ -136,39 +120,48 @@ impl WindowHandler for MyWindowHandler
 	fn on_draw(&mut self, helper: &mut WindowHelper, graphics: &mut Graphics2D) {
 		// println!("On draw");
 
-		let rgd = self.rgd.blocking_lock();
-		let mut config_has_changed = false;
-		if self.current_cfg_copy.version != rgd.cfg.version {
-			self.current_cfg_copy = rgd.cfg.clone();
-			config_has_changed = true;
+		let mut rgd = self.rgd.blocking_lock();
+		let fonts = if rgd.fonts.generation != self.renderer.font_generation() {
+			Some(rgd.fonts.clone())
+		} else {
+			None
+		};
+		let images = if rgd.images.generation != self.renderer.image_generation() {
+			Some(rgd.images.clone())
+		} else {
+			None
+		};
+		let cfg_changed = self.renderer.config().version != rgd.cfg.version;
+		let mut rule = self.schedule_rule;
+		if cfg_changed || self.last_schedule_check.is_none_or(|t| t.elapsed().as_millis() >= SCHEDULE_CHECK_MILLISEC) {
+			rule = schedule::active_rule(&rgd.cfg, chrono::Local::now().naive_local());
+			self.last_schedule_check = Some(Instant::now());
+		}
+		let new_cfg = if cfg_changed || rule != self.schedule_rule {
+			self.schedule_rule = rule;
+			Some(schedule::scheduled_config(&rgd.cfg, rule))
+		} else {
+			None
+		};
+		let playlist_request = rgd.playlist_request.take();
+		let playlist_current = self.renderer.playlist_current().unwrap_or(0);
+		if rgd.playlist_current != playlist_current {
+			rgd.playlist_current = playlist_current;
+			rgd.notify("playlist", serde_json::json!({ "current": playlist_current }));
 		}
+		self.renderer.pause = rgd.paused;
 		drop(rgd);
 
-		if config_has_changed {
-			self.y = match self.current_cfg_copy.disp_orientation {
-				TextScrollOrientation::ScrollVertical => {
-					if self.current_cfg_copy.disp_scrollspeed < 0 {
-						self.max_y
-					} else {
-						self.min_y
-					}
-				},
-				TextScrollOrientation::ScrollHorizontal => self.current_cfg_copy.disp_vmargin as f32
-			};
-			self.x = match self.current_cfg_copy.disp_orientation {
-				TextScrollOrientation::ScrollHorizontal => {
-					if self.current_cfg_copy.disp_scrollspeed < 0 {
-						self.max_x
-					} else {
-						self.min_x
-					}
-				},
-				TextScrollOrientation::ScrollVertical => self.current_cfg_copy.disp_hmargin as f32
-			};
-
-
-			self.rebuild_text_block();
-			if self.current_cfg_copy.disp_fullscreen {
+		if let Some(fonts) = fonts {
+			self.renderer.set_font_catalog(fonts);
+		}
+		if let Some(images) = images {
+			self.renderer.set_image_catalog(images);
+		}
+		if let Some(cfg) = new_cfg {
+			let fullscreen = cfg.disp_fullscreen;
+			self.renderer.set_config(cfg);
+			if fullscreen {
 				// Enter fullscreen mode
 				helper.set_fullscreen_mode(WindowFullscreenMode::FullscreenBorderless);
 				if !self.mouse_hidden {
@@ -184,70 +177,25 @@ impl WindowHandler for MyWindowHandler
 			}
 		}
 
-		let bgcolor = match csscolorparser::parse(&self.current_cfg_copy.disp_backgroundcolor) {
-			Ok(c) => Color::from_rgb(c.r as f32, c.g as f32, c.b as f32),
-			Err(_) => Color::BLACK
-		};
+		if let Some(r) = playlist_request {
+			self.renderer.playlist_request(r);
+		}
 
-		let clip_area = Rectangle::from_tuples(
-			(self.current_cfg_copy.disp_hmargin as i32, self.current_cfg_copy.disp_vmargin as i32 ),
-			(self.size.x as i32 - self.current_cfg_copy.disp_hmargin as i32, self.size.y as i32 - self.current_cfg_copy.disp_vmargin as i32)
-		);
-
-		graphics.clear_screen(bgcolor);
-
-		match &self.block {
-			Some(b) => {
-
-				let fgcolor = match csscolorparser::parse(&self.current_cfg_copy.disp_textcolor) {
-					Ok(c) => Color::from_rgb(c.r as f32, c.g as f32, c.b as f32),
-					Err(_) => Color::WHITE
-				};
-
-				graphics.set_clip(Some(clip_area));
-
-				graphics.draw_text((
-						self.x,
-						self.y
-					),
-					fgcolor, &b);
-
-				// println!("self.size.x={} self.size.y={} self.x={} self.y={}", self.size.x, self.size.y, self.x, self.y);
-
-				if !self.pause {
-					match self.current_cfg_copy.disp_orientation {
-						TextScrollOrientation::ScrollVertical => {
-							self.y -= self.current_cfg_copy.disp_scrollspeed as f32;
-
-							if self.y >=  self.max_y {
-								self.y = self.min_y;
-							}
-							if self.y < self.min_y {
-								self.y = self.max_y;
-							}
-						},
-						TextScrollOrientation::ScrollHorizontal => {
-							self.x -= self.current_cfg_copy.disp_scrollspeed as f32;
-
-							if self.x >=  self.max_x {
-								self.x = self.min_x;
-							}
-							if self.x < self.min_x {
-								self.x = self.max_x;
-							}
-						}
-					}
-				}
+		self.renderer.draw(&mut WindowCanvas::new(graphics, &mut self.pictures));
 
-			},
-			None => {}
-		}
+		/* Move the text by the time elapsed since the previous frame */
+		let now = Instant::now();
+		let dt = match self.last_frame_time {
+			Some(t) => now.duration_since(t).as_secs_f32().min(MAX_FRAME_TIME_SEC),
+			None => 0.0
+		};
+		self.last_frame_time = Some(now);
+		self.renderer.advance(dt);
 
-		if self.current_cfg_copy.disp_fullscreen && !self.mouse_hidden {
-			if self.last_mouse_move.elapsed().as_millis() > FULLSCREEN_MOUSE_HIDE_MILLISEC {
-				self.mouse_hidden = true;
-				helper.set_cursor_visible(false);
-			}
+		if self.renderer.config().disp_fullscreen && !self.mouse_hidden &&
+				self.last_mouse_move.elapsed().as_millis() > FULLSCREEN_MOUSE_HIDE_MILLISEC {
+			self.mouse_hidden = true;
+			helper.set_cursor_visible(false);
 		}
 
 		// Request that we draw another frame once this one has finished
@@ -256,8 +204,7 @@ impl WindowHandler for MyWindowHandler
 
 	fn on_resize(&mut self, _helper: &mut WindowHelper, size_pixels: Vector2<u32>) {
 
-		self.size = size_pixels;
-		self.rebuild_text_block();
+		self.renderer.resize(size_pixels);
 	}
 
 	fn on_start(&mut self, helper: &mut WindowHelper, info: WindowStartupInfo) {
@@ -269,13 +216,16 @@ impl WindowHandler for MyWindowHandler
 			Err(e) => println!("Icon image load error: {}", e)
 		};
 
-		self.size = info.viewport_size_pixels().clone();
-		self.rebuild_text_block();
+		self.renderer.resize(*info.viewport_size_pixels());
 	}
 
 	fn on_mouse_button_down(&mut self, _helper: &mut WindowHelper<()>, button: MouseButton) {
 		if button == MouseButton::Right {
-			self.pause = !self.pause;
+			let mut rgd = self.rgd.blocking_lock();
+			rgd.paused = !rgd.paused;
+			let paused = rgd.paused;
+			rgd.notify("pause", serde_json::json!({ "paused": paused }));
+			self.renderer.pause = paused;
 		}
 	}
 
@@ -289,34 +239,89 @@ impl WindowHandler for MyWindowHandler
 
 	fn on_scale_factor_changed(&mut self, _helper: &mut WindowHelper<()>, scale_factor: f64) {
 		println!("Scale factor changed: {}", scale_factor);
+		self.renderer.refit();
 	}
 
 }
 
-#[tokio::main]
-async fn main() {
-
-	/* Try to find where our runtime-data directory is and set runtime_data_dir */
-
+/* Try to find where our runtime-data directory is */
+async fn find_runtime_data_dir() -> PathBuf {
 	let runtime_data_dir_relpath = PathBuf::from("./runtime-data");
-	let mut runtime_data_dir = match tokio::fs::canonicalize(runtime_data_dir_relpath.clone()).await {
+	let runtime_data_dir = match tokio::fs::canonicalize(runtime_data_dir_relpath.clone()).await {
 		Ok(p) => p,
 		Err(_e) => runtime_data_dir_relpath
 	};
 	if !runtime_data_dir.exists() {
-		runtime_data_dir = PathBuf::from("/usr/share/").join(APP_NAME_APPLICATION);
+		return PathBuf::from("/usr/share/").join(APP_NAME_APPLICATION);
 	}
+	runtime_data_dir
+}
 
+#[tokio::main]
+async fn main() {
 
-	let html_dir = runtime_data_dir.join("html");
+	let opts = match cmdline::parse_cmdline(std::env::args().skip(1)) {
+		Ok(o) => o,
+		Err(e) => {
+			eprintln!("{}\n\n{}", e, cmdline::USAGE);
+			std::process::exit(1);
+		}
+	};
+	if opts.help {
+		print!("{}", cmdline::USAGE);
+		return;
+	}
+
+	let runtime_data_dir = find_runtime_data_dir().await;
+	/* Offscreen frames must not depend on the fonts of the machine */
+	let fonts = match opts.render_frames {
+		Some(_) => FontCatalog::scan_without_system(&runtime_data_dir),
+		None => FontCatalog::scan(&runtime_data_dir)
+	};
+	println!("Found {} fonts", fonts.fonts.len());
+	let images = ImageCatalog::scan();
+
+	if let Some(rf) = opts.render_frames {
+		let cfg = match &rf.config_file {
+			Some(f) => match Config::load_existing(f).await {
+				Ok(cfg) => cfg,
+				Err(e) => {
+					eprintln!("{}", e);
+					std::process::exit(1);
+				}
+			},
+			None => Config::load().await
+		};
+		/* The same frames whenever they are rendered, the schedule only
+		 * applies at the time asked for */
+		let rule = rf.at.and_then(|t| schedule::active_rule(&cfg, t));
+		let cfg = schedule::scheduled_config(&cfg, rule);
+		if let Err(e) = headless::render_frames(&rf, cfg, fonts, images) {
+			eprintln!("Offscreen rendering failed: {}", e);
+			std::process::exit(1);
+		}
+		return;
+	}
+
+	let webroot = match opts.webroot {
+		Some(d) => WebRoot::Dir(d),
+		None => WebRoot::default_for(&runtime_data_dir)
+	};
+	println!("Serving the web interface from {}", webroot);
 	let cfg : lconfig::Config = lconfig::Config::load().await;
 
 
 	let runtime_global_data = Arc::new(Mutex::new(
 		RuntimeGlobalData {
-				runtime_data_dir: runtime_data_dir,
-				html_dir: html_dir,
-				cfg: cfg
+				runtime_data_dir,
+				webroot,
+				cfg,
+				playlist_request: None,
+				playlist_current: 0,
+				paused: false,
+				fonts: fonts.clone(),
+				images: images.clone(),
+				events: tokio::sync::broadcast::channel(EVENT_QUEUE_LEN).0
 		}
 	));
 
@@ -326,7 +331,7 @@ async fn main() {
 
 	let window = Window::new_centered("Title",(640, 480)).unwrap();
 
-	let wh = MyWindowHandler::new(rgdclone);
+	let wh = MyWindowHandler::new(rgdclone, fonts, images);
 	println!("Starting window loop");
 	block_in_place(move || {window.run_loop(wh)});
 
@@ -0,0 +1,223 @@
+/* What is drawn under the zones: the background of the window, a
+ * gradient or an image, and the logos over it.  They are prepared for the
+ * size of the window, drawing them only copies pictures. */
+
+use std::collections::HashMap;
+
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use speedy2d::color::Color;
+use speedy2d::dimen::Vector2;
+use speedy2d::shape::Rectangle;
+
+use crate::images::ImageCatalog;
+use crate::lconfig::{BackgroundMode, Config, ImageScaling, Overlay, ZoneRect};
+use crate::render::{color_to_rgba, parse_color, Canvas, Picture};
+
+/* The uploaded images, decoded the first time they are shown */
+pub struct ImageSet {
+	catalog: ImageCatalog,
+	/* None when the image could not be read */
+	decoded: HashMap<String, Option<RgbaImage>>
+}
+
+impl ImageSet {
+	pub fn new(catalog: ImageCatalog) -> ImageSet {
+		ImageSet { catalog, decoded: HashMap::new() }
+	}
+
+	pub fn generation(&self) -> u64 {
+		self.catalog.generation
+	}
+
+	fn get(&mut self, file_name: &str) -> Option<&RgbaImage> {
+		let catalog = &self.catalog;
+		self.decoded.entry(file_name.to_string()).or_insert_with(|| {
+			let Some(info) = catalog.find(file_name) else {
+				println!("No uploaded image {}", file_name);
+				return None;
+			};
+			match image::open(&info.path) {
+				Ok(i) => Some(i.into_rgba8()),
+				Err(e) => {
+					println!("Unable to load image {}: {}", file_name, e);
+					None
+				}
+			}
+		}).as_ref()
+	}
+}
+
+struct Layer {
+	picture: Picture,
+	rect: Rectangle<f32>,
+	opacity: f32
+}
+
+/* What the layers are made from: the backdrop is only prepared again when
+ * they change, not for every new version of the configuration */
+#[derive(PartialEq)]
+struct Settings {
+	size: Vector2<u32>,
+	background: BackgroundMode,
+	gradientcolors: Vec<String>,
+	gradientangle: f32,
+	backgroundimage: String,
+	imagescaling: ImageScaling,
+	overlays: Vec<Overlay>
+}
+
+impl Settings {
+	fn of(cfg: &Config, size: Vector2<u32>) -> Settings {
+		Settings {
+			size,
+			background: cfg.disp_background,
+			gradientcolors: cfg.disp_gradientcolors.clone(),
+			gradientangle: cfg.disp_gradientangle,
+			backgroundimage: cfg.disp_backgroundimage.clone(),
+			imagescaling: cfg.disp_imagescaling,
+			overlays: cfg.overlays.clone()
+		}
+	}
+}
+
+pub struct Backdrop {
+	layers: Vec<Layer>,
+	/* None for the empty one */
+	settings: Option<Settings>
+}
+
+/* Color at t, between 0 and 1, of evenly spaced stops */
+fn gradient_color(stops: &[Color], t: f32) -> Color {
+	match stops {
+		[] => Color::BLACK,
+		[c] => *c,
+		_ => {
+			let pos = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+			let i = (pos.floor() as usize).min(stops.len() - 2);
+			let f = pos - i as f32;
+			let (a, b) = (stops[i], stops[i + 1]);
+			let mix = |x: f32, y: f32| x + (y - x) * f;
+			Color::from_rgba(mix(a.r(), b.r()), mix(a.g(), b.g()), mix(a.b(), b.b()), mix(a.a(), b.a()))
+		}
+	}
+}
+
+/* Gradient filling size, t giving the position of each pixel on it.  The
+ * colors are computed once per step of t, not per pixel */
+fn gradient(size: Vector2<u32>, stops: &[Color], t: impl Fn(f32, f32) -> f32) -> RgbaImage {
+	const STEPS: usize = 1024;
+	let table: Vec<Rgba<u8>> = (0..=STEPS)
+		.map(|i| color_to_rgba(gradient_color(stops, i as f32 / STEPS as f32)))
+		.collect();
+	RgbaImage::from_fn(size.x, size.y, |x, y| {
+		let v = t(x as f32 + 0.5, y as f32 + 0.5).clamp(0.0, 1.0);
+		table[(v * STEPS as f32).round() as usize]
+	})
+}
+
+/* Like a CSS linear-gradient: the first color in one corner or side, the
+ * last one in the opposite one */
+fn linear_gradient(size: Vector2<u32>, stops: &[Color], angle: f32) -> RgbaImage {
+	let (w, h) = (size.x as f32, size.y as f32);
+	let (dx, dy) = (angle.to_radians().sin(), -angle.to_radians().cos());
+	let len = (w * dx).abs() + (h * dy).abs();
+	gradient(size, stops, |x, y| ((x - w / 2.0) * dx + (y - h / 2.0) * dy) / len + 0.5)
+}
+
+/* The first color in the center, the last one in the corners */
+fn radial_gradient(size: Vector2<u32>, stops: &[Color]) -> RgbaImage {
+	let (w, h) = (size.x as f32 / 2.0, size.y as f32 / 2.0);
+	gradient(size, stops, |x, y| (((x - w) / w).powi(2) + ((y - h) / h).powi(2)).sqrt() / std::f32::consts::SQRT_2)
+}
+
+/* The largest rectangle with the proportions of image inside area, in its
+ * center.  With cover, the smallest one covering area */
+fn fitted(image: &RgbaImage, area: &Rectangle<f32>, cover: bool) -> Rectangle<f32> {
+	let sx = area.width() / image.width() as f32;
+	let sy = area.height() / image.height() as f32;
+	let scale = if cover { sx.max(sy) } else { sx.min(sy) };
+	let size = Vector2::new(image.width() as f32 * scale, image.height() as f32 * scale);
+	let top_left = area.top_left() + (area.size() - size) / 2.0;
+	Rectangle::new(top_left, top_left + size)
+}
+
+/* The image resized to the pixels of rect, scaling on the GPU would alias */
+fn scaled(image: &RgbaImage, rect: &Rectangle<f32>) -> RgbaImage {
+	let (w, h) = (rect.width().round().max(1.0) as u32, rect.height().round().max(1.0) as u32);
+	if (w, h) == image.dimensions() {
+		image.clone()
+	} else {
+		imageops::resize(image, w, h, FilterType::Triangle)
+	}
+}
+
+fn window_rect(rect: &ZoneRect, size: Vector2<u32>) -> Rectangle<f32> {
+	let (w, h) = (size.x as f32 / 100.0, size.y as f32 / 100.0);
+	Rectangle::from_tuples((rect.x * w, rect.y * h), ((rect.x + rect.w) * w, (rect.y + rect.h) * h))
+}
+
+impl Backdrop {
+	pub fn new(cfg: &Config, size: Vector2<u32>, images: &mut ImageSet) -> Backdrop {
+		let mut layers = Vec::new();
+		let window = Rectangle::from_tuples((0.0, 0.0), (size.x as f32, size.y as f32));
+		let stops: Vec<Color> = cfg.disp_gradientcolors.iter().map(|c| parse_color(c, Color::BLACK)).collect();
+		let layer = |image: RgbaImage, rect: Rectangle<f32>, opacity: f32| Layer { picture: Picture::new(image), rect, opacity };
+
+		match cfg.disp_background {
+			_ if size.x == 0 || size.y == 0 => (),
+			BackgroundMode::Color => (),
+			BackgroundMode::LinearGradient => layers.push(layer(linear_gradient(size, &stops, cfg.disp_gradientangle), window.clone(), 1.0)),
+			BackgroundMode::RadialGradient => layers.push(layer(radial_gradient(size, &stops), window.clone(), 1.0)),
+			BackgroundMode::Image => if let Some(image) = images.get(&cfg.disp_backgroundimage) {
+				match cfg.disp_imagescaling {
+					ImageScaling::Fit => {
+						let rect = fitted(image, &window, false);
+						layers.push(layer(scaled(image, &rect), rect, 1.0));
+					},
+					ImageScaling::Fill => {
+						/* Without the parts outside of the window */
+						let rect = fitted(image, &window, true);
+						let (x, y) = (-rect.top_left().x.min(0.0), -rect.top_left().y.min(0.0));
+						let visible = imageops::crop_imm(&scaled(image, &rect), x as u32, y as u32, size.x, size.y).to_image();
+						layers.push(layer(visible, window.clone(), 1.0));
+					},
+					ImageScaling::Tile => {
+						/* One picture, small images would make many tiles */
+						let mut tiles = RgbaImage::new(size.x, size.y);
+						imageops::tile(&mut tiles, image);
+						layers.push(layer(tiles, window.clone(), 1.0));
+					}
+				}
+			}
+		}
+
+		for o in &cfg.overlays {
+			if o.opacity <= 0.0 {
+				continue;
+			}
+			if let Some(image) = images.get(&o.image) {
+				let rect = fitted(image, &window_rect(&o.rect, size), false);
+				if rect.width() >= 1.0 && rect.height() >= 1.0 {
+					layers.push(layer(scaled(image, &rect), rect, o.opacity));
+				}
+			}
+		}
+		Backdrop { layers, settings: Some(Settings::of(cfg, size)) }
+	}
+
+	pub fn empty() -> Backdrop {
+		Backdrop { layers: Vec::new(), settings: None }
+	}
+
+	/* Whether it is the one Backdrop::new() would prepare */
+	pub fn is_for(&self, cfg: &Config, size: Vector2<u32>) -> bool {
+		self.settings.as_ref().is_some_and(|s| *s == Settings::of(cfg, size))
+	}
+
+	pub fn draw<C: Canvas>(&self, canvas: &mut C) {
+		for l in &self.layers {
+			canvas.draw_picture(&l.picture, &l.rect, l.opacity);
+		}
+	}
+}
@@ -0,0 +1,106 @@
+use image::RgbaImage;
+use speedy2d::color::Color;
+use speedy2d::dimen::Vector2;
+use speedy2d::shape::Rectangle;
+
+use crate::render::{color_to_rgba, Canvas, Picture};
+use crate::render::layout::{FontSet, TextRun};
+
+/* Draws into an RGBA image on the CPU, no window or GPU needed */
+pub struct ImageCanvas {
+	image: RgbaImage,
+	clip: Option<Rectangle<i32>>
+}
+
+impl ImageCanvas {
+	pub fn new(width: u32, height: u32) -> ImageCanvas {
+		ImageCanvas {
+			image: RgbaImage::new(width, height),
+			clip: None
+		}
+	}
+
+	pub fn image(&self) -> &RgbaImage {
+		&self.image
+	}
+
+	fn is_inside_clip(&self, x: i32, y: i32) -> bool {
+		if x < 0 || y < 0 || x >= self.image.width() as i32 || y >= self.image.height() as i32 {
+			return false;
+		}
+		match &self.clip {
+			Some(c) => x >= c.top_left().x && x < c.bottom_right().x &&
+				y >= c.top_left().y && y < c.bottom_right().y,
+			None => true
+		}
+	}
+
+	/* Blends color over the pixel, coverage is the fraction of the pixel
+	 * covered by the shape being drawn */
+	fn blend_pixel(&mut self, x: i32, y: i32, color: Color, coverage: f32) {
+		if !self.is_inside_clip(x, y) {
+			return;
+		}
+		let alpha = (color.a() * coverage).clamp(0.0, 1.0);
+		let px = self.image.get_pixel_mut(x as u32, y as u32);
+		let src = color_to_rgba(color);
+		for i in 0..3 {
+			px.0[i] = (src.0[i] as f32 * alpha + px.0[i] as f32 * (1.0 - alpha)).round() as u8;
+		}
+		px.0[3] = ((alpha + px.0[3] as f32 / 255.0 * (1.0 - alpha)) * 255.0).round() as u8;
+	}
+}
+
+impl Canvas for ImageCanvas {
+	fn clear(&mut self, color: Color) {
+		let px = color_to_rgba(color);
+		for p in self.image.pixels_mut() {
+			*p = px;
+		}
+	}
+
+	fn set_clip(&mut self, clip: Option<Rectangle<i32>>) {
+		self.clip = clip;
+	}
+
+	fn fill_rect(&mut self, rect: &Rectangle<i32>, color: Color) {
+		for y in rect.top_left().y..rect.bottom_right().y {
+			for x in rect.top_left().x..rect.bottom_right().x {
+				self.blend_pixel(x, y, color, 1.0);
+			}
+		}
+	}
+
+	fn draw_run(&mut self, fonts: &FontSet, run: &TextRun, origin: Vector2<f32>, color: Color) {
+		let baseline = rusttype::point(origin.x + run.x, origin.y + run.baseline);
+		let (glyphs, _) = fonts.get(run.font).position_glyphs(&run.text, run.size, baseline);
+		for g in glyphs {
+			if let Some(bb) = g.pixel_bounding_box() {
+				g.draw(|gx, gy, coverage| {
+					self.blend_pixel(bb.min.x + gx as i32, bb.min.y + gy as i32, color, coverage);
+				});
+			}
+		}
+	}
+
+	/* Nearest pixel, the pictures have the size they are drawn with */
+	fn draw_picture(&mut self, picture: &Picture, rect: &Rectangle<f32>, opacity: f32) {
+		let (pw, ph) = picture.image.dimensions();
+		if pw == 0 || ph == 0 || rect.width() <= 0.0 || rect.height() <= 0.0 {
+			return;
+		}
+		let x0 = rect.top_left().x.round().max(0.0) as i32;
+		let y0 = rect.top_left().y.round().max(0.0) as i32;
+		let x1 = rect.bottom_right().x.round().min(self.image.width() as f32) as i32;
+		let y1 = rect.bottom_right().y.round().min(self.image.height() as f32) as i32;
+		for y in y0..y1 {
+			let sy = ((y as f32 + 0.5 - rect.top_left().y) / rect.height() * ph as f32) as u32;
+			for x in x0..x1 {
+				let sx = ((x as f32 + 0.5 - rect.top_left().x) / rect.width() * pw as f32) as u32;
+				let p = picture.image.get_pixel(sx.min(pw - 1), sy.min(ph - 1)).0;
+				let color = Color::from_int_rgba(p[0], p[1], p[2], p[3]);
+				self.blend_pixel(x, y, color, opacity);
+			}
+		}
+	}
+}
@@ -0,0 +1,886 @@
+/* Text layout shared by the window and by the offscreen renderer.
+ *
+ * speedy2d does not expose the position of the glyphs it lays out, so we
+ * lay out the text here with rusttype, following the same rules used by
+ * speedy2d's Font::layout_text() (word wrapping, line metrics, kerning).
+ * The result is a list of runs, each one on a single line with a single
+ * style: the window draws each run with speedy2d, the offscreen renderer
+ * rasterises the same runs on the CPU. */
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use rusttype::{Scale, Point, PositionedGlyph, VMetrics};
+use speedy2d::font::{FormattedTextBlock, TextLayout, TextOptions};
+
+use crate::fonts::{FontCatalog, FontInfo, EMBEDDED_FONT};
+use crate::lconfig::TextAlign;
+use crate::render::markup::{Span, TextStyle};
+use crate::render::shaping;
+
+/* There is no bold font: bold text is drawn a second time, shifted to the
+ * right by a fraction of the font size */
+const BOLD_SHIFT_RATIO: f32 = 24.0;
+
+pub fn bold_shift(size: f32) -> f32 {
+	(size / BOLD_SHIFT_RATIO).max(1.0)
+}
+
+/* Glyphs used in place of a character missing from the font, like speedy2d */
+const MISSING_GLYPH_REPLACEMENTS: [char; 2] = ['□', '?'];
+
+/* Invisible characters changing the characters around them: variation
+ * selectors, joiners, tags of the flag emoji...  Drawn by nothing when the
+ * font does not have them */
+pub fn is_default_ignorable(c: char) -> bool {
+	matches!(c as u32, 0x00AD | 0x034F | 0x061C | 0x180B..=0x180F | 0x200B..=0x200F | 0x202A..=0x202E |
+		0x2060..=0x206F | 0xFE00..=0xFE0F | 0xFEFF | 0xE0000..=0xE0FFF)
+}
+
+pub struct LoadedFont {
+	pub rt_font: rusttype::Font<'static>,
+	pub gpu_font: speedy2d::font::Font
+}
+
+/* The fonts of the catalog loaded so far.  The first one is the embedded
+ * font, used when a font is not found */
+pub struct FontSet {
+	catalog: FontCatalog,
+	fonts: Vec<LoadedFont>,
+	/* Index in the catalog of each font */
+	loaded: Vec<usize>,
+	/* Fonts of the catalog that could not be loaded */
+	failed: Vec<usize>,
+	/* Families for the characters missing from the font of a zone */
+	fallback: Vec<String>
+}
+
+impl LoadedFont {
+	pub fn from_bytes(bytes: &[u8]) -> Option<LoadedFont> {
+		let rt_font = rusttype::Font::try_from_vec(bytes.to_vec())?;
+		let gpu_font = speedy2d::font::Font::new(bytes).ok()?;
+		Some(LoadedFont { rt_font, gpu_font })
+	}
+
+	pub fn has_glyph(&self, c: char) -> bool {
+		self.rt_font.glyph(c).id().0 != 0
+	}
+
+	fn glyph_for_char(&self, c: char) -> Option<rusttype::Glyph<'static>> {
+		let glyph = self.rt_font.glyph(c);
+		if glyph.id().0 != 0 {
+			return Some(glyph);
+		}
+		if is_default_ignorable(c) {
+			return None;
+		}
+		MISSING_GLYPH_REPLACEMENTS.iter()
+			.map(|r| self.rt_font.glyph(*r))
+			.find(|g| g.id().0 != 0)
+	}
+
+	/* Positions the glyphs of a single line of text, with the baseline
+	 * starting at origin. Returns the glyphs and the advance width. */
+	pub fn position_glyphs(&self, text: &str, size: f32, origin: Point<f32>) -> (Vec<PositionedGlyph<'static>>, f32) {
+		let scale = Scale::uniform(size);
+		let mut glyphs = Vec::with_capacity(text.len());
+		let mut x = 0.0;
+		let mut last_glyph_id = None;
+
+		for c in text.chars() {
+			let glyph = match self.glyph_for_char(c) {
+				Some(g) => g.scaled(scale),
+				None => continue
+			};
+			if let Some(last) = last_glyph_id {
+				x += self.rt_font.pair_kerning(scale, last, glyph.id());
+			}
+			last_glyph_id = Some(glyph.id());
+			let advance = glyph.h_metrics().advance_width;
+			glyphs.push(glyph.positioned(rusttype::point(origin.x + x, origin.y)));
+			x += advance;
+		}
+		(glyphs, x)
+	}
+
+	fn advance_width(&self, text: &str, size: f32) -> f32 {
+		self.position_glyphs(text, size, rusttype::point(0.0, 0.0)).1
+	}
+}
+
+impl FontSet {
+	pub fn new(catalog: FontCatalog) -> FontSet {
+		let embedded = LoadedFont::from_bytes(EMBEDDED_FONT).expect("Unable to load embedded font");
+		FontSet { catalog, fonts: vec![embedded], loaded: vec![0], failed: Vec::new(), fallback: Vec::new() }
+	}
+
+	pub fn get(&self, idx: usize) -> &LoadedFont {
+		&self.fonts[idx]
+	}
+
+	pub fn catalog_generation(&self) -> u64 {
+		self.catalog.generation
+	}
+
+	/* The fonts already loaded stay, the zones may be drawing with them */
+	pub fn set_catalog(&mut self, catalog: FontCatalog) {
+		let same = |a: &FontInfo, b: &FontInfo| a.path == b.path && a.family == b.family && a.weight == b.weight;
+		self.loaded = self.loaded.iter().map(|ci| {
+			self.catalog.fonts.get(*ci)
+				.and_then(|old| catalog.fonts.iter().position(|f| same(f, old)))
+				.unwrap_or(usize::MAX)
+		}).collect();
+		self.failed.clear();
+		self.catalog = catalog;
+	}
+
+	pub fn fallback(&self) -> &[String] {
+		&self.fallback
+	}
+
+	/* The families to use for the missing characters, loaded with
+	 * load_with_fallback() */
+	pub fn set_fallback(&mut self, families: &[String]) {
+		self.fallback = families.to_vec();
+	}
+
+	/* Loads the font of family closest to weight, if it is not loaded yet */
+	pub fn load(&mut self, family: &str, weight: u16) {
+		if family.trim().is_empty() {
+			return;
+		}
+		match self.catalog.find(family, weight) {
+			Some(ci) => self.load_catalog_font(ci),
+			None => println!("Font {} not found, using {}", family, self.catalog.fonts[0].family)
+		}
+	}
+
+	/* Loads the font of a zone and the fallback fonts of the same weight.
+	 * The fallback fonts missing from the catalog are silently skipped */
+	pub fn load_with_fallback(&mut self, family: &str, weight: u16) {
+		self.load(family, weight);
+		for i in 0..self.fallback.len() {
+			if let Some(ci) = self.catalog.find(&self.fallback[i], weight) {
+				self.load_catalog_font(ci);
+			}
+		}
+	}
+
+	fn load_catalog_font(&mut self, ci: usize) {
+		if self.loaded.contains(&ci) || self.failed.contains(&ci) {
+			return;
+		}
+		let info = &self.catalog.fonts[ci];
+		match info.load().ok().and_then(|b| LoadedFont::from_bytes(b.as_slice())) {
+			Some(f) => {
+				println!("Loaded font {} {} from {}", info.family, info.weight, info.file);
+				self.fonts.push(f);
+				self.loaded.push(ci);
+			},
+			None => {
+				eprintln!("Unable to load font {}", info.file);
+				self.failed.push(ci);
+			}
+		}
+	}
+
+	fn find_loaded(&self, family: &str, weight: u16) -> Option<usize> {
+		self.catalog.find(family, weight)
+			.and_then(|ci| self.loaded.iter().position(|l| *l == ci))
+	}
+
+	/* The loaded font of family closest to weight, the embedded font when
+	 * there is none */
+	pub fn find(&self, family: &str, weight: u16) -> usize {
+		self.find_loaded(family, weight).unwrap_or(0)
+	}
+
+	/* The fonts to draw the text of a zone with: its font, the loaded
+	 * fallback fonts, then the embedded font */
+	pub fn fallback_chain(&self, family: &str, weight: u16) -> Vec<usize> {
+		let mut chain = vec![self.find(family, weight)];
+		let fallback = self.fallback.iter().filter_map(|f| self.find_loaded(f, weight));
+		for f in fallback.chain(std::iter::once(0)) {
+			if !chain.contains(&f) {
+				chain.push(f);
+			}
+		}
+		chain
+	}
+}
+
+/* Splits text into the pieces drawn with the same font: the first of chain
+ * having the character, or the first of chain when none has it */
+fn font_segments<'a>(fonts: &FontSet, chain: &[usize], text: &'a str) -> Vec<(usize, &'a str)> {
+	let mut segments: Vec<(usize, &str)> = Vec::new();
+	let mut start = 0;
+	let mut current = None;
+	for (i, c) in text.char_indices() {
+		/* Spaces and joiners stay in the font of the text around them */
+		let font = match current {
+			Some(f) if c.is_whitespace() || is_default_ignorable(c) => f,
+			_ => chain.iter().copied().find(|f| fonts.get(*f).has_glyph(c)).unwrap_or(chain[0])
+		};
+		match current {
+			Some(f) if f != font => {
+				segments.push((f, &text[start..i]));
+				start = i;
+			},
+			_ => ()
+		}
+		current = Some(font);
+	}
+	if let Some(f) = current {
+		segments.push((f, &text[start..]));
+	}
+	segments
+}
+
+/* A piece of text on a single line, drawn with one font, size and style */
+pub struct TextRun {
+	pub text: String,
+	pub font: usize,
+	pub size: f32,
+	pub style: TextStyle,
+	/* Position of the start of the baseline, relative to the block top left */
+	pub x: f32,
+	pub baseline: f32,
+	pub width: f32,
+	pub ascent: f32,
+	/* Negative, below the baseline */
+	pub descent: f32,
+	/* The run laid out by speedy2d, built the first time the window draws it */
+	gpu_block: RefCell<Option<Rc<FormattedTextBlock>>>
+}
+
+impl TextRun {
+	/* The first n characters of the run, for the text being typed */
+	pub fn prefix(&self, fonts: &FontSet, n: usize) -> TextRun {
+		let text: String = self.text.chars().take(n).collect();
+		let mut width = fonts.get(self.font).advance_width(&text, self.size);
+		if self.style.bold {
+			width += bold_shift(self.size);
+		}
+		TextRun {
+			text,
+			font: self.font,
+			size: self.size,
+			style: self.style,
+			x: self.x,
+			baseline: self.baseline,
+			width,
+			ascent: self.ascent,
+			descent: self.descent,
+			gpu_block: RefCell::new(None)
+		}
+	}
+
+	pub fn gpu_block(&self, fonts: &FontSet) -> Rc<FormattedTextBlock> {
+		self.gpu_block.borrow_mut()
+			.get_or_insert_with(|| {
+				fonts.get(self.font).gpu_font.layout_text(&self.text, self.size, TextOptions::new())
+			})
+			.clone()
+	}
+}
+
+pub struct TextBlock {
+	pub runs: Vec<TextRun>,
+	pub width: f32,
+	pub height: f32,
+	/* Top and bottom of each line */
+	pub lines: Vec<(f32, f32)>,
+	/* The text starts with a right-to-left script */
+	pub rtl: bool
+}
+
+impl TextBlock {
+	pub fn char_count(&self) -> usize {
+		self.runs.iter().map(|r| r.text.chars().count()).sum()
+	}
+}
+
+#[derive(Clone)]
+enum Word<'a> {
+	/* Pieces of text of consecutive spans with no break between them,
+	 * with the index of their span */
+	Text(Vec<(usize, &'a str)>),
+	Space(usize, &'a str),
+	Newline
+}
+
+fn split_words(spans: &[Span]) -> Vec<Word<'_>> {
+	let mut words = Vec::new();
+	let mut word: Vec<(usize, &str)> = Vec::new();
+	for (si, span) in spans.iter().enumerate() {
+		let text = span.text.as_str();
+		let mut start = None;
+		for (i, c) in text.char_indices() {
+			let is_break = matches!(c, ' ' | '\t' | '\r' | '\n' | '\u{200B}');
+			if is_break {
+				if let Some(s) = start.take() {
+					word.push((si, &text[s..i]));
+				}
+				if !word.is_empty() {
+					words.push(Word::Text(std::mem::take(&mut word)));
+				}
+				match c {
+					'\n' => words.push(Word::Newline),
+					' ' | '\t' => words.push(Word::Space(si, &text[i..i + c.len_utf8()])),
+					_ => {}
+				}
+			} else if start.is_none() {
+				start = Some(i);
+			}
+		}
+		/* The word may go on in the next span */
+		if let Some(s) = start {
+			word.push((si, &text[s..]));
+		}
+	}
+	if !word.is_empty() {
+		words.push(Word::Text(word));
+	}
+	words
+}
+
+/* A line being laid out: text of consecutive spans */
+#[derive(Clone, Default)]
+struct Line {
+	parts: Vec<(usize, String)>,
+	/* Index of the paragraph, the lines between two newlines */
+	paragraph: usize,
+	/* The line goes on from the one before, wrapped */
+	continued: bool
+}
+
+impl Line {
+	fn is_empty(&self) -> bool {
+		self.parts.is_empty()
+	}
+
+	fn push(&mut self, span: usize, text: &str) {
+		match self.parts.last_mut() {
+			Some((s, t)) if *s == span => t.push_str(text),
+			_ => self.parts.push((span, text.to_string()))
+		}
+	}
+}
+
+/* The spans with the Arabic letters in their contextual forms.  Letters
+ * join across spans, a word may change color in the middle */
+fn shape_spans(fonts: &FontSet, chain: &[usize], spans: &[Span]) -> Vec<Span> {
+	let chars: Vec<(char, usize)> = spans.iter().enumerate()
+		.flat_map(|(si, s)| s.text.chars().map(move |c| (c, si)))
+		.collect();
+	let shaped = shaping::shape_arabic(&chars, |c| chain.iter().any(|f| fonts.get(*f).has_glyph(c)));
+	let mut out: Vec<Span> = spans.iter().map(|s| Span { text: String::new(), style: s.style }).collect();
+	for (c, si) in shaped {
+		out[si].text.push(c);
+	}
+	out
+}
+
+/* The characters of a line in display order, by span.  rtl is the
+ * direction of its paragraph, wrapped when the line goes on in the next */
+fn visual_parts(line: &Line, rtl: bool, wrapped: bool) -> Vec<(usize, String)> {
+	let mut chars: Vec<(char, usize)> = line.parts.iter()
+		.flat_map(|(si, t)| t.chars().map(move |c| (c, *si)))
+		.collect();
+	/* The spaces where a line has been wrapped are not drawn */
+	while wrapped && chars.last().is_some_and(|(c, _)| matches!(c, ' ' | '\t')) {
+		chars.pop();
+	}
+	let text: String = chars.iter().map(|(c, _)| *c).collect();
+	let offsets: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+
+	let mut parts: Vec<(usize, String)> = Vec::new();
+	for (range, run_rtl) in shaping::visual_runs(&text, rtl) {
+		let run = &chars[offsets.partition_point(|o| *o < range.start)..offsets.partition_point(|o| *o < range.end)];
+		let run = if run_rtl { shaping::reverse_rtl(run) } else { run.to_vec() };
+		for (c, si) in run {
+			match parts.last_mut() {
+				Some((s, t)) if *s == si => t.push(c),
+				_ => parts.push((si, c.to_string()))
+			}
+		}
+	}
+	parts
+}
+
+/* Splits text in words and single spaces, the spaces of justified lines
+ * are stretched */
+fn split_spaces(text: &str) -> Vec<&str> {
+	let mut pieces = Vec::new();
+	let mut start = 0;
+	for (i, c) in text.char_indices() {
+		if c == ' ' {
+			if i > start {
+				pieces.push(&text[start..i]);
+			}
+			pieces.push(&text[i..i + 1]);
+			start = i + 1;
+		}
+	}
+	if start < text.len() {
+		pieces.push(&text[start..]);
+	}
+	pieces
+}
+
+/* Lays out the spans of text, optionally wrapping lines to wrap_width.
+ * size is the font size of the text without [size].  The lines are
+ * aligned in wrap_width, or in the widest line when not wrapping */
+pub fn layout_spans(fonts: &FontSet, chain: &[usize], spans: &[Span], size: f32, wrap_width: Option<f32>, align: TextAlign) -> TextBlock {
+	let shaped = shape_spans(fonts, chain, spans);
+	let spans = shaped.as_slice();
+	let span_size = |si: usize| size * spans[si].style.scale;
+	/* Width of a run of a single font */
+	let run_width = |si: usize, font: usize, text: &str| -> f32 {
+		let w = fonts.get(font).advance_width(text, span_size(si));
+		if spans[si].style.bold { w + bold_shift(span_size(si)) } else { w }
+	};
+	let width_of = |parts: &[(usize, String)]| -> f32 {
+		parts.iter()
+			.flat_map(|(si, t)| font_segments(fonts, chain, t).into_iter().map(move |(f, st)| run_width(*si, f, st)))
+			.sum()
+	};
+	/* Width of line with the text added at its end */
+	let width_with = |line: &Line, added: &[(usize, &str)]| -> f32 {
+		let mut l = line.clone();
+		for (si, t) in added {
+			l.push(*si, t);
+		}
+		width_of(&l.parts)
+	};
+
+	let mut lines: Vec<Line> = Vec::new();
+	let mut line = Line::default();
+	let mut paragraph = 0;
+	let mut pending: VecDeque<Word> = split_words(spans).into();
+
+	while let Some(word) = pending.pop_front() {
+		let (fits, parts) = match word {
+			Word::Newline => {
+				lines.push(std::mem::take(&mut line));
+				paragraph += 1;
+				line.paragraph = paragraph;
+				continue;
+			},
+			/* Whitespace at the start of a wrapped line is skipped */
+			Word::Space(..) if line.is_empty() && line.continued => continue,
+			Word::Space(si, w) => (wrap_width.is_none_or(|ww| width_with(&line, &[(si, w)]) <= ww), vec![(si, w)]),
+			Word::Text(ref t) => (wrap_width.is_none_or(|ww| width_with(&line, t) <= ww), t.clone())
+		};
+
+		if fits {
+			for (si, t) in &parts {
+				line.push(*si, t);
+			}
+			continue;
+		}
+
+		if let (Word::Text(_), true) = (&word, line.is_empty()) {
+			/* A single word wider than the line: split it, keeping at
+			 * least one character on each line.  chars holds the part
+			 * and the end in the part of each character */
+			let ww = wrap_width.unwrap_or(f32::MAX);
+			let chars: Vec<(usize, usize)> = parts.iter().enumerate()
+				.flat_map(|(pi, (_, t))| t.char_indices().map(move |(ci, c)| (pi, ci + c.len_utf8())))
+				.collect();
+			let head = |n: usize| -> Vec<(usize, &str)> {
+				let mut h: Vec<(usize, &str)> = Vec::new();
+				for &(pi, end) in &chars[..n] {
+					let (si, t) = parts[pi];
+					if h.len() == pi + 1 {
+						h[pi] = (si, &t[..end]);
+					} else {
+						h.push((si, &t[..end]));
+					}
+				}
+				h
+			};
+			let mut split = 1;
+			for n in 2..chars.len() {
+				if width_with(&line, &head(n)) > ww {
+					break;
+				}
+				split = n;
+			}
+			for (si, t) in head(split) {
+				line.push(si, t);
+			}
+			if split < chars.len() {
+				let (pi, start) = match chars[split - 1] {
+					(pi, end) if end == parts[pi].1.len() => (pi + 1, 0),
+					(pi, end) => (pi, end)
+				};
+				let mut rest = vec![(parts[pi].0, &parts[pi].1[start..])];
+				rest.extend_from_slice(&parts[pi + 1..]);
+				pending.push_front(Word::Text(rest));
+			}
+		} else if let Word::Text(_) = word {
+			pending.push_front(word);
+		}
+		lines.push(std::mem::take(&mut line));
+		line.paragraph = paragraph;
+		line.continued = true;
+	}
+	if !line.is_empty() || lines.is_empty() {
+		lines.push(line);
+	}
+
+	/* Direction of each paragraph, the one of the whole text when it has
+	 * no letter */
+	let all_text: String = spans.iter().map(|s| s.text.as_str()).collect();
+	let rtl = shaping::text_direction(&all_text).unwrap_or(false);
+	let mut para_rtl = vec![rtl; paragraph + 1];
+	for (p, dir) in para_rtl.iter_mut().enumerate() {
+		let text: String = lines.iter()
+			.filter(|l| l.paragraph == p)
+			.flat_map(|l| l.parts.iter().map(|(_, t)| t.as_str()))
+			.collect();
+		*dir = shaping::text_direction(&text).unwrap_or(rtl);
+	}
+
+	let base_vm = fonts.get(chain[0]).rt_font.v_metrics(Scale::uniform(size));
+	let vm_of = |si: usize, font: usize| fonts.get(font).rt_font.v_metrics(Scale::uniform(span_size(si)));
+	let mut runs = Vec::new();
+	/* Runs, width, end of paragraph and direction of each line */
+	let mut line_info = Vec::new();
+	let mut line_boxes = Vec::with_capacity(lines.len());
+	let mut y = 0.0;
+	let mut width: f32 = 0.0;
+	let nlines = lines.len();
+	for i in 0..nlines {
+		let line = &lines[i];
+		let line_rtl = para_rtl[line.paragraph];
+		let last = i + 1 == nlines || lines[i + 1].paragraph != line.paragraph;
+		let parts = visual_parts(line, line_rtl, i + 1 < nlines && lines[i + 1].continued);
+		let mut segments: Vec<(usize, usize, &str)> = parts.iter()
+			.flat_map(|(si, t)| font_segments(fonts, chain, t).into_iter().map(move |(f, st)| (*si, f, st)))
+			.collect();
+		if align == TextAlign::Justify && !last {
+			segments = segments.into_iter()
+				.flat_map(|(si, f, t)| split_spaces(t).into_iter().map(move |p| (si, f, p)))
+				.collect();
+		}
+		/* The line is as high as its largest text */
+		let vm = segments.iter()
+			.map(|(si, f, _)| vm_of(*si, *f))
+			.fold(None, |acc: Option<VMetrics>, m| Some(match acc {
+				Some(a) => VMetrics {
+					ascent: a.ascent.max(m.ascent),
+					descent: a.descent.min(m.descent),
+					line_gap: a.line_gap.max(m.line_gap)
+				},
+				None => m
+			}))
+			.unwrap_or(base_vm);
+
+		let first = runs.len();
+		let mut x = 0.0;
+		for (si, font, text) in segments {
+			let run_vm = vm_of(si, font);
+			let w = run_width(si, font, text);
+			runs.push(TextRun {
+				text: text.to_string(),
+				font,
+				size: span_size(si),
+				style: spans[si].style,
+				x,
+				baseline: y + vm.ascent,
+				width: w,
+				ascent: run_vm.ascent,
+				descent: run_vm.descent,
+				gpu_block: RefCell::new(None)
+			});
+			x += w;
+		}
+		line_info.push((first..runs.len(), x, last, line_rtl));
+		width = width.max(x);
+		line_boxes.push((y, y + vm.ascent - vm.descent));
+		y += vm.ascent - vm.descent;
+		if i + 1 < nlines {
+			y += vm.line_gap;
+		}
+	}
+
+	let avail = wrap_width.unwrap_or(width);
+	for (range, w, last, line_rtl) in line_info {
+		let free = (avail - w).max(0.0);
+		let line_runs = &mut runs[range];
+		match align {
+			TextAlign::Justify if !last => {
+				let spaces = line_runs.iter().filter(|r| r.text == " ").count();
+				if spaces > 0 {
+					let extra = free / spaces as f32;
+					let mut shift = 0.0;
+					for r in line_runs.iter_mut() {
+						r.x += shift;
+						if r.text == " " {
+							shift += extra;
+						}
+					}
+					width = width.max(avail);
+				}
+				continue;
+			},
+			_ => ()
+		}
+		let shift = match align {
+			TextAlign::Left => 0.0,
+			TextAlign::Center => free / 2.0,
+			TextAlign::Right => free,
+			TextAlign::Auto | TextAlign::Justify => if line_rtl { free } else { 0.0 }
+		};
+		for r in line_runs.iter_mut() {
+			r.x += shift;
+		}
+		width = width.max(w + shift);
+	}
+
+	TextBlock { runs, width, height: y, lines: line_boxes, rtl }
+}
+
+/* Width of the widest word of the spans, lines are never narrower */
+fn widest_word(fonts: &FontSet, chain: &[usize], spans: &[Span], size: f32) -> f32 {
+	split_words(spans).iter()
+		.filter_map(|w| match w {
+			Word::Text(parts) => Some(parts.iter()
+				.flat_map(|(si, t)| font_segments(fonts, chain, t).into_iter().map(move |(f, st)| (*si, f, st)))
+				.map(|(si, f, st)| {
+					let sz = size * spans[si].style.scale;
+					let w = fonts.get(f).advance_width(st, sz);
+					if spans[si].style.bold { w + bold_shift(sz) } else { w }
+				})
+				.sum::<f32>()),
+			_ => None
+		})
+		.fold(0.0, f32::max)
+}
+
+/* Fitted font sizes are searched from this one, measured at the base */
+const MIN_SIZE: f32 = 4.0;
+const BASE_SIZE: f32 = 100.0;
+
+/* The largest font size with which the spans fit in width x height,
+ * wrapping the lines between the words */
+pub fn fit_size(fonts: &FontSet, chain: &[usize], spans: &[Span], width: f32, height: f32, align: TextAlign) -> f32 {
+	if width < 1.0 || height < 1.0 {
+		return MIN_SIZE;
+	}
+	/* The size grows with the text, the widest word gives the largest size */
+	let ww = widest_word(fonts, chain, spans, BASE_SIZE);
+	let mut hi = if ww > 0.0 { BASE_SIZE * width / ww } else { height };
+	hi = hi.min(height * 2.0);
+	let mut lo = MIN_SIZE;
+	if hi <= lo {
+		return lo;
+	}
+	let fits = |size: f32| layout_spans(fonts, chain, spans, size, Some(width), align).height <= height;
+	if fits(hi) {
+		return hi.floor();
+	}
+	while hi - lo > 0.5 {
+		let mid = (lo + hi) / 2.0;
+		if fits(mid) {
+			lo = mid;
+		} else {
+			hi = mid;
+		}
+	}
+	lo.floor()
+}
+
+/* The largest font size with which the lines of the spans fill height,
+ * or with which lines lines of the tallest one do.  The height of the
+ * lines grows with the size, the width does not matter */
+pub fn fit_height(fonts: &FontSet, chain: &[usize], spans: &[Span], height: f32, lines: Option<u16>) -> f32 {
+	let block = layout_spans(fonts, chain, spans, BASE_SIZE, None, TextAlign::Auto);
+	let tallest = block.lines.iter().map(|(top, bottom)| bottom - top).fold(0.0, f32::max);
+	let base_height = match lines {
+		_ if tallest <= 0.0 => {
+			let vm = fonts.get(chain[0]).rt_font.v_metrics(Scale::uniform(BASE_SIZE));
+			(vm.ascent - vm.descent) * lines.unwrap_or(1) as f32
+		},
+		Some(n) => tallest * n as f32,
+		None => block.height
+	};
+	(BASE_SIZE * height / base_height).floor().max(MIN_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::path::{Path, PathBuf};
+
+	use crate::fonts::{FontSource, DEFAULT_FONT_WEIGHT};
+	use crate::render::markup::parse_markup;
+
+	/* An icon font of the web interface, without any letter */
+	const ICON_FONT: &str = "runtime-data/html/css/fa/webfonts/fa-solid-900.ttf";
+
+	fn catalog_with(fonts: &[(&str, PathBuf)]) -> FontCatalog {
+		let mut c = FontCatalog::embedded();
+		for (family, path) in fonts {
+			c.fonts.push(FontInfo {
+				family: family.to_string(),
+				weight: DEFAULT_FONT_WEIGHT,
+				italic: false,
+				source: FontSource::RuntimeData,
+				file: path.file_name().unwrap().to_string_lossy().into_owned(),
+				path: Some(path.clone())
+			});
+		}
+		c
+	}
+
+	fn layout(fonts: &FontSet, family: &str, text: &str) -> TextBlock {
+		let chain = fonts.fallback_chain(family, DEFAULT_FONT_WEIGHT);
+		layout_spans(fonts, &chain, &parse_markup(text), 32.0, Some(400.0), TextAlign::Auto)
+	}
+
+	/* The characters of the block drawn with .notdef: not in the font of
+	 * their run */
+	fn notdef_chars(fonts: &FontSet, block: &TextBlock) -> Vec<char> {
+		block.runs.iter()
+			.flat_map(|r| r.text.chars().map(move |c| (r.font, c)))
+			.filter(|(f, c)| !c.is_whitespace() && !is_default_ignorable(*c) && !fonts.get(*f).has_glyph(*c))
+			.map(|(_, c)| c)
+			.collect()
+	}
+
+	fn assert_no_notdef(fonts: &FontSet, block: &TextBlock) {
+		assert_eq!(notdef_chars(fonts, block), Vec::<char>::new());
+		for r in &block.runs {
+			let (glyphs, _) = fonts.get(r.font).position_glyphs(&r.text, r.size, rusttype::point(0.0, 0.0));
+			assert!(glyphs.iter().all(|g| g.id().0 != 0), "notdef glyph in {:?}", r.text);
+		}
+	}
+
+	#[test]
+	fn embedded_font_covers_european_scripts() {
+		let fonts = FontSet::new(FontCatalog::embedded());
+		let block = layout(&fonts, "", "Hello Ελληνικά Кириллица Ærøskøbing Łódź «€»");
+		assert_no_notdef(&fonts, &block);
+		assert!(block.runs.iter().all(|r| r.font == 0));
+	}
+
+	#[test]
+	fn missing_characters_use_the_next_font_of_the_chain() {
+		let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(ICON_FONT);
+		let mut fonts = FontSet::new(catalog_with(&[("Icons", path)]));
+		fonts.load_with_fallback("Icons", DEFAULT_FONT_WEIGHT);
+		let icons = fonts.find("Icons", DEFAULT_FONT_WEIGHT);
+		assert_ne!(icons, 0);
+		assert!(!fonts.get(icons).has_glyph('A'));
+
+		let block = layout(&fonts, "Icons", "\u{f007} User Пользователь [b]\u{f015}[/b] Σπίτι");
+		assert_no_notdef(&fonts, &block);
+		let font_of = |text: &str| block.runs.iter().find(|r| r.text.contains(text)).map(|r| r.font);
+		assert_eq!(font_of("\u{f007}"), Some(icons));
+		assert_eq!(font_of("\u{f015}"), Some(icons));
+		assert_eq!(font_of("User"), Some(0));
+		assert_eq!(font_of("Σπίτι"), Some(0));
+	}
+
+	#[test]
+	fn configured_fallback_fonts_come_first() {
+		let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(ICON_FONT);
+		let mut fonts = FontSet::new(catalog_with(&[("Icons", path)]));
+		fonts.set_fallback(&[String::from("Missing"), String::from("Icons")]);
+		fonts.load_with_fallback("", DEFAULT_FONT_WEIGHT);
+		let icons = fonts.find("Icons", DEFAULT_FONT_WEIGHT);
+		assert_eq!(fonts.fallback_chain("", DEFAULT_FONT_WEIGHT), vec![0, icons]);
+		assert_eq!(fonts.fallback_chain("Icons", DEFAULT_FONT_WEIGHT), vec![icons, 0]);
+
+		let block = layout(&fonts, "", "Home \u{f015}");
+		assert_no_notdef(&fonts, &block);
+		assert_eq!(block.runs.last().map(|r| r.font), Some(icons));
+	}
+
+	#[test]
+	fn invisible_characters_are_not_drawn() {
+		let fonts = FontSet::new(FontCatalog::embedded());
+		/* Variation selector, zero width joiner, byte order mark */
+		let text = "A\u{fe0f}B\u{200d}C\u{feff}";
+		let block = layout(&fonts, "", text);
+		assert_no_notdef(&fonts, &block);
+		let (glyphs, _) = fonts.get(0).position_glyphs(text, 32.0, rusttype::point(0.0, 0.0));
+		assert_eq!(glyphs.len(), 3);
+	}
+
+	#[test]
+	fn auto_size_fills_the_height() {
+		let fonts = FontSet::new(FontCatalog::embedded());
+		let chain = fonts.fallback_chain("", DEFAULT_FONT_WEIGHT);
+		let height_at = |text: &str, size: f32| layout_spans(&fonts, &chain, &parse_markup(text), size, None, TextAlign::Auto).height;
+
+		let size = fit_height(&fonts, &chain, &parse_markup("Hello [b]world[/b]"), 120.0, None);
+		assert!(height_at("Hello [b]world[/b]", size) <= 120.0);
+		assert!(height_at("Hello [b]world[/b]", size + 2.0) > 120.0);
+
+		/* Three lines of a single-line text */
+		let size = fit_height(&fonts, &chain, &parse_markup("Hello"), 120.0, Some(3));
+		assert!(height_at("One\nTwo\nThree", size) <= 120.0);
+		assert!(height_at("One\nTwo\nThree", size + 2.0) > 120.0);
+	}
+
+	/* Characters no font has are drawn as .notdef, the rest of the text
+	 * still finds its fonts */
+	#[test]
+	fn characters_no_font_has_do_not_stop_the_layout() {
+		let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(ICON_FONT);
+		let mut fonts = FontSet::new(catalog_with(&[("Icons", path)]));
+		fonts.set_fallback(&[String::from("Icons")]);
+		fonts.load_with_fallback("", DEFAULT_FONT_WEIGHT);
+		let icons = fonts.find("Icons", DEFAULT_FONT_WEIGHT);
+
+		let block = layout(&fonts, "", "Home \u{f015} العربية 日本語 Ελληνικά 👍");
+		let mut missing = notdef_chars(&fonts, &block);
+		missing.sort();
+		let mut expected: Vec<char> = "العربية日本語👍".chars().collect();
+		expected.sort();
+		assert_eq!(missing, expected);
+		let font_of = |text: &str| block.runs.iter().find(|r| r.text.contains(text)).map(|r| r.font);
+		assert_eq!(font_of("Home"), Some(0));
+		assert_eq!(font_of("\u{f015}"), Some(icons));
+		assert_eq!(font_of("Ελληνικά"), Some(0));
+	}
+
+	/* Fonts of assets/test-fonts, each covering the blocks of some scripts */
+	const TEST_FONTS: &[(&str, &str)] = &[
+		("Test Fallback RTL", "TestFallbackRTL.ttf"),
+		("Test Fallback Indic", "TestFallbackIndic.ttf"),
+		("Test Fallback CJK", "TestFallbackCJK.ttf"),
+		("Test Fallback Symbols", "TestFallbackSymbols.ttf")
+	];
+
+	#[test]
+	fn fallback_fonts_cover_mixed_scripts() {
+		let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/test-fonts");
+		let mut fonts = FontSet::new(catalog_with(&TEST_FONTS.iter().map(|(f, file)| (*f, dir.join(file))).collect::<Vec<_>>()));
+		fonts.set_fallback(&TEST_FONTS.iter().map(|(f, _)| f.to_string()).collect::<Vec<_>>());
+		fonts.load_with_fallback("", DEFAULT_FONT_WEIGHT);
+		let font = |i: usize| fonts.find(TEST_FONTS[i].0, DEFAULT_FONT_WEIGHT);
+		assert!((0..TEST_FONTS.len()).all(|i| font(i) != 0));
+
+		let texts = [
+			("Latin Ελληνικά Русский", &["Ελληνικά"][..], 0),
+			("English עברית العربية mixed", &["עברית"], font(0)),
+			("हिन्दी ไทย", &["हिन्दी", "ไทย"], font(1)),
+			("日本語 中文 한국어", &["日本語", "한국어"], font(2)),
+			("Emoji ❤️ 👍🏽 👨‍👩‍👧 ☀ ✓", &["👍"], font(3))
+		];
+		for (text, words, font) in texts {
+			let block = layout(&fonts, "", text);
+			assert_no_notdef(&fonts, &block);
+			/* By character: the runs of right-to-left text are reversed */
+			for c in words.iter().flat_map(|w| w.chars()) {
+				assert_eq!(block.runs.iter().find(|r| r.text.contains(c)).map(|r| r.font), Some(font), "{}", c);
+			}
+		}
+	}
+}
@@ -0,0 +1,159 @@
+/* Inline markup of the text, a small BBCode subset:
+ *
+ *   [b]bold[/b]
+ *   [color=#ff0000]red[/color]       any CSS color
+ *   [size=2]twice as large[/size]    relative to the font size of the zone
+ *   [hl]highlighted[/hl]             [hl=color] for another color than yellow
+ *
+ * Tags can be nested, a closing tag also closes the tags opened after it.
+ * Unknown tags are shown as they are, \[ is a [ never starting a tag. */
+
+use speedy2d::color::Color;
+
+use crate::render::try_parse_color;
+
+/* [size] is limited to a sane range */
+const MIN_SCALE: f32 = 0.25;
+const MAX_SCALE: f32 = 8.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TextStyle {
+	pub bold: bool,
+	/* None for the color of the zone */
+	pub color: Option<Color>,
+	/* Multiplies the font size of the zone */
+	pub scale: f32,
+	pub highlight: Option<Color>
+}
+
+impl Default for TextStyle {
+	fn default() -> TextStyle {
+		TextStyle { bold: false, color: None, scale: 1.0, highlight: None }
+	}
+}
+
+/* A piece of text with the same style */
+#[derive(Clone, PartialEq, Debug)]
+pub struct Span {
+	pub text: String,
+	pub style: TextStyle
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Tag {
+	Bold,
+	Color,
+	Size,
+	Highlight
+}
+
+impl Tag {
+	fn from_name(name: &str) -> Option<Tag> {
+		match name.to_ascii_lowercase().as_str() {
+			"b" => Some(Tag::Bold),
+			"color" => Some(Tag::Color),
+			"size" => Some(Tag::Size),
+			"hl" => Some(Tag::Highlight),
+			_ => None
+		}
+	}
+
+	/* The style inside the tag, None when the value is invalid */
+	fn apply(&self, value: Option<&str>, style: &TextStyle) -> Option<TextStyle> {
+		let mut s = *style;
+		match (self, value) {
+			(Tag::Bold, None) => s.bold = true,
+			(Tag::Color, Some(v)) => s.color = Some(try_parse_color(v.trim())?),
+			(Tag::Size, Some(v)) => {
+				let f = v.trim().parse::<f32>().ok().filter(|f| f.is_finite())?;
+				s.scale = (style.scale * f).clamp(MIN_SCALE, MAX_SCALE);
+			},
+			(Tag::Highlight, None) => s.highlight = Some(Color::YELLOW),
+			(Tag::Highlight, Some(v)) => s.highlight = Some(try_parse_color(v.trim())?),
+			_ => return None
+		}
+		Some(s)
+	}
+}
+
+/* Splits text into the spans of the same style, without the tags */
+pub fn parse_markup(text: &str) -> Vec<Span> {
+	let mut spans: Vec<Span> = Vec::new();
+	/* The open tags, with the style inside each one */
+	let mut open: Vec<(Tag, TextStyle)> = Vec::new();
+	let mut current = String::new();
+	let mut rest = text;
+
+	let style_of = |open: &Vec<(Tag, TextStyle)>| open.last().map(|t| t.1).unwrap_or_default();
+	let flush = |current: &mut String, spans: &mut Vec<Span>, style: TextStyle| {
+		if current.is_empty() {
+			return;
+		}
+		let text = std::mem::take(current);
+		match spans.last_mut() {
+			Some(last) if last.style == style => last.text.push_str(&text),
+			_ => spans.push(Span { text, style })
+		}
+	};
+
+	while let Some(i) = rest.find(['[', '\\']) {
+		current.push_str(&rest[..i]);
+		rest = &rest[i..];
+
+		if let Some(r) = rest.strip_prefix("\\[") {
+			current.push('[');
+			rest = r;
+			continue;
+		}
+		if rest.starts_with('\\') {
+			current.push('\\');
+			rest = &rest[1..];
+			continue;
+		}
+
+		let end = match rest.find(']') {
+			Some(e) => e,
+			None => break
+		};
+		let tag = &rest[1..end];
+		let (closing, tag) = match tag.strip_prefix('/') {
+			Some(t) => (true, t),
+			None => (false, tag)
+		};
+		let (name, value) = match tag.split_once('=') {
+			Some((n, v)) => (n, Some(v)),
+			None => (tag, None)
+		};
+
+		let style = style_of(&open);
+		let handled = match Tag::from_name(name) {
+			Some(t) if closing && value.is_none() => match open.iter().rposition(|o| o.0 == t) {
+				Some(pos) => {
+					flush(&mut current, &mut spans, style);
+					open.truncate(pos);
+					true
+				},
+				None => false
+			},
+			Some(t) if !closing => match t.apply(value, &style) {
+				Some(s) => {
+					flush(&mut current, &mut spans, style);
+					open.push((t, s));
+					true
+				},
+				None => false
+			},
+			_ => false
+		};
+		if handled {
+			rest = &rest[end + 1..];
+		} else {
+			/* Not a tag, shown as it is */
+			current.push('[');
+			rest = &rest[1..];
+		}
+	}
+	current.push_str(rest);
+	flush(&mut current, &mut spans, style_of(&open));
+	spans
+}
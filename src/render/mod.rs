@@ -0,0 +1,224 @@
+/* Layout and scrolling of the text, independent from the output device.
+ * MyWindowHandler draws it in the window through WindowCanvas, the
+ * offscreen renderer draws it into an image through ImageCanvas. */
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use image::{Rgba, RgbaImage};
+use speedy2d::color::Color;
+use speedy2d::dimen::Vector2;
+use speedy2d::shape::Rectangle;
+
+use crate::fonts::FontCatalog;
+use crate::images::ImageCatalog;
+use crate::lconfig::{Config, PlaylistRequest};
+
+pub mod backdrop;
+pub mod layout;
+pub mod markup;
+pub mod shaping;
+pub mod zone;
+pub mod playlist;
+pub mod window_canvas;
+pub mod image_canvas;
+
+use backdrop::{Backdrop, ImageSet};
+use layout::{FontSet, TextRun};
+use zone::ZoneRenderer;
+use playlist::PlaylistPlayer;
+
+/* Drawing primitives needed by the Renderer */
+pub trait Canvas {
+	fn clear(&mut self, color: Color);
+	fn set_clip(&mut self, clip: Option<Rectangle<i32>>);
+	fn fill_rect(&mut self, rect: &Rectangle<i32>, color: Color);
+	/* Draws a run of text, origin is the top left corner of its TextBlock */
+	fn draw_run(&mut self, fonts: &FontSet, run: &TextRun, origin: Vector2<f32>, color: Color);
+	/* Draws picture stretched to rect, opacity 0 is transparent */
+	fn draw_picture(&mut self, picture: &Picture, rect: &Rectangle<f32>, opacity: f32);
+}
+
+/* An image to draw.  The id tells the canvases keeping a copy of it, like
+ * a texture on the GPU, when it is not the same picture any more */
+pub struct Picture {
+	pub id: u64,
+	pub image: RgbaImage
+}
+
+impl Picture {
+	pub fn new(image: RgbaImage) -> Picture {
+		static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+		Picture { id: NEXT_ID.fetch_add(1, Ordering::Relaxed), image }
+	}
+}
+
+pub fn try_parse_color(s: &str) -> Option<Color> {
+	csscolorparser::parse(s).ok()
+		.map(|c| Color::from_rgba(c.r as f32, c.g as f32, c.b as f32, c.a as f32))
+}
+
+pub fn parse_color(s: &str, default: Color) -> Color {
+	try_parse_color(s).unwrap_or(default)
+}
+
+pub(crate) fn color_to_rgba(color: Color) -> Rgba<u8> {
+	let c = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+	Rgba([c(color.r()), c(color.g()), c(color.b()), c(color.a())])
+}
+
+pub struct Renderer {
+	fonts: FontSet,
+	cfg: Config,
+	size: Vector2<u32>,
+	zones: Vec<ZoneRenderer>,
+	playlist: Option<PlaylistPlayer>,
+	images: ImageSet,
+	backdrop: Backdrop,
+	pub pause: bool
+}
+
+impl Renderer {
+	pub fn new(fonts: FontSet, images: ImageCatalog) -> Renderer {
+		Renderer {
+			fonts,
+			cfg: Config::new(),
+			size: Vector2 { x: 10, y: 10 },
+			zones: Vec::new(),
+			playlist: None,
+			images: ImageSet::new(images),
+			backdrop: Backdrop::empty(),
+			pause: false
+		}
+	}
+
+	pub fn config(&self) -> &Config {
+		&self.cfg
+	}
+
+	/* Applies a new configuration.  Zones whose configuration has not
+	 * changed keep scrolling from where they are, the others restart */
+	pub fn set_config(&mut self, cfg: Config) {
+		let zone_cfgs = cfg.effective_zones();
+		if self.fonts.fallback() != cfg.fallback_fonts.as_slice() {
+			/* The text of every zone may be drawn with other fonts now */
+			self.fonts.set_fallback(&cfg.fallback_fonts);
+			self.zones.clear();
+		}
+		for z in &zone_cfgs {
+			self.fonts.load_with_fallback(&z.fontfamily, z.fontweight);
+		}
+
+		/* Zone showing the playlist, if there is one */
+		let playlist_zone = if cfg.playlist.items.is_empty() {
+			None
+		} else {
+			zone_cfgs.iter().position(|z| z.name == cfg.playlist.zone)
+				.or(if cfg.playlist.zone.is_empty() { Some(0) } else { None })
+		};
+		let old_player = self.playlist.take();
+		self.playlist = playlist_zone.map(|idx| match old_player {
+			Some(p) if p.zone_idx == idx && p.base == zone_cfgs[idx] && p.playlist == cfg.playlist => p,
+			_ => PlaylistPlayer::new(cfg.playlist.clone(), idx, zone_cfgs[idx].clone())
+		});
+
+		let mut old_zones: Vec<Option<ZoneRenderer>> = std::mem::take(&mut self.zones)
+			.into_iter().map(Some).collect();
+		for (i, mut zcfg) in zone_cfgs.into_iter().enumerate() {
+			if let Some(p) = self.playlist.as_ref().filter(|p| p.zone_idx == i) {
+				zcfg = p.item_zone_config(p.current());
+			}
+			let z = match old_zones.get_mut(i).and_then(Option::take) {
+				Some(old) if old.zcfg == zcfg => old,
+				_ => ZoneRenderer::new(zcfg, &self.fonts, self.size)
+			};
+			self.zones.push(z);
+		}
+		self.cfg = cfg;
+		self.update_backdrop();
+	}
+
+	/* Preparing the backdrop takes time, text changes do not need it */
+	fn update_backdrop(&mut self) {
+		if !self.backdrop.is_for(&self.cfg, self.size) {
+			self.backdrop = Backdrop::new(&self.cfg, self.size, &mut self.images);
+		}
+	}
+
+	pub fn font_generation(&self) -> u64 {
+		self.fonts.catalog_generation()
+	}
+
+	/* Fonts have been added or removed: the zones look for their font
+	 * again, a font that was missing may be there now */
+	pub fn set_font_catalog(&mut self, catalog: FontCatalog) {
+		self.fonts.set_catalog(catalog);
+		self.zones.clear();
+		self.set_config(self.cfg.clone());
+	}
+
+	pub fn image_generation(&self) -> u64 {
+		self.images.generation()
+	}
+
+	/* Images have been uploaded or deleted, the background may show
+	 * another one now */
+	pub fn set_image_catalog(&mut self, catalog: ImageCatalog) {
+		self.images = ImageSet::new(catalog);
+		self.backdrop = Backdrop::new(&self.cfg, self.size, &mut self.images);
+	}
+
+	/* Index of the playlist item being shown */
+	pub fn playlist_current(&self) -> Option<usize> {
+		self.playlist.as_ref().map(PlaylistPlayer::current)
+	}
+
+	pub fn playlist_request(&mut self, r: PlaylistRequest) {
+		if let Some(p) = &mut self.playlist {
+			p.request(r, &mut self.zones[p.zone_idx], &self.fonts, self.size);
+		}
+	}
+
+	pub fn resize(&mut self, size: Vector2<u32>) {
+		self.size = size;
+		for z in &mut self.zones {
+			z.rebuild_text_block(&self.fonts, size);
+		}
+		if let Some(p) = &mut self.playlist {
+			p.rebuild_text_block(&self.fonts, size);
+		}
+		self.update_backdrop();
+	}
+
+	/* Lays the text out again in the same window, the sizes fitting the
+	 * zones may have changed */
+	pub fn refit(&mut self) {
+		self.resize(self.size);
+	}
+
+	pub fn advance(&mut self, dt: f32) {
+		if self.pause {
+			return;
+		}
+		for z in &mut self.zones {
+			z.advance(dt);
+		}
+		if let Some(p) = &mut self.playlist {
+			p.advance(dt, &mut self.zones[p.zone_idx], &self.fonts, self.size);
+		}
+	}
+
+	pub fn draw<C: Canvas>(&self, canvas: &mut C) {
+		let bgcolor = parse_color(&self.cfg.disp_backgroundcolor, Color::BLACK);
+
+		canvas.set_clip(None);
+		canvas.clear(bgcolor);
+		self.backdrop.draw(canvas);
+
+		for (i, z) in self.zones.iter().enumerate() {
+			match &self.playlist {
+				Some(p) if p.zone_idx == i => p.draw(z, &self.fonts, canvas),
+				_ => z.draw(&self.fonts, canvas, Vector2::ZERO, 1.0)
+			}
+		}
+	}
+}
@@ -0,0 +1,148 @@
+/* Shows the messages of the playlist in turn in one zone, with a
+ * transition between them */
+
+use speedy2d::dimen::Vector2;
+
+use crate::lconfig::{Playlist, PlaylistRequest, PlaylistTransition, TextScrollOrientation, ZoneConfig};
+use crate::render::Canvas;
+use crate::render::layout::FontSet;
+use crate::render::zone::ZoneRenderer;
+
+/* Used when an item sets neither a duration nor a number of passes */
+const DEFAULT_ITEM_DURATION: f32 = 10.0;
+
+struct Transition {
+	kind: PlaylistTransition,
+	outgoing: ZoneRenderer,
+	elapsed: f32
+}
+
+pub struct PlaylistPlayer {
+	pub playlist: Playlist,
+	/* Index of the zone showing the playlist, and its configuration
+	 * without the changes made by the items */
+	pub zone_idx: usize,
+	pub base: ZoneConfig,
+	current: usize,
+	elapsed: f32,
+	transition: Option<Transition>
+}
+
+impl PlaylistPlayer {
+	pub fn new(playlist: Playlist, zone_idx: usize, base: ZoneConfig) -> PlaylistPlayer {
+		PlaylistPlayer {
+			playlist,
+			zone_idx,
+			base,
+			current: 0,
+			elapsed: 0.0,
+			transition: None
+		}
+	}
+
+	pub fn current(&self) -> usize {
+		self.current
+	}
+
+	/* Configuration of the zone while showing item idx */
+	pub fn item_zone_config(&self, idx: usize) -> ZoneConfig {
+		let mut zcfg = self.base.clone();
+		if let Some(item) = self.playlist.items.get(idx) {
+			item.apply_to(&mut zcfg);
+		}
+		zcfg
+	}
+
+	fn item_finished(&self, zone: &ZoneRenderer) -> bool {
+		let item = match self.playlist.items.get(self.current) {
+			Some(i) => i,
+			None => return false
+		};
+		let by_time = item.duration > 0.0 && self.elapsed >= item.duration;
+		let by_passes = item.passes > 0 && zone.passes >= item.passes;
+		let by_default = item.duration <= 0.0 &&
+			(item.passes == 0 || !zone.has_passes()) &&
+			self.elapsed >= DEFAULT_ITEM_DURATION;
+		by_time || by_passes || by_default
+	}
+
+	/* Replaces zone with the one showing item idx */
+	fn show_item(&mut self, idx: usize, zone: &mut ZoneRenderer, fonts: &FontSet, window_size: Vector2<u32>) {
+		if self.playlist.items.is_empty() {
+			return;
+		}
+		let idx = idx % self.playlist.items.len();
+		let new_zone = ZoneRenderer::new(self.item_zone_config(idx), fonts, window_size);
+		let outgoing = std::mem::replace(zone, new_zone);
+		let kind = self.playlist.items[idx].transition;
+
+		self.transition = if kind == PlaylistTransition::Cut || self.playlist.transition_time <= 0.0 {
+			None
+		} else {
+			Some(Transition { kind, outgoing, elapsed: 0.0 })
+		};
+		self.current = idx;
+		self.elapsed = 0.0;
+	}
+
+	pub fn request(&mut self, r: PlaylistRequest, zone: &mut ZoneRenderer, fonts: &FontSet, window_size: Vector2<u32>) {
+		let idx = match r {
+			PlaylistRequest::Next => self.current + 1,
+			PlaylistRequest::Jump(i) => i
+		};
+		self.show_item(idx, zone, fonts, window_size);
+	}
+
+	pub fn advance(&mut self, dt: f32, zone: &mut ZoneRenderer, fonts: &FontSet, window_size: Vector2<u32>) {
+		if let Some(t) = &mut self.transition {
+			t.elapsed += dt;
+			t.outgoing.advance(dt);
+			if t.elapsed >= self.playlist.transition_time {
+				self.transition = None;
+			}
+		}
+
+		self.elapsed += dt;
+		if self.item_finished(zone) {
+			self.show_item(self.current + 1, zone, fonts, window_size);
+		}
+	}
+
+	pub fn rebuild_text_block(&mut self, fonts: &FontSet, window_size: Vector2<u32>) {
+		if let Some(t) = &mut self.transition {
+			t.outgoing.rebuild_text_block(fonts, window_size);
+		}
+	}
+
+	/* Draws the zone showing the playlist, in the middle of a transition
+	 * when there is one */
+	pub fn draw<C: Canvas>(&self, zone: &ZoneRenderer, fonts: &FontSet, canvas: &mut C) {
+		let t = match &self.transition {
+			Some(t) => t,
+			None => {
+				zone.draw(fonts, canvas, Vector2::ZERO, 1.0);
+				return;
+			}
+		};
+		let progress = (t.elapsed / self.playlist.transition_time).clamp(0.0, 1.0);
+
+		match t.kind {
+			PlaylistTransition::Cut => zone.draw(fonts, canvas, Vector2::ZERO, 1.0),
+			PlaylistTransition::Fade => {
+				t.outgoing.draw(fonts, canvas, Vector2::ZERO, 1.0 - progress);
+				zone.draw(fonts, canvas, Vector2::ZERO, progress);
+			},
+			PlaylistTransition::Slide => {
+				/* The new message pushes the old one out, in the direction
+				 * the text scrolls */
+				let size = zone.size();
+				let dir = match zone.zcfg.orientation {
+					TextScrollOrientation::ScrollHorizontal => Vector2::new(size.x, 0.0),
+					TextScrollOrientation::ScrollVertical => Vector2::new(0.0, size.y)
+				};
+				t.outgoing.draw(fonts, canvas, dir * -progress, 1.0);
+				zone.draw(fonts, canvas, dir * (1.0 - progress), 1.0);
+			}
+		}
+	}
+}
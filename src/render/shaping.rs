@@ -0,0 +1,349 @@
+/* What the fonts and rusttype do not do for right-to-left scripts: Arabic
+ * letters are replaced by their contextual forms (initial, medial, final)
+ * from the Presentation Forms blocks, and the characters of each line are
+ * put in display order with the Unicode bidirectional algorithm.
+ *
+ * Shaping is done on the whole text before wrapping, so the line widths
+ * are the ones drawn, reordering is done on each line after wrapping. */
+
+use std::ops::Range;
+
+use unicode_bidi::{BidiClass, Direction, Level, ParagraphBidiInfo};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Joining {
+	/* Joins on both sides */
+	Dual,
+	/* Joins only with the previous letter */
+	Right,
+	/* Tatweel and zero width joiner */
+	Causing,
+	/* Marks, skipped when looking at the letters around */
+	Transparent,
+	None
+}
+
+/* Isolated, final, initial and medial forms of the Arabic letters.  The
+ * letters joining only on the right have no initial and medial forms */
+const ARABIC_FORMS: &[(char, [u32; 4])] = &[
+	('\u{0621}', [0xFE80, 0, 0, 0]),
+	('\u{0622}', [0xFE81, 0xFE82, 0, 0]),
+	('\u{0623}', [0xFE83, 0xFE84, 0, 0]),
+	('\u{0624}', [0xFE85, 0xFE86, 0, 0]),
+	('\u{0625}', [0xFE87, 0xFE88, 0, 0]),
+	('\u{0626}', [0xFE89, 0xFE8A, 0xFE8B, 0xFE8C]),
+	('\u{0627}', [0xFE8D, 0xFE8E, 0, 0]),
+	('\u{0628}', [0xFE8F, 0xFE90, 0xFE91, 0xFE92]),
+	('\u{0629}', [0xFE93, 0xFE94, 0, 0]),
+	('\u{062A}', [0xFE95, 0xFE96, 0xFE97, 0xFE98]),
+	('\u{062B}', [0xFE99, 0xFE9A, 0xFE9B, 0xFE9C]),
+	('\u{062C}', [0xFE9D, 0xFE9E, 0xFE9F, 0xFEA0]),
+	('\u{062D}', [0xFEA1, 0xFEA2, 0xFEA3, 0xFEA4]),
+	('\u{062E}', [0xFEA5, 0xFEA6, 0xFEA7, 0xFEA8]),
+	('\u{062F}', [0xFEA9, 0xFEAA, 0, 0]),
+	('\u{0630}', [0xFEAB, 0xFEAC, 0, 0]),
+	('\u{0631}', [0xFEAD, 0xFEAE, 0, 0]),
+	('\u{0632}', [0xFEAF, 0xFEB0, 0, 0]),
+	('\u{0633}', [0xFEB1, 0xFEB2, 0xFEB3, 0xFEB4]),
+	('\u{0634}', [0xFEB5, 0xFEB6, 0xFEB7, 0xFEB8]),
+	('\u{0635}', [0xFEB9, 0xFEBA, 0xFEBB, 0xFEBC]),
+	('\u{0636}', [0xFEBD, 0xFEBE, 0xFEBF, 0xFEC0]),
+	('\u{0637}', [0xFEC1, 0xFEC2, 0xFEC3, 0xFEC4]),
+	('\u{0638}', [0xFEC5, 0xFEC6, 0xFEC7, 0xFEC8]),
+	('\u{0639}', [0xFEC9, 0xFECA, 0xFECB, 0xFECC]),
+	('\u{063A}', [0xFECD, 0xFECE, 0xFECF, 0xFED0]),
+	('\u{0641}', [0xFED1, 0xFED2, 0xFED3, 0xFED4]),
+	('\u{0642}', [0xFED5, 0xFED6, 0xFED7, 0xFED8]),
+	('\u{0643}', [0xFED9, 0xFEDA, 0xFEDB, 0xFEDC]),
+	('\u{0644}', [0xFEDD, 0xFEDE, 0xFEDF, 0xFEE0]),
+	('\u{0645}', [0xFEE1, 0xFEE2, 0xFEE3, 0xFEE4]),
+	('\u{0646}', [0xFEE5, 0xFEE6, 0xFEE7, 0xFEE8]),
+	('\u{0647}', [0xFEE9, 0xFEEA, 0xFEEB, 0xFEEC]),
+	('\u{0648}', [0xFEED, 0xFEEE, 0, 0]),
+	('\u{0649}', [0xFEEF, 0xFEF0, 0, 0]),
+	('\u{064A}', [0xFEF1, 0xFEF2, 0xFEF3, 0xFEF4]),
+	/* Persian and Urdu */
+	('\u{0671}', [0xFB50, 0xFB51, 0, 0]),
+	('\u{067E}', [0xFB56, 0xFB57, 0xFB58, 0xFB59]),
+	('\u{0686}', [0xFB7A, 0xFB7B, 0xFB7C, 0xFB7D]),
+	('\u{0698}', [0xFB8A, 0xFB8B, 0, 0]),
+	('\u{06A9}', [0xFB8E, 0xFB8F, 0xFB90, 0xFB91]),
+	('\u{06AF}', [0xFB92, 0xFB93, 0xFB94, 0xFB95]),
+	('\u{06CC}', [0xFBFC, 0xFBFD, 0xFBFE, 0xFBFF])
+];
+
+/* Lam followed by an alef is a single ligature, isolated and final */
+const LAM: char = '\u{0644}';
+const LAM_ALEF: &[(char, [u32; 2])] = &[
+	('\u{0622}', [0xFEF5, 0xFEF6]),
+	('\u{0623}', [0xFEF7, 0xFEF8]),
+	('\u{0625}', [0xFEF9, 0xFEFA]),
+	('\u{0627}', [0xFEFB, 0xFEFC])
+];
+
+const ISOLATED: usize = 0;
+const FINAL: usize = 1;
+const INITIAL: usize = 2;
+const MEDIAL: usize = 3;
+
+/* Characters drawn as their mirror image in right-to-left text */
+const MIRRORED: &[(char, char)] = &[
+	('(', ')'), ('[', ']'), ('{', '}'), ('<', '>'), ('«', '»'), ('‹', '›'),
+	('≤', '≥'), ('⁅', '⁆'), ('⟨', '⟩'), ('⟦', '⟧')
+];
+
+fn forms(c: char) -> Option<&'static [u32; 4]> {
+	ARABIC_FORMS.iter().find(|f| f.0 == c).map(|f| &f.1)
+}
+
+/* Nonspacing marks of the Arabic blocks */
+pub fn is_arabic_mark(c: char) -> bool {
+	matches!(c as u32, 0x0610..=0x061A | 0x064B..=0x065F | 0x0670 | 0x06D6..=0x06DC | 0x06DF..=0x06E4 |
+		0x06E7..=0x06E8 | 0x06EA..=0x06ED | 0x08D3..=0x08FF)
+}
+
+fn joining(c: char) -> Joining {
+	if c == '\u{0640}' || c == '\u{200D}' {
+		return Joining::Causing;
+	}
+	if is_arabic_mark(c) {
+		return Joining::Transparent;
+	}
+	match forms(c) {
+		Some(f) if f[INITIAL] != 0 => Joining::Dual,
+		Some(f) if f[FINAL] != 0 => Joining::Right,
+		_ => Joining::None
+	}
+}
+
+fn to_char(code: u32) -> Option<char> {
+	if code == 0 { None } else { char::from_u32(code) }
+}
+
+/* Replaces the Arabic letters of text by their contextual forms.  Each
+ * character comes with a tag (the span it belongs to), kept on the result.
+ * A form is used only when has_glyph() says it can be drawn */
+pub fn shape_arabic<T: Copy>(text: &[(char, T)], has_glyph: impl Fn(char) -> bool) -> Vec<(char, T)> {
+	if !text.iter().any(|(c, _)| forms(*c).is_some()) {
+		return text.to_vec();
+	}
+	let types: Vec<Joining> = text.iter().map(|(c, _)| joining(*c)).collect();
+	/* The joining type of the closest letter before or after i */
+	let around = |i: usize, forward: bool| -> Joining {
+		let mut j = i;
+		loop {
+			if forward {
+				j += 1;
+				if j >= types.len() {
+					return Joining::None;
+				}
+			} else {
+				if j == 0 {
+					return Joining::None;
+				}
+				j -= 1;
+			}
+			if types[j] != Joining::Transparent {
+				return types[j];
+			}
+		}
+	};
+
+	let mut shaped = Vec::with_capacity(text.len());
+	let mut i = 0;
+	while i < text.len() {
+		let (c, tag) = text[i];
+		let t = types[i];
+		let joins_prev = matches!(t, Joining::Dual | Joining::Right | Joining::Causing) &&
+			matches!(around(i, false), Joining::Dual | Joining::Causing);
+
+		if c == LAM {
+			let lig = text.get(i + 1).and_then(|(n, _)| LAM_ALEF.iter().find(|l| l.0 == *n));
+			if let Some(l) = lig.and_then(|l| to_char(l.1[if joins_prev { 1 } else { 0 }])).filter(|l| has_glyph(*l)) {
+				shaped.push((l, tag));
+				i += 2;
+				continue;
+			}
+		}
+
+		let joins_next = matches!(t, Joining::Dual | Joining::Causing) &&
+			matches!(around(i, true), Joining::Dual | Joining::Right | Joining::Causing);
+		let form = match (joins_prev, joins_next) {
+			(true, true) => MEDIAL,
+			(true, false) => FINAL,
+			(false, true) => INITIAL,
+			(false, false) => ISOLATED
+		};
+		let out = forms(c)
+			.and_then(|f| to_char(f[form]))
+			.filter(|f| has_glyph(*f))
+			.unwrap_or(c);
+		shaped.push((out, tag));
+		i += 1;
+	}
+	shaped
+}
+
+/* Direction of the first character with a strong direction, None when
+ * there is none (digits, punctuation...) */
+pub fn text_direction(text: &str) -> Option<bool> {
+	match unicode_bidi::get_base_direction(text) {
+		Direction::Rtl => Some(true),
+		Direction::Ltr => Some(false),
+		Direction::Mixed => None
+	}
+}
+
+pub fn mirror(c: char) -> char {
+	MIRRORED.iter()
+		.find_map(|(a, b)| if *a == c { Some(*b) } else if *b == c { Some(*a) } else { None })
+		.unwrap_or(c)
+}
+
+/* The pieces of a line in the order they are displayed from left to
+ * right, as byte ranges of the line and whether they are right-to-left.
+ * rtl is the direction of the paragraph the line belongs to */
+pub fn visual_runs(line: &str, rtl: bool) -> Vec<(Range<usize>, bool)> {
+	if line.is_empty() {
+		return Vec::new();
+	}
+	/* Only left-to-right characters, the common case */
+	if !rtl && !line.chars().any(|c| matches!(unicode_bidi::bidi_class(c),
+			BidiClass::R | BidiClass::AL | BidiClass::RLE | BidiClass::RLO | BidiClass::RLI)) {
+		return vec![(0..line.len(), false)];
+	}
+	let level = if rtl { Level::rtl() } else { Level::ltr() };
+	let info = ParagraphBidiInfo::new(line, Some(level));
+	let (levels, runs) = info.visual_runs(0..line.len());
+	runs.into_iter()
+		.map(|r| {
+			let rtl = levels[r.start].is_rtl();
+			(r, rtl)
+		})
+		.collect()
+}
+
+/* The characters of a right-to-left piece in display order: reversed,
+ * with the marks kept after the letter they go on */
+pub fn reverse_rtl<T: Copy>(text: &[(char, T)]) -> Vec<(char, T)> {
+	let mut clusters: Vec<&[(char, T)]> = Vec::new();
+	let mut start = 0;
+	for i in 1..=text.len() {
+		if i == text.len() || !is_arabic_mark(text[i].0) && !is_hebrew_mark(text[i].0) {
+			clusters.push(&text[start..i]);
+			start = i;
+		}
+	}
+	clusters.iter().rev()
+		.flat_map(|c| c.iter().map(|(ch, t)| (mirror(*ch), *t)))
+		.collect()
+}
+
+fn is_hebrew_mark(c: char) -> bool {
+	matches!(c as u32, 0x0591..=0x05BD | 0x05BF | 0x05C1..=0x05C2 | 0x05C4..=0x05C5 | 0x05C7)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn shape_with(text: &str, has_glyph: impl Fn(char) -> bool) -> Vec<u32> {
+		let chars: Vec<(char, ())> = text.chars().map(|c| (c, ())).collect();
+		shape_arabic(&chars, has_glyph).into_iter().map(|(c, _)| c as u32).collect()
+	}
+
+	fn shape(text: &str) -> Vec<u32> {
+		shape_with(text, |_| true)
+	}
+
+	fn reversed(text: &str) -> String {
+		let chars: Vec<(char, ())> = text.chars().map(|c| (c, ())).collect();
+		reverse_rtl(&chars).into_iter().map(|(c, _)| c).collect()
+	}
+
+	#[test]
+	fn dual_joining_letters() {
+		/* Beh alone, then two and three of them */
+		assert_eq!(shape("\u{0628}"), vec![0xFE8F]);
+		assert_eq!(shape("\u{0628}\u{0628}"), vec![0xFE91, 0xFE90]);
+		assert_eq!(shape("\u{0628}\u{0628}\u{0628}"), vec![0xFE91, 0xFE92, 0xFE90]);
+		/* A space breaks the joining */
+		assert_eq!(shape("\u{0628} \u{0628}"), vec![0xFE8F, 0x20, 0xFE8F]);
+	}
+
+	#[test]
+	fn right_joining_letters() {
+		/* Beh alef: the alef joins the beh before it */
+		assert_eq!(shape("\u{0628}\u{0627}"), vec![0xFE91, 0xFE8E]);
+		/* Alef beh: nothing joins after an alef */
+		assert_eq!(shape("\u{0627}\u{0628}"), vec![0xFE8D, 0xFE8F]);
+		/* Beh dal beh */
+		assert_eq!(shape("\u{0628}\u{062F}\u{0628}"), vec![0xFE91, 0xFEAA, 0xFE8F]);
+	}
+
+	#[test]
+	fn marks_are_transparent() {
+		/* Beh fatha beh, beh shadda kasra beh */
+		assert_eq!(shape("\u{0628}\u{064E}\u{0628}"), vec![0xFE91, 0x064E, 0xFE90]);
+		assert_eq!(shape("\u{0628}\u{0651}\u{0650}\u{0628}"), vec![0xFE91, 0x0651, 0x0650, 0xFE90]);
+	}
+
+	#[test]
+	fn tatweel_and_joiner_cause_joining() {
+		assert_eq!(shape("\u{0640}\u{0628}"), vec![0x0640, 0xFE90]);
+		assert_eq!(shape("\u{0628}\u{0640}"), vec![0xFE91, 0x0640]);
+		assert_eq!(shape("\u{0628}\u{200D}"), vec![0xFE91, 0x200D]);
+	}
+
+	#[test]
+	fn lam_alef_ligatures() {
+		assert_eq!(shape("\u{0644}\u{0627}"), vec![0xFEFB]);
+		assert_eq!(shape("\u{0644}\u{0623}"), vec![0xFEF7]);
+		/* After a letter joining it, the final form */
+		assert_eq!(shape("\u{0628}\u{0644}\u{0627}"), vec![0xFE91, 0xFEFC]);
+		/* After an alef, which does not join, the isolated one */
+		assert_eq!(shape("\u{0627}\u{0644}\u{0627}"), vec![0xFE8D, 0xFEFB]);
+		/* Lam and another letter are not a ligature */
+		assert_eq!(shape("\u{0644}\u{0628}"), vec![0xFEDF, 0xFE90]);
+	}
+
+	#[test]
+	fn forms_the_font_lacks_are_not_used() {
+		assert_eq!(shape_with("\u{0628}\u{0628}", |_| false), vec![0x0628, 0x0628]);
+		/* Without the ligature, lam and alef are joined one by one */
+		assert_eq!(shape_with("\u{0644}\u{0627}", |c| c != '\u{FEFB}'), vec![0xFEDF, 0xFE8E]);
+		assert_eq!(shape("Hello"), "Hello".chars().map(|c| c as u32).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn directions() {
+		assert_eq!(text_direction("Hello"), Some(false));
+		assert_eq!(text_direction("שלום"), Some(true));
+		assert_eq!(text_direction("123 سلام"), Some(true));
+		assert_eq!(text_direction("123 ..."), None);
+	}
+
+	#[test]
+	fn runs_of_mixed_lines() {
+		assert_eq!(visual_runs("", false), vec![]);
+		assert_eq!(visual_runs("Hello world", false), vec![(0..11, false)]);
+		/* "abc " then the Hebrew, 2 bytes per letter */
+		assert_eq!(visual_runs("abc אבג", false), vec![(0..4, false), (4..10, true)]);
+		/* In a right-to-left paragraph the English comes first from the left */
+		assert_eq!(visual_runs("אבג abc", true), vec![(7..10, false), (0..7, true)]);
+		assert_eq!(visual_runs("abc", true), vec![(0..3, false)]);
+	}
+
+	#[test]
+	fn right_to_left_pieces_are_reversed() {
+		assert_eq!(reversed("אבג"), "גבא");
+		/* Brackets are mirrored, so they still open towards the text */
+		assert_eq!(reversed("(אב)"), "(בא)");
+		assert_eq!(reversed("«א»"), "«א»");
+		assert_eq!(mirror('['), ']');
+		assert_eq!(mirror('a'), 'a');
+		/* The marks stay after their letter */
+		assert_eq!(reversed("ב\u{05BC}ג"), "גב\u{05BC}");
+		assert_eq!(reversed("\u{0628}\u{064E}\u{062A}"), "\u{062A}\u{0628}\u{064E}");
+	}
+}
@@ -0,0 +1,80 @@
+use std::collections::{HashMap, HashSet};
+
+use speedy2d::Graphics2D;
+use speedy2d::color::Color;
+use speedy2d::dimen::Vector2;
+use speedy2d::image::{ImageDataType, ImageHandle, ImageSmoothingMode};
+use speedy2d::shape::Rectangle;
+
+use crate::render::{Canvas, Picture};
+use crate::render::layout::{FontSet, TextRun};
+
+/* The pictures uploaded to the GPU, kept while they are drawn in every
+ * frame */
+#[derive(Default)]
+pub struct PictureCache {
+	handles: HashMap<u64, ImageHandle>,
+	drawn: HashSet<u64>
+}
+
+/* Draws on the window with speedy2d */
+pub struct WindowCanvas<'a> {
+	graphics: &'a mut Graphics2D,
+	pictures: &'a mut PictureCache
+}
+
+impl<'a> WindowCanvas<'a> {
+	pub fn new(graphics: &'a mut Graphics2D, pictures: &'a mut PictureCache) -> WindowCanvas<'a> {
+		WindowCanvas { graphics, pictures }
+	}
+}
+
+/* At the end of the frame, the pictures not drawn are released */
+impl Drop for WindowCanvas<'_> {
+	fn drop(&mut self) {
+		let drawn = std::mem::take(&mut self.pictures.drawn);
+		self.pictures.handles.retain(|id, _| drawn.contains(id));
+	}
+}
+
+impl Canvas for WindowCanvas<'_> {
+	fn clear(&mut self, color: Color) {
+		self.graphics.clear_screen(color);
+	}
+
+	fn set_clip(&mut self, clip: Option<Rectangle<i32>>) {
+		self.graphics.set_clip(clip);
+	}
+
+	fn fill_rect(&mut self, rect: &Rectangle<i32>, color: Color) {
+		let r = Rectangle::from_tuples(
+			(rect.top_left().x as f32, rect.top_left().y as f32),
+			(rect.bottom_right().x as f32, rect.bottom_right().y as f32)
+		);
+		self.graphics.draw_rectangle(r, color);
+	}
+
+	fn draw_run(&mut self, fonts: &FontSet, run: &TextRun, origin: Vector2<f32>, color: Color) {
+		/* speedy2d positions the text by its top left corner */
+		let pos = (origin.x + run.x, origin.y + run.baseline - run.ascent);
+		self.graphics.draw_text(pos, color, &run.gpu_block(fonts));
+	}
+
+	fn draw_picture(&mut self, picture: &Picture, rect: &Rectangle<f32>, opacity: f32) {
+		self.pictures.drawn.insert(picture.id);
+		if !self.pictures.handles.contains_key(&picture.id) {
+			let image = &picture.image;
+			match self.graphics.create_image_from_raw_pixels(ImageDataType::RGBA, ImageSmoothingMode::Linear, image.dimensions(), image.as_raw()) {
+				Ok(h) => {
+					self.pictures.handles.insert(picture.id, h);
+				},
+				Err(e) => {
+					println!("Unable to create the image: {}", e);
+					return;
+				}
+			}
+		}
+		let tint = Color::from_rgba(1.0, 1.0, 1.0, opacity);
+		self.graphics.draw_rectangle_image_tinted(rect.clone(), tint, &self.pictures.handles[&picture.id]);
+	}
+}
@@ -0,0 +1,499 @@
+use speedy2d::color::Color;
+use speedy2d::dimen::Vector2;
+use speedy2d::shape::Rectangle;
+
+use crate::lconfig::{DisplayMode, TextAlign, TextScrollOrientation, ZoneConfig};
+use crate::render::{Canvas, parse_color};
+use crate::render::layout::{self, FontSet, TextBlock};
+use crate::render::markup::parse_markup;
+
+/* Layout and position of the text of a single zone, moving it as the
+ * display mode of the zone says */
+pub struct ZoneRenderer {
+	pub zcfg: ZoneConfig,
+	/* Zone area on the window, in pixels */
+	rect: Rectangle<i32>,
+	block: Option<TextBlock>,
+	/* Continuous zones: the separator drawn after each copy of the text,
+	 * where it is drawn from the copy, and the distance between copies
+	 * (0 when the zone is not continuous) */
+	separator: Option<TextBlock>,
+	sep_offset: Vector2<f32>,
+	period: f32,
+	y: f32,
+	x: f32,
+	min_x: f32,
+	max_x: f32,
+	min_y: f32,
+	max_y: f32,
+	/* Seconds since the text was typed, the page shown or the blink
+	 * started */
+	elapsed: f32,
+	/* Bounce: 1 moving right or down, -1 left or up */
+	bounce_dir: f32,
+	/* Paging: top and bottom of the pages in the text, the page shown */
+	pages: Vec<(f32, f32)>,
+	page: usize,
+	/* Number of times the text has scrolled across the zone, or has been
+	 * typed, blinked or paged through */
+	pub passes: u32
+}
+
+impl ZoneRenderer {
+	pub fn new(zcfg: ZoneConfig, fonts: &FontSet, window_size: Vector2<u32>) -> ZoneRenderer {
+		let mut z = ZoneRenderer {
+			zcfg,
+			rect: Rectangle::from_tuples((0, 0), (0, 0)),
+			block: None,
+			separator: None,
+			sep_offset: Vector2::ZERO,
+			period: 0.0,
+			y: 0.0,
+			x: 0.0,
+			min_x: 0.0,
+			max_x: 100.0,
+			min_y: 0.0,
+			max_y: 100.0,
+			elapsed: 0.0,
+			bounce_dir: -1.0,
+			pages: Vec::new(),
+			page: 0,
+			passes: 0
+		};
+		z.rebuild_text_block(fonts, window_size);
+		z.reset_position();
+		z
+	}
+
+	/* Area where the text is visible: the zone minus its margins */
+	fn clip_area(&self) -> Rectangle<i32> {
+		let hm = self.zcfg.hmargin as i32;
+		let vm = self.zcfg.vmargin as i32;
+		Rectangle::from_tuples(
+			(self.rect.top_left().x + hm, self.rect.top_left().y + vm),
+			(self.rect.bottom_right().x - hm, self.rect.bottom_right().y - vm)
+		)
+	}
+
+	pub fn rebuild_text_block(&mut self, fonts: &FontSet, window_size: Vector2<u32>) {
+		let r = &self.zcfg.rect;
+		let px = |percent: f32, size: u32| (percent / 100.0 * size as f32).round() as i32;
+		let left = px(r.x, window_size.x);
+		let top = px(r.y, window_size.y);
+		self.rect = Rectangle::from_tuples(
+			(left, top),
+			(left + px(r.w, window_size.x), top + px(r.h, window_size.y))
+		);
+
+		let clip = self.clip_area();
+		let (cw, ch) = (clip.width().max(0) as f32, clip.height().max(0) as f32);
+		let mode = self.zcfg.mode;
+		let wrap_width = match (mode, &self.zcfg.orientation) {
+			(DisplayMode::Scroll | DisplayMode::Bounce, TextScrollOrientation::ScrollHorizontal) => None,
+			_ => Some(cw)
+		};
+		/* Centered text is centered line by line too */
+		let align = match (mode, self.zcfg.textalign) {
+			(DisplayMode::Static | DisplayMode::Blink, TextAlign::Auto) => TextAlign::Center,
+			(_, a) => a
+		};
+
+		let chain = fonts.fallback_chain(&self.zcfg.fontfamily, self.zcfg.fontweight);
+		let spans = parse_markup(&self.zcfg.text);
+		let size = match (mode, &self.zcfg.orientation) {
+			(DisplayMode::Static | DisplayMode::Blink, _) => layout::fit_size(fonts, &chain, &spans, cw, ch, align),
+			(_, TextScrollOrientation::ScrollHorizontal) if self.zcfg.autosize => layout::fit_height(fonts, &chain, &spans, ch, None),
+			(_, TextScrollOrientation::ScrollVertical) if self.zcfg.autosize => layout::fit_height(fonts, &chain, &spans, ch, Some(self.zcfg.lines)),
+			_ => self.zcfg.fontsize as f32
+		};
+		let layout = |text: &str| layout::layout_spans(fonts, &chain, &parse_markup(text), size, wrap_width, align);
+		let block = layout::layout_spans(fonts, &chain, &spans, size, wrap_width, align);
+
+		self.separator = None;
+		self.period = 0.0;
+		if self.zcfg.continuous && mode == DisplayMode::Scroll {
+			let sep = Some(&self.zcfg.separator).filter(|s| !s.is_empty()).map(|s| layout(s));
+			let spacing = self.zcfg.spacing as f32;
+			let (period, offset) = match self.zcfg.orientation {
+				TextScrollOrientation::ScrollHorizontal => {
+					/* On the baseline of the first line of the text */
+					let baseline = |b: &TextBlock| b.runs.first().map_or(0.0, |r| r.baseline);
+					let sep_y = sep.as_ref().map_or(0.0, |s| baseline(&block) - baseline(s));
+					(block.width + spacing + sep.as_ref().map_or(0.0, |s| s.width),
+						Vector2::new(block.width + spacing / 2.0, sep_y))
+				},
+				TextScrollOrientation::ScrollVertical => (
+					block.height + spacing + sep.as_ref().map_or(0.0, |s| s.height),
+					Vector2::new(0.0, block.height + spacing / 2.0)
+				)
+			};
+			/* An empty text with no separator and spacing has nothing to repeat */
+			if period >= 1.0 {
+				self.period = period;
+				self.sep_offset = offset;
+				self.separator = sep;
+			}
+		}
+
+		self.max_y = clip.bottom_right().y as f32;
+		self.min_y = clip.top_left().y as f32 - block.height;
+		self.max_x = clip.bottom_right().x as f32;
+		self.min_x = clip.top_left().x as f32 - block.width;
+
+		/* Each page has the lines fitting the zone, at least one */
+		self.pages.clear();
+		for &(top, bottom) in &block.lines {
+			match self.pages.last_mut() {
+				Some(p) if bottom - p.0 <= ch => p.1 = bottom,
+				_ => self.pages.push((top, bottom))
+			}
+		}
+		self.page = self.page.min(self.pages.len().saturating_sub(1));
+
+		self.block = Some(block);
+		if mode != DisplayMode::Scroll {
+			/* The position does not depend on the time spent scrolling */
+			self.place();
+		}
+	}
+
+	/* Position of the text in the modes other than Scroll */
+	fn place(&mut self) {
+		let clip = self.clip_area();
+		let (left, top) = (clip.top_left().x as f32, clip.top_left().y as f32);
+		let (cw, ch) = (clip.width() as f32, clip.height() as f32);
+		let (bw, bh) = self.block.as_ref().map_or((0.0, 0.0), |b| (b.width, b.height));
+		match self.zcfg.mode {
+			DisplayMode::Scroll => (),
+			DisplayMode::Static | DisplayMode::Blink | DisplayMode::Typewriter => {
+				/* The lines are aligned in the width of the zone already */
+				self.x = left;
+				self.y = top + ((ch - bh) / 2.0).max(0.0);
+			},
+			DisplayMode::Paging => {
+				self.x = left;
+				self.y = top - self.pages.get(self.page).map_or(0.0, |p| p.0);
+			},
+			DisplayMode::Bounce => {
+				let (lo, hi) = self.bounce_range();
+				match self.zcfg.orientation {
+					TextScrollOrientation::ScrollHorizontal => {
+						self.x = self.x.clamp(lo, hi);
+						self.y = top + ((ch - bh) / 2.0).max(0.0);
+					},
+					TextScrollOrientation::ScrollVertical => {
+						self.x = left + ((cw - bw) / 2.0).min(0.0);
+						self.y = self.y.clamp(lo, hi);
+					}
+				}
+			}
+		}
+	}
+
+	/* The positions a bouncing text goes between: from one margin to the
+	 * other, or showing both ends of a text longer than the zone */
+	fn bounce_range(&self) -> (f32, f32) {
+		let clip = self.clip_area();
+		let (bw, bh) = self.block.as_ref().map_or((0.0, 0.0), |b| (b.width, b.height));
+		let (start, end, len) = match self.zcfg.orientation {
+			TextScrollOrientation::ScrollHorizontal => (clip.top_left().x as f32, clip.bottom_right().x as f32, bw),
+			TextScrollOrientation::ScrollVertical => (clip.top_left().y as f32, clip.bottom_right().y as f32, bh)
+		};
+		let other_end = end - len;
+		(start.min(other_end), start.max(other_end))
+	}
+
+	/* Number of passes the zone can count, the static text never ends */
+	pub fn has_passes(&self) -> bool {
+		match self.zcfg.mode {
+			DisplayMode::Scroll | DisplayMode::Bounce => self.zcfg.scrollspeed != 0,
+			DisplayMode::Static => false,
+			DisplayMode::Typewriter | DisplayMode::Blink => true,
+			DisplayMode::Paging => self.pages.len() > 1
+		}
+	}
+
+	/* Right-to-left text scrolling horizontally goes the other way when
+	 * the zone follows the direction of the text */
+	fn reversed(&self) -> bool {
+		self.zcfg.followdirection &&
+			self.zcfg.orientation == TextScrollOrientation::ScrollHorizontal &&
+			self.block.as_ref().is_some_and(|b| b.rtl)
+	}
+
+	/* Pixels per second */
+	fn speed(&self) -> f32 {
+		if self.reversed() {
+			-(self.zcfg.scrollspeed as f32)
+		} else {
+			self.zcfg.scrollspeed as f32
+		}
+	}
+
+	pub fn reset_position(&mut self) {
+		let clip = self.clip_area();
+		self.elapsed = 0.0;
+		self.page = 0;
+		if self.zcfg.mode != DisplayMode::Scroll {
+			/* Bouncing starts from the margin the text scrolls from */
+			let (lo, hi) = self.bounce_range();
+			self.bounce_dir = if self.zcfg.scrollspeed < 0 { 1.0 } else { -1.0 };
+			let start = if self.bounce_dir < 0.0 { hi } else { lo };
+			self.x = start;
+			self.y = start;
+			self.place();
+			return;
+		}
+		if self.period > 0.0 {
+			/* A continuous ticker is full from the start */
+			self.x = clip.top_left().x as f32;
+			self.y = clip.top_left().y as f32;
+			return;
+		}
+		if self.zcfg.scrollspeed == 0 {
+			/* Not scrolling, like a static header: keep the text in view */
+			self.x = clip.top_left().x as f32;
+			self.y = clip.top_left().y as f32;
+			if self.reversed() {
+				self.x = self.max_x - self.block.as_ref().map_or(0.0, |b| b.width);
+			}
+			return;
+		}
+		/* The text enters from the bottom or right side when the speed is
+		 * positive, from the top or left side when it is negative */
+		let speed = self.speed();
+		self.y = match self.zcfg.orientation {
+			TextScrollOrientation::ScrollVertical => {
+				if speed < 0.0 {
+					self.min_y
+				} else {
+					self.max_y
+				}
+			},
+			TextScrollOrientation::ScrollHorizontal => clip.top_left().y as f32
+		};
+		self.x = match self.zcfg.orientation {
+			TextScrollOrientation::ScrollHorizontal => {
+				if speed < 0.0 {
+					self.min_x
+				} else {
+					self.max_x
+				}
+			},
+			TextScrollOrientation::ScrollVertical => clip.top_left().x as f32
+		};
+	}
+
+	/* Moves the text by the distance covered in dt seconds.  Scroll speed is
+	 * in pixels per second, x and y keep the fractional part, so slow speeds
+	 * still move at a constant rate */
+	pub fn advance(&mut self, dt: f32) {
+		let Some(block) = &self.block else {
+			return;
+		};
+		match self.zcfg.mode {
+			DisplayMode::Scroll => (),
+			DisplayMode::Static => return,
+			DisplayMode::Bounce => {
+				let (lo, hi) = self.bounce_range();
+				let pos = match self.zcfg.orientation {
+					TextScrollOrientation::ScrollHorizontal => &mut self.x,
+					TextScrollOrientation::ScrollVertical => &mut self.y
+				};
+				if hi - lo < 1.0 {
+					return;
+				}
+				*pos += self.bounce_dir * (self.zcfg.scrollspeed as f32).abs() * dt;
+				/* Back from the end by what went past it */
+				loop {
+					if *pos < lo {
+						*pos = 2.0 * lo - *pos;
+					} else if *pos > hi {
+						*pos = 2.0 * hi - *pos;
+					} else {
+						break;
+					}
+					self.bounce_dir = -self.bounce_dir;
+					self.passes += 1;
+				}
+				return;
+			},
+			DisplayMode::Typewriter => {
+				self.elapsed += dt;
+				let typed = block.char_count() as f32 / self.zcfg.typespeed;
+				if self.elapsed >= typed + self.zcfg.dwell {
+					self.elapsed = 0.0;
+					self.passes += 1;
+				}
+				return;
+			},
+			DisplayMode::Blink => {
+				self.elapsed += dt;
+				let cycle = self.zcfg.blinkinterval * 2.0;
+				while self.elapsed >= cycle {
+					self.elapsed -= cycle;
+					self.passes += 1;
+				}
+				return;
+			},
+			DisplayMode::Paging => {
+				self.elapsed += dt;
+				if self.pages.len() > 1 && self.elapsed >= self.zcfg.dwell {
+					self.elapsed = 0.0;
+					self.page = (self.page + 1) % self.pages.len();
+					if self.page == 0 {
+						self.passes += 1;
+					}
+					self.place();
+				}
+				return;
+			}
+		}
+		let delta = self.speed() * dt;
+
+		if self.period > 0.0 {
+			let clip = self.clip_area();
+			let (pos, start) = match self.zcfg.orientation {
+				TextScrollOrientation::ScrollVertical => (&mut self.y, clip.top_left().y as f32),
+				TextScrollOrientation::ScrollHorizontal => (&mut self.x, clip.top_left().x as f32)
+			};
+			*pos -= delta;
+			/* The first copy stays less than a period before the start of
+			 * the zone, each period is a pass */
+			while *pos <= start - self.period {
+				*pos += self.period;
+				self.passes += 1;
+			}
+			while *pos > start {
+				*pos -= self.period;
+				self.passes += 1;
+			}
+			return;
+		}
+
+		match self.zcfg.orientation {
+			TextScrollOrientation::ScrollVertical => {
+				self.y -= delta;
+
+				if self.y > self.max_y {
+					self.y = self.min_y;
+					self.passes += 1;
+				}
+				if self.y < self.min_y {
+					self.y = self.max_y;
+					self.passes += 1;
+				}
+			},
+			TextScrollOrientation::ScrollHorizontal => {
+				self.x -= delta;
+
+				if self.x > self.max_x {
+					self.x = self.min_x;
+					self.passes += 1;
+				}
+				if self.x < self.min_x {
+					self.x = self.max_x;
+					self.passes += 1;
+				}
+			}
+		}
+	}
+
+	pub fn size(&self) -> Vector2<f32> {
+		Vector2::new(self.rect.width() as f32, self.rect.height() as f32)
+	}
+
+	/* Where the copies of the text are drawn: a single one, or as many as
+	 * needed to fill the zone when it is continuous */
+	fn copies(&self, clip: &Rectangle<i32>) -> Vec<Vector2<f32>> {
+		let first = Vector2::new(self.x, self.y);
+		if self.period <= 0.0 {
+			return vec![first];
+		}
+		let (step, pos, end) = match self.zcfg.orientation {
+			TextScrollOrientation::ScrollHorizontal => (Vector2::new(self.period, 0.0), self.x, clip.bottom_right().x as f32),
+			TextScrollOrientation::ScrollVertical => (Vector2::new(0.0, self.period), self.y, clip.bottom_right().y as f32)
+		};
+		/* One more on each side, for the playlist transitions moving the zone */
+		let n = ((end - pos) / self.period).ceil().max(0.0) as usize + 1;
+		(0..=n).map(|k| first + step * (k as f32 - 1.0)).collect()
+	}
+
+	/* Draws the first chars characters of block */
+	fn draw_block<C: Canvas>(fonts: &FontSet, canvas: &mut C, block: &TextBlock, origin: Vector2<f32>, fgcolor: Color, fade: impl Fn(Color) -> Color, chars: usize) {
+		let mut left_chars = chars;
+		for run in &block.runs {
+			if left_chars == 0 {
+				break;
+			}
+			let n = run.text.chars().count();
+			let typed;
+			let run = if n > left_chars {
+				typed = run.prefix(fonts, left_chars);
+				&typed
+			} else {
+				run
+			};
+			left_chars -= n.min(left_chars);
+
+			if let Some(hl) = run.style.highlight {
+				let top = origin.y + run.baseline - run.ascent;
+				let left = origin.x + run.x;
+				canvas.fill_rect(&Rectangle::from_tuples(
+					(left.round() as i32, top.round() as i32),
+					((left + run.width).round() as i32, (top + run.ascent - run.descent).round() as i32)
+				), fade(hl));
+			}
+			let color = run.style.color.map(&fade).unwrap_or(fgcolor);
+			canvas.draw_run(fonts, run, origin, color);
+			if run.style.bold {
+				let shift = Vector2::new(layout::bold_shift(run.size), 0.0);
+				canvas.draw_run(fonts, run, origin + shift, color);
+			}
+		}
+	}
+
+	/* Draws the zone, moved by offset and with the given opacity (used by
+	 * playlist transitions) */
+	pub fn draw<C: Canvas>(&self, fonts: &FontSet, canvas: &mut C, offset: Vector2<f32>, opacity: f32) {
+		let fade = |c: Color| Color::from_rgba(c.r(), c.g(), c.b(), c.a() * opacity);
+		let clip = self.clip_area();
+
+		if !self.zcfg.backgroundcolor.is_empty() {
+			let bgcolor = parse_color(&self.zcfg.backgroundcolor, Color::TRANSPARENT);
+			let tl = *self.rect.top_left();
+			let br = *self.rect.bottom_right();
+			let off = Vector2::new(offset.x.round() as i32, offset.y.round() as i32);
+			canvas.set_clip(Some(self.rect.clone()));
+			canvas.fill_rect(&Rectangle::new(tl + off, br + off), fade(bgcolor));
+		}
+
+		if let Some(b) = &self.block {
+			let fgcolor = fade(parse_color(&self.zcfg.textcolor, Color::WHITE));
+			let mut clip = clip;
+			let mut chars = usize::MAX;
+			match self.zcfg.mode {
+				DisplayMode::Blink if self.elapsed >= self.zcfg.blinkinterval => return,
+				DisplayMode::Typewriter => chars = (self.elapsed * self.zcfg.typespeed) as usize,
+				DisplayMode::Paging => {
+					/* Not the beginning of the next page */
+					if let Some((top, bottom)) = self.pages.get(self.page) {
+						let tl = *clip.top_left();
+						let bottom = (tl.y + (bottom - top).ceil() as i32).min(clip.bottom_right().y);
+						clip = Rectangle::from_tuples((tl.x, tl.y), (clip.bottom_right().x, bottom));
+					}
+				},
+				_ => ()
+			}
+
+			canvas.set_clip(Some(clip.clone()));
+			for origin in self.copies(&clip) {
+				let origin = origin + offset;
+				Self::draw_block(fonts, canvas, b, origin, fgcolor, fade, chars);
+				if let Some(sep) = &self.separator {
+					Self::draw_block(fonts, canvas, sep, origin + self.sep_offset, fgcolor, fade, usize::MAX);
+				}
+			}
+		}
+	}
+}
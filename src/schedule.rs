@@ -0,0 +1,188 @@
+/* Time based scheduling of the content: the first rule of Config::schedule
+ * matching the local date and time replaces the display settings. */
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use serde_json::{Map, Value};
+
+use crate::lconfig::{Config, ScheduleRule};
+
+const TIME_FORMAT: &str = "%H:%M";
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+fn parse_time(s: &str) -> Result<Option<NaiveTime>, String> {
+	if s.is_empty() {
+		return Ok(None);
+	}
+	NaiveTime::parse_from_str(s, TIME_FORMAT).map(Some)
+		.map_err(|_| format!("Invalid time {}, expected HH:MM", s))
+}
+
+fn parse_date(s: &str) -> Result<Option<NaiveDate>, String> {
+	if s.is_empty() {
+		return Ok(None);
+	}
+	NaiveDate::parse_from_str(s, DATE_FORMAT).map(Some)
+		.map_err(|_| format!("Invalid date {}, expected YYYY-MM-DD", s))
+}
+
+fn parse_weekdays(days: &[String]) -> Result<Vec<Weekday>, String> {
+	days.iter()
+		.map(|d| d.parse::<Weekday>().map_err(|_| format!("Invalid weekday {}", d)))
+		.collect()
+}
+
+/* Checks that all the conditions of the rule can be parsed */
+pub fn validate_rule(rule: &ScheduleRule) -> Result<(), String> {
+	parse_weekdays(&rule.weekdays)?;
+	parse_time(&rule.time_from)?;
+	parse_time(&rule.time_to)?;
+	parse_date(&rule.date_from)?;
+	parse_date(&rule.date_to)?;
+	Ok(())
+}
+
+pub fn rule_matches(rule: &ScheduleRule, now: NaiveDateTime) -> bool {
+	let (weekdays, time_from, time_to, date_from, date_to) = match (
+		parse_weekdays(&rule.weekdays),
+		parse_time(&rule.time_from),
+		parse_time(&rule.time_to),
+		parse_date(&rule.date_from),
+		parse_date(&rule.date_to)
+	) {
+		(Ok(w), Ok(tf), Ok(tt), Ok(df), Ok(dt)) => (w, tf, tt, df, dt),
+		/* Rules with invalid conditions never match */
+		_ => return false
+	};
+
+	let time = now.time();
+	/* After midnight, a window crossing it belongs to the day it started */
+	let day = match (time_from, time_to) {
+		(Some(from), Some(to)) if to < from => {
+			if time >= from {
+				now.date()
+			} else if time < to {
+				match now.date().pred_opt() {
+					Some(d) => d,
+					None => return false
+				}
+			} else {
+				return false;
+			}
+		},
+		(from, to) => {
+			if from.is_some_and(|f| time < f) || to.is_some_and(|t| time >= t) {
+				return false;
+			}
+			now.date()
+		}
+	};
+
+	if !weekdays.is_empty() && !weekdays.contains(&day.weekday()) {
+		return false;
+	}
+	!(date_from.is_some_and(|d| day < d) || date_to.is_some_and(|d| day > d))
+}
+
+/* Index of the rule deciding what is shown at the given time */
+pub fn active_rule(cfg: &Config, now: NaiveDateTime) -> Option<usize> {
+	cfg.schedule.iter().position(|r| rule_matches(r, now))
+}
+
+/* A human readable description of the conditions of a rule */
+pub fn describe_rule(rule: &ScheduleRule) -> String {
+	let mut cond = Vec::new();
+	if !rule.weekdays.is_empty() {
+		cond.push(format!("on {}", rule.weekdays.join(", ")));
+	}
+	if !rule.time_from.is_empty() || !rule.time_to.is_empty() {
+		let from = if rule.time_from.is_empty() { "00:00" } else { &rule.time_from };
+		let to = if rule.time_to.is_empty() { "24:00" } else { &rule.time_to };
+		cond.push(format!("from {} to {}", from, to));
+	}
+	if !rule.date_from.is_empty() {
+		cond.push(format!("since {}", rule.date_from));
+	}
+	if !rule.date_to.is_empty() {
+		cond.push(format!("until {}", rule.date_to));
+	}
+	if cond.is_empty() {
+		String::from("always")
+	} else {
+		cond.join(" ")
+	}
+}
+
+/* Keys of the configuration a rule is allowed to change */
+fn is_display_key(key: &str) -> bool {
+	key.starts_with("disp_") || key == "zones" || key == "overlays" || key == "playlist"
+}
+
+/* The configuration to be shown while rule is active */
+pub fn scheduled_config(cfg: &Config, rule: Option<usize>) -> Config {
+	let mut scfg = cfg.clone();
+	if let Some(content) = rule.and_then(|r| cfg.schedule.get(r)).and_then(|r| r.content.as_object()) {
+		let display: Map<String, Value> = content.iter()
+			.filter(|(k, _)| is_display_key(k))
+			.map(|(k, v)| (k.clone(), v.clone()))
+			.collect();
+		scfg.set_partial_cfg(&Value::Object(display));
+		/* Still the same configuration, only shown differently */
+		scfg.version = cfg.version;
+	}
+	scfg
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rule(weekdays: &[&str], time_from: &str, time_to: &str, date_from: &str, date_to: &str) -> ScheduleRule {
+		let mut r = ScheduleRule::new();
+		r.weekdays = weekdays.iter().map(|d| d.to_string()).collect();
+		r.time_from = time_from.to_string();
+		r.time_to = time_to.to_string();
+		r.date_from = date_from.to_string();
+		r.date_to = date_to.to_string();
+		r
+	}
+
+	fn at(s: &str) -> NaiveDateTime {
+		NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+	}
+
+	#[test]
+	fn weekdays_and_times() {
+		/* 2024-05-10 is a Friday */
+		let r = rule(&["Fri"], "09:00", "17:00", "", "");
+		assert!(rule_matches(&r, at("2024-05-10 09:00")));
+		assert!(rule_matches(&r, at("2024-05-10 16:59")));
+		assert!(!rule_matches(&r, at("2024-05-10 17:00")));
+		assert!(!rule_matches(&r, at("2024-05-10 08:59")));
+		assert!(!rule_matches(&r, at("2024-05-11 10:00")));
+	}
+
+	#[test]
+	fn date_ranges_include_both_ends() {
+		let r = rule(&[], "", "", "2024-05-10", "2024-05-12");
+		assert!(!rule_matches(&r, at("2024-05-09 23:59")));
+		assert!(rule_matches(&r, at("2024-05-10 00:00")));
+		assert!(rule_matches(&r, at("2024-05-12 23:59")));
+		assert!(!rule_matches(&r, at("2024-05-13 00:00")));
+	}
+
+	#[test]
+	fn overnight_windows_belong_to_the_day_they_start() {
+		let r = rule(&["Fri"], "22:00", "02:00", "", "");
+		assert!(rule_matches(&r, at("2024-05-10 23:00")));
+		assert!(rule_matches(&r, at("2024-05-11 01:00")));
+		assert!(!rule_matches(&r, at("2024-05-11 02:00")));
+		assert!(!rule_matches(&r, at("2024-05-11 23:00")));
+		assert!(!rule_matches(&r, at("2024-05-10 01:00")));
+
+		let r = rule(&[], "22:00", "02:00", "2024-05-10", "2024-05-10");
+		assert!(!rule_matches(&r, at("2024-05-10 01:00")));
+		assert!(rule_matches(&r, at("2024-05-10 22:00")));
+		assert!(rule_matches(&r, at("2024-05-11 01:59")));
+		assert!(!rule_matches(&r, at("2024-05-11 22:00")));
+	}
+}
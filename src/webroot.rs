@@ -0,0 +1,57 @@
+/* Where the files of the web interface come from: a directory, or with the
+ * embedded-ui feature the copy of runtime-data compiled into the executable */
+
+use std::fmt;
+use std::path::PathBuf;
+
+#[cfg(feature = "embedded-ui")]
+static RUNTIME_DATA: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/runtime-data");
+
+#[derive(Clone)]
+pub enum WebRoot {
+	Dir(PathBuf),
+	/* runtime-data/html inside the executable */
+	Embedded
+}
+
+impl WebRoot {
+	/* The embedded files when they are there, otherwise the html directory
+	 * of runtime_data_dir */
+	pub fn default_for(runtime_data_dir: &std::path::Path) -> WebRoot {
+		if cfg!(feature = "embedded-ui") {
+			WebRoot::Embedded
+		} else {
+			WebRoot::Dir(runtime_data_dir.join("html"))
+		}
+	}
+}
+
+impl fmt::Display for WebRoot {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			WebRoot::Dir(d) => write!(f, "{}", d.display()),
+			WebRoot::Embedded => write!(f, "the files embedded in the executable")
+		}
+	}
+}
+
+/* A file of runtime-data, like "icons/main_icon.png" */
+#[cfg(feature = "embedded-ui")]
+pub fn embedded_file(path: &str) -> Option<&'static [u8]> {
+	RUNTIME_DATA.get_file(path).map(|f| f.contents())
+}
+
+#[cfg(not(feature = "embedded-ui"))]
+pub fn embedded_file(_path: &str) -> Option<&'static [u8]> {
+	None
+}
+
+#[cfg(feature = "embedded-ui")]
+pub fn embedded_dir_exists(path: &str) -> bool {
+	path.is_empty() || RUNTIME_DATA.get_dir(path).is_some()
+}
+
+#[cfg(not(feature = "embedded-ui"))]
+pub fn embedded_dir_exists(_path: &str) -> bool {
+	false
+}